@@ -1 +1,6 @@
-pub const BUDDY_ALLOCATOR_ORDER_SIZE: usize = 32;
\ No newline at end of file
+pub const BUDDY_ALLOCATOR_ORDER_SIZE: usize = 32;
+
+pub const SLAB_PAGE_SIZE: usize = 4096;
+pub const SLAB_MIN_OBJECTS: usize = 8; // a slab takes more pages until it holds this many objects
+pub const SLAB_MAX_ORDER: usize = 4; // a slab takes at most 2^4 pages
+pub const MAX_OBJECT_CACHES: usize = 8; // the number of caches a global allocator can route to
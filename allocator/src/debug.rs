@@ -0,0 +1,285 @@
+// debug heap: each block gets a header and red zones, and freed memory is poisoned.
+// a block is laid out as | header | front red zone | data | back red zone |
+// the header keeps the size of the data and links the block into the list of live blocks, so outstanding
+// allocations can be dumped. Its first word is left alone, for free lists link free blocks through it,
+// then the header of a freed block still tells a double free until the block is allocated again.
+
+use core::alloc::Layout;
+use core::cmp::max;
+use core::fmt;
+use core::mem::{align_of, size_of};
+use core::ptr::null_mut;
+
+const RED_ZONE_SIZE: usize = 16;
+const RED_ZONE_BYTE: u8 = 0xbb;
+const ALLOC_POISON: u8 = 0x5a; // new data, reading it before writing is a bug
+const FREE_POISON: u8 = 0x6b; // freed data, reading it is a use after free
+const MAGIC_ALLOCATED: usize = 0xa110_c8ed;
+const MAGIC_FREED: usize = 0xdead_f4ee;
+
+#[repr(C)]
+struct Header {
+    link: usize, // used by free lists
+    magic: usize,
+    size: usize,
+    align: usize,
+    prev: *mut Header,
+    next: *mut Header,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeapCorruption {
+    DoubleFree { addr: usize, size: usize },
+    InvalidFree { addr: usize }, // not a block of the heap, or its header is overwritten
+    SizeMismatch { addr: usize, size: usize, freed_size: usize },
+    RedZone { addr: usize, size: usize, bad_addr: usize }, // bad_addr: the first overwritten byte
+}
+
+impl fmt::Display for HeapCorruption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeapCorruption::DoubleFree { addr, size } => {
+                write!(f, "double free of block {:#x}, size = {}", addr, size)
+            }
+            HeapCorruption::InvalidFree { addr } => {
+                write!(f, "free of {:#x}, which is not an allocated block", addr)
+            }
+            HeapCorruption::SizeMismatch { addr, size, freed_size } => {
+                write!(f, "block {:#x} of size {} is freed with size {}", addr, size, freed_size)
+            }
+            HeapCorruption::RedZone { addr, size, bad_addr } => {
+                write!(f, "red zone of block {:#x}, size = {} is overwritten at {:#x}", addr, size, bad_addr)
+            }
+        }
+    }
+}
+
+pub struct DebugHeap {
+    live: *mut Header,                    // blocks not freed yet
+    corruption: Option<HeapCorruption>,   // the first corruption found
+}
+
+impl DebugHeap {
+    pub const fn new() -> Self {
+        Self {
+            live: null_mut(),
+            corruption: None,
+        }
+    }
+
+    // the layout of the block for data of layout
+    pub fn block_layout(layout: Layout) -> Layout {
+        let align = max(layout.align(), align_of::<Header>());
+        let size = data_offset(layout.align()) + layout.size() + RED_ZONE_SIZE;
+        return Layout::from_size_align(size, align).unwrap();
+    }
+
+    // set up a new block, return its data
+    pub unsafe fn on_alloc(&mut self, block: *mut u8, layout: Layout) -> *mut u8 {
+        let header = block as *mut Header;
+        (*header).magic = MAGIC_ALLOCATED;
+        (*header).size = layout.size();
+        (*header).align = layout.align();
+        (*header).prev = null_mut();
+        (*header).next = self.live;
+        if !self.live.is_null() {
+            (*self.live).prev = header;
+        }
+        self.live = header;
+        let data = block.add(data_offset(layout.align()));
+        let front = block.add(size_of::<Header>());
+        front.write_bytes(RED_ZONE_BYTE, data as usize - front as usize);
+        data.write_bytes(ALLOC_POISON, layout.size());
+        data.add(layout.size()).write_bytes(RED_ZONE_BYTE, RED_ZONE_SIZE);
+        return data;
+    }
+
+    // check the block of data before it is freed, return the block
+    pub unsafe fn on_dealloc(&mut self, data: *mut u8, layout: Layout) -> Result<*mut u8, HeapCorruption> {
+        let block = data.sub(data_offset(layout.align()));
+        let header = block as *mut Header;
+        match (*header).magic {
+            MAGIC_ALLOCATED => {}
+            MAGIC_FREED => {
+                return Err(HeapCorruption::DoubleFree { addr: data as usize, size: (*header).size });
+            }
+            _ => return Err(HeapCorruption::InvalidFree { addr: data as usize }),
+        }
+        if (*header).size != layout.size() || (*header).align != layout.align() {
+            return Err(HeapCorruption::SizeMismatch {
+                addr: data as usize,
+                size: (*header).size,
+                freed_size: layout.size(),
+            });
+        }
+        check_red_zones(header)?;
+        if (*header).prev.is_null() {
+            self.live = (*header).next;
+        } else {
+            (*(*header).prev).next = (*header).next;
+        }
+        if !(*header).next.is_null() {
+            (*(*header).next).prev = (*header).prev;
+        }
+        (*header).magic = MAGIC_FREED;
+        let front = block.add(size_of::<Header>());
+        let end = block.add(Self::block_layout(layout).size());
+        front.write_bytes(FREE_POISON, end as usize - front as usize);
+        return Ok(block);
+    }
+
+    pub fn record(&mut self, corruption: HeapCorruption) {
+        self.corruption.get_or_insert(corruption);
+    }
+
+    // the first corruption found, or a live block whose red zones are overwritten
+    pub fn check(&self) -> Option<HeapCorruption> {
+        if self.corruption.is_some() {
+            return self.corruption;
+        }
+        let mut header = self.live;
+        while !header.is_null() {
+            unsafe {
+                if let Err(corruption) = check_red_zones(header) {
+                    return Some(corruption);
+                }
+                header = (*header).next;
+            }
+        }
+        return None;
+    }
+
+    // f(addr, size) for each live block
+    pub fn for_each_allocation(&self, mut f: impl FnMut(usize, usize)) {
+        let mut header = self.live;
+        while !header.is_null() {
+            unsafe {
+                f(header as usize + data_offset((*header).align), (*header).size);
+                header = (*header).next;
+            }
+        }
+    }
+}
+
+// the offset of data in a block, it leaves room for the header and the front red zone
+fn data_offset(align: usize) -> usize {
+    let offset = size_of::<Header>() + RED_ZONE_SIZE;
+    return (offset + align - 1) & !(align - 1);
+}
+
+unsafe fn check_red_zones(header: *mut Header) -> Result<(), HeapCorruption> {
+    let block = header as *mut u8;
+    let data = block.add(data_offset((*header).align));
+    let size = (*header).size;
+    let front = block.add(size_of::<Header>());
+    let back = data.add(size);
+    let zones = [(front, data as usize - front as usize), (back, RED_ZONE_SIZE)];
+    for (start, len) in zones {
+        if let Some(i) = (0..len).find(|&i| *start.add(i) != RED_ZONE_BYTE) {
+            return Err(HeapCorruption::RedZone {
+                addr: data as usize,
+                size,
+                bad_addr: start as usize + i,
+            });
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap_allocator::tests::{heap, Region};
+    use core::alloc::GlobalAlloc;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::vec::Vec;
+
+    #[test]
+    fn poison() {
+        let region = Region::new(0x1000);
+        let heap = heap(&region, 0x1000);
+        let layout = Layout::from_size_align(40, 8).unwrap();
+        let data = unsafe { heap.alloc(layout) };
+        let bytes = unsafe { core::slice::from_raw_parts(data, 40) };
+        assert!(bytes.iter().all(|&x| x == ALLOC_POISON));
+        unsafe { heap.dealloc(data, layout) };
+        assert!(bytes.iter().all(|&x| x == FREE_POISON));
+        assert_eq!(heap.check(), None);
+    }
+
+    #[test]
+    fn red_zones() {
+        let region = Region::new(0x1000);
+        let heap = heap(&region, 0x1000);
+        let layout = Layout::from_size_align(10, 2).unwrap();
+        for offset in [-1, 10, 10 + RED_ZONE_SIZE as isize - 1] {
+            let data = unsafe { heap.alloc(layout) };
+            unsafe { *data.offset(offset) = 0 };
+            let corruption = HeapCorruption::RedZone {
+                addr: data as usize,
+                size: 10,
+                bad_addr: unsafe { data.offset(offset) } as usize,
+            };
+            // found while the block is live, and when it is freed
+            assert_eq!(heap.debug.exclusive_access().check(), Some(corruption));
+            let result = catch_unwind(AssertUnwindSafe(|| unsafe { heap.dealloc(data, layout) }));
+            assert!(result.is_err());
+            assert_eq!(heap.check(), Some(corruption));
+            heap.debug.exclusive_access().corruption = None;
+            // repair the red zone, then the block can be freed
+            unsafe {
+                *data.offset(offset) = RED_ZONE_BYTE;
+                heap.dealloc(data, layout);
+            }
+        }
+        assert_eq!(heap.check(), None);
+    }
+
+    #[test]
+    fn double_free() {
+        let region = Region::new(0x1000);
+        let heap = heap(&region, 0x1000);
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let data = unsafe { heap.alloc(layout) };
+        unsafe { heap.dealloc(data, layout) };
+        let result = catch_unwind(AssertUnwindSafe(|| unsafe { heap.dealloc(data, layout) }));
+        assert!(result.is_err());
+        assert_eq!(heap.check(), Some(HeapCorruption::DoubleFree { addr: data as usize, size: 24 }));
+    }
+
+    #[test]
+    fn size_mismatch() {
+        let region = Region::new(0x1000);
+        let heap = heap(&region, 0x1000);
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let data = unsafe { heap.alloc(layout) };
+        let wrong = Layout::from_size_align(16, 8).unwrap();
+        let result = catch_unwind(AssertUnwindSafe(|| unsafe { heap.dealloc(data, wrong) }));
+        assert!(result.is_err());
+        assert_eq!(
+            heap.check(),
+            Some(HeapCorruption::SizeMismatch { addr: data as usize, size: 24, freed_size: 16 })
+        );
+    }
+
+    #[test]
+    fn outstanding_allocations() {
+        let region = Region::new(0x4000);
+        let heap = heap(&region, 0x4000);
+        let layouts: Vec<_> = [1, 100, 7, 512].iter().map(|&size| Layout::from_size_align(size, 4).unwrap()).collect();
+        let mut live: Vec<_> = layouts.iter().map(|&layout| (unsafe { heap.alloc(layout) }, layout)).collect();
+        let (data, layout) = live.remove(1);
+        unsafe { heap.dealloc(data, layout) };
+        let mut dumped = Vec::new();
+        heap.for_each_allocation(|addr, size| dumped.push((addr, size)));
+        dumped.sort();
+        let mut expected: Vec<_> = live.iter().map(|(data, layout)| (*data as usize, layout.size())).collect();
+        expected.sort();
+        assert_eq!(dumped, expected);
+        // the diagnostics do not wait for a busy heap
+        let _busy = heap.debug.exclusive_access();
+        let mut cnt = 0;
+        heap.for_each_allocation(|_, _| cnt += 1);
+        assert_eq!(cnt, 0);
+    }
+}
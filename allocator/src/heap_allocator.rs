@@ -1,16 +1,54 @@
 
 
-use crate::config::BUDDY_ALLOCATOR_ORDER_SIZE;
+use crate::config::{BUDDY_ALLOCATOR_ORDER_SIZE, MAX_OBJECT_CACHES};
 use crate::linked_list::LinkedList;
+use crate::slab::{ObjectCache, SlabStats};
+#[cfg(feature = "debug-heap")]
+use crate::debug::{DebugHeap, HeapCorruption};
 use core::alloc::{GlobalAlloc, Layout};
 use core::cmp::*;
-use core::mem::size_of;
 use core::ptr::null_mut;
 use sync::UPSafeCell;
 
+// called when the heap is exhausted, with the smallest and the largest size of the new region.
+// return the start and the size of a new region, which should be aligned to its size.
+pub type GrowHandler = fn(min_size: usize, max_size: usize) -> Option<(usize, usize)>;
 
+// called when a free makes a large free block, return true if the memory of the block is given back
+pub type ShrinkHandler = fn(start: usize, size: usize) -> bool;
 
+// statistics of a heap
+#[derive(Clone, Copy, Debug)]
+pub struct HeapStats {
+    pub user: usize,      // the total size user asked for
+    pub allocated: usize, // the total size allocated
+    pub peak: usize,      // the largest allocated size so far
+    pub total: usize,     // the total size of the memory
+    pub failed: usize,    // the number of allocations that returned null
+    pub free_blocks: [usize; BUDDY_ALLOCATOR_ORDER_SIZE], // the number of free blocks of each order
+}
+
+impl HeapStats {
+    pub fn free(&self) -> usize {
+        self.total - self.allocated
+    }
 
+    // the size of the largest free block, a larger allocation fails even if there is enough free memory
+    pub fn largest_free_block(&self) -> usize {
+        match self.free_blocks.iter().rposition(|&cnt| cnt > 0) {
+            Some(order) => 1 << order,
+            None => 0,
+        }
+    }
+
+    // the percentage of free memory outside the largest free block
+    pub fn fragmentation(&self) -> usize {
+        if self.free() == 0 {
+            return 0;
+        }
+        return 100 - self.largest_free_block() * 100 / self.free();
+    }
+}
 
 struct BuddyAllocator {
     free_list: [LinkedList; BUDDY_ALLOCATOR_ORDER_SIZE], // available memory blocks of each order, record the start address of each block
@@ -19,27 +57,47 @@ struct BuddyAllocator {
 
     allocated: usize, // the total size allocated
 
+    peak: usize, // the largest allocated size so far
+
+    failed: usize, // the number of failed allocations
+
     total: usize, // the total size of the memory
 
     granularity: usize, // the granularity of the memory
+
+    max_total: usize, // the upper bound of total, the heap does not grow beyond it
+
+    grow_handler: Option<GrowHandler>, // where to get more memory when the heap is exhausted
+
+    shrink_handler: Option<(ShrinkHandler, usize)>, // where to give back free blocks of at least the size
 }
 
 
 impl BuddyAllocator{
-    pub const fn new(size: usize, gran: usize) -> Self {
+    // max_size: the upper bound of the heap size
+    pub const fn new(max_size: usize, gran: usize) -> Self {
         let new_allocator = Self {
             free_list: [LinkedList::new(); BUDDY_ALLOCATOR_ORDER_SIZE],
             user: 0,
             allocated: 0,
-            total: size,
+            peak: 0,
+            failed: 0,
+            total: 0,
             granularity: gran,
+            max_total: max_size,
+            grow_handler: None,
+            shrink_handler: None,
         };
         return new_allocator;
     }
 
-    pub unsafe fn init(&mut self, mut start: usize, mut size: usize) {
-        start = (start + self.granularity - 1) & (!self.granularity + 1); //make start up aligned to granularity
-        size = size & (!self.granularity + 1); //make size down aligned to granularity
+    pub unsafe fn init(&mut self, mut start: usize, size: usize) {
+        let end = (start + size) & !(self.granularity - 1); //make end down aligned to granularity
+        start = (start + self.granularity - 1) & !(self.granularity - 1); //make start up aligned to granularity
+        if start >= end {
+            return;
+        }
+        let mut size = end - start;
         self.total += size;
         let mut order: usize;
         while size > 0 {
@@ -71,29 +129,30 @@ impl BuddyAllocator{
     }
 
     
-    unsafe fn merge(&mut self, mut addr: usize, mut order: usize) {
+    // return the merged block
+    unsafe fn merge(&mut self, mut addr: usize, mut order: usize) -> (usize, usize) {
         let mut buddy_addr: usize;
-        while order < BUDDY_ALLOCATOR_ORDER_SIZE {
+        while order + 1 < BUDDY_ALLOCATOR_ORDER_SIZE {
             buddy_addr = addr ^ (1 << order); // address has been aligned to 2^order
-            if self.free_list[order].is_empty() {
-                self.free_list[order].push(addr);
-                return;
-            } else {
-                if self.free_list[order].search_and_delete(buddy_addr){
-                    addr = min(addr, buddy_addr);
-                    order += 1;
-                } else {
-                    self.free_list[order].push(addr);
-                    return;
-                }
+            if !self.free_list[order].search_and_delete(buddy_addr) {
+                break;
             }
+            addr = min(addr, buddy_addr);
+            order += 1;
         }
+        self.free_list[order].push(addr);
+        return (addr, order);
+    }
+
+    //the size of the block for layout, it is a power of 2
+    fn block_size(&self, layout: Layout) -> usize {
+        let align = max(self.granularity, layout.align());
+        return max(layout.size().next_power_of_two(), align);
     }
 
     #[no_mangle]
     unsafe fn alloc_heap(&mut self, layout: Layout) -> *mut u8 {
-        let align = max(self.granularity, layout.align());
-        let size = max(layout.size().next_power_of_two(), align); //size is a power of 2
+        let size = self.block_size(layout);
         let order = size.trailing_zeros() as usize; //the order of the memory block to allocate
         for i in order..BUDDY_ALLOCATOR_ORDER_SIZE {
             if !self.free_list[i].is_empty() {
@@ -103,6 +162,7 @@ impl BuddyAllocator{
                 assert!(!self.free_list[order].is_empty());
                 self.user += layout.size();
                 self.allocated += size;
+                self.peak = max(self.peak, self.allocated);
                 let ans = self.free_list[order].pop() as *mut u8;
                 return ans;
             }
@@ -111,47 +171,533 @@ impl BuddyAllocator{
     }
 
 
-    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
-        let align = max(self.granularity, layout.align());
-        let size = max(layout.size().next_power_of_two(), align); //size is a power of 2
+    // return the free block after merging
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) -> (usize, usize) {
+        let size = self.block_size(layout);
         let order = size.trailing_zeros() as usize; //the order of the memory block to allocate
-        if self.free_list[order].is_empty() {
-            self.free_list[order].push(ptr as usize);
-        } else {
-            self.merge(ptr as usize, order);
+        self.user -= layout.size();
+        self.allocated -= size;
+        return self.merge(ptr as usize, order);
+    }
+
+    fn stats(&self) -> HeapStats {
+        HeapStats {
+            user: self.user,
+            allocated: self.allocated,
+            peak: self.peak,
+            total: self.total,
+            failed: self.failed,
+            free_blocks: core::array::from_fn(|order| self.free_list[order].len()),
         }
     }
 
 }
 
 pub struct GlobalBuddyAllocator {
-    allocator : UPSafeCell<BuddyAllocator>
+    allocator : UPSafeCell<BuddyAllocator>,
+    caches: UPSafeCell<[Option<&'static dyn ObjectCache>; MAX_OBJECT_CACHES]>, // size classes, small allocations go to them
+    #[cfg(feature = "debug-heap")]
+    pub(crate) debug: UPSafeCell<DebugHeap>,
 }
 
 impl GlobalBuddyAllocator {
     pub const unsafe fn new(size: usize, gran: usize) -> Self {
         Self {
             allocator: UPSafeCell::new(BuddyAllocator::new(size, gran)),
+            caches: UPSafeCell::new([None; MAX_OBJECT_CACHES]),
+            #[cfg(feature = "debug-heap")]
+            debug: UPSafeCell::new(DebugHeap::new()),
         }
     }
 
     pub unsafe fn init(&self, start: usize, size: usize) {
         self.allocator.exclusive_access().init(start, size);
     }
+
+    // let the heap grow by asking handler for more memory when it is exhausted
+    pub fn set_grow_handler(&self, handler: GrowHandler) {
+        self.allocator.exclusive_access().grow_handler = Some(handler);
+    }
+
+    // offer free blocks of at least min_size bytes to handler, it may give them back to where the heap grows from
+    pub fn set_shrink_handler(&self, handler: ShrinkHandler, min_size: usize) {
+        self.allocator.exclusive_access().shrink_handler = Some((handler, min_size));
+    }
+
+    // add a size class: allocations that fit in the cache's layout, and in no smaller class, go to it.
+    // It must be called before the heap is used, so that every allocation is freed to where it was allocated.
+    pub fn add_cache(&self, cache: &'static dyn ObjectCache) {
+        assert!(self.allocator.exclusive_access().allocated == 0, "object caches must be added before the heap is used");
+        let mut caches = self.caches.exclusive_access();
+        let slot = caches.iter_mut().find(|slot| slot.is_none()).expect("too many object caches");
+        *slot = Some(cache);
+    }
+
+    pub fn for_each_cache(&self, mut f: impl FnMut(SlabStats)) {
+        for cache in self.caches.exclusive_access().iter().flatten() {
+            f(cache.stats());
+        }
+    }
+
+    // the smallest size class layout fits in
+    fn find_cache(&self, layout: Layout) -> Option<&'static dyn ObjectCache> {
+        let caches = self.caches.exclusive_access();
+        return caches
+            .iter()
+            .flatten()
+            .filter(|cache| cache.layout().size() >= layout.size() && cache.layout().align() >= layout.align())
+            .min_by_key(|cache| cache.layout().size())
+            .copied();
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        self.allocator.exclusive_access().stats()
+    }
+
+    // add a new region large enough for layout, return false if the heap cannot grow
+    unsafe fn grow(&self, layout: Layout) -> bool {
+        let allocator = self.allocator.exclusive_access();
+        let handler = match allocator.grow_handler {
+            Some(handler) => handler,
+            None => return false,
+        };
+        let min_size = allocator.block_size(layout);
+        let max_size = allocator.max_total - allocator.total;
+        if min_size > max_size {
+            return false;
+        }
+        // the handler may print or allocate frames, do not hold the allocator meanwhile
+        drop(allocator);
+        match handler(min_size, max_size) {
+            Some((start, size)) => {
+                self.init(start, size);
+                true
+            }
+            None => false,
+        }
+    }
+
+    unsafe fn alloc_block(&self, layout: Layout) -> *mut u8 {
+        // allocations of a size class always come from its cache, for they are freed to it
+        let ptr = match self.find_cache(layout) {
+            Some(cache) => cache.alloc(),
+            None => {
+                let ptr = self.allocator.exclusive_access().alloc_heap(layout);
+                if ptr.is_null() && self.grow(layout) {
+                    self.allocator.exclusive_access().alloc_heap(layout)
+                } else {
+                    ptr
+                }
+            }
+        };
+        if ptr.is_null() {
+            self.allocator.exclusive_access().failed += 1;
+        }
+        return ptr;
+    }
+
+    unsafe fn dealloc_block(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(cache) = self.find_cache(layout) {
+            return cache.dealloc(ptr);
+        }
+        let (addr, order) = self.allocator.exclusive_access().dealloc(ptr,layout);
+        self.shrink(addr, order);
+    }
+
+    // offer the free block to the shrink handler, remove it from the heap if it is taken
+    unsafe fn shrink(&self, addr: usize, order: usize) {
+        let mut allocator = self.allocator.exclusive_access();
+        let handler = match allocator.shrink_handler {
+            Some((handler, min_size)) if 1 << order >= min_size => handler,
+            _ => return,
+        };
+        allocator.free_list[order].search_and_delete(addr);
+        allocator.total -= 1 << order;
+        drop(allocator);
+        if !handler(addr, 1 << order) {
+            let mut allocator = self.allocator.exclusive_access();
+            allocator.free_list[order].push(addr);
+            allocator.total += 1 << order;
+        }
+    }
+}
+
+// diagnostics of the debug heap. They do not wait for the debug heap, so they can be used while panicking.
+#[cfg(feature = "debug-heap")]
+impl GlobalBuddyAllocator {
+    // the first corruption found, or a live block whose red zones are overwritten
+    pub fn check(&self) -> Option<HeapCorruption> {
+        self.debug.try_exclusive_access()?.check()
+    }
+
+    // f(addr, size) for each outstanding allocation
+    pub fn for_each_allocation(&self, f: impl FnMut(usize, usize)) {
+        if let Some(debug) = self.debug.try_exclusive_access() {
+            debug.for_each_allocation(f);
+        }
+    }
 }
 
 // implement 2 global allocator traits
 unsafe impl GlobalAlloc for GlobalBuddyAllocator {
+    #[cfg(not(feature = "debug-heap"))]
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        return self.allocator.exclusive_access().alloc_heap(layout);
+        return self.alloc_block(layout);
     }
 
+    #[cfg(not(feature = "debug-heap"))]
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        self.allocator.exclusive_access().dealloc(ptr,layout);
+        self.dealloc_block(ptr, layout);
+    }
+
+    #[cfg(feature = "debug-heap")]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let block = self.alloc_block(DebugHeap::block_layout(layout));
+        if block.is_null() {
+            return block;
+        }
+        return self.debug.exclusive_access().on_alloc(block, layout);
+    }
+
+    #[cfg(feature = "debug-heap")]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let result = self.debug.exclusive_access().on_dealloc(ptr, layout);
+        match result {
+            Ok(block) => self.dealloc_block(block, DebugHeap::block_layout(layout)),
+            Err(corruption) => {
+                self.debug.exclusive_access().record(corruption);
+                panic!("heap corruption: {}", corruption);
+            }
+        }
     }
-    
 }
 
 fn prev_power_of_two(num: usize) -> usize {
-    1 << (8 * (size_of::<usize>()) - num.leading_zeros() as usize - 1)
-}
\ No newline at end of file
+    1 << (usize::BITS - num.leading_zeros() - 1)
+}
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::alloc::{alloc, dealloc};
+    use std::vec::Vec;
+
+    const GRAN: usize = 8;
+
+    // a region of host memory aligned to its size
+    pub(crate) struct Region {
+        pub(crate) start: usize,
+        layout: Layout,
+    }
+
+    impl Region {
+        pub(crate) fn new(size: usize) -> Self {
+            let layout = Layout::from_size_align(size, size).unwrap();
+            let start = unsafe { alloc(layout) } as usize;
+            assert_ne!(start, 0);
+            Self { start, layout }
+        }
+    }
+
+    impl Drop for Region {
+        fn drop(&mut self) {
+            unsafe { dealloc(self.start as *mut u8, self.layout) };
+        }
+    }
+
+    pub(crate) fn heap(region: &Region, size: usize) -> GlobalBuddyAllocator {
+        let heap = unsafe { GlobalBuddyAllocator::new(size, GRAN) };
+        unsafe { heap.init(region.start, size) };
+        heap
+    }
+
+    fn free_bytes(stats: &HeapStats) -> usize {
+        stats.free_blocks.iter().enumerate().map(|(order, cnt)| cnt << order).sum()
+    }
+
+    // xorshift, enough to shuffle the operations
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 as usize
+        }
+    }
+
+    #[test]
+    fn alloc_and_free() {
+        let region = Region::new(0x1000);
+        let heap = heap(&region, 0x1000);
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let a = unsafe { heap.alloc_block(layout) };
+        let b = unsafe { heap.alloc_block(layout) };
+        assert!(!a.is_null() && !b.is_null());
+        assert_ne!(a, b);
+        let stats = heap.stats();
+        assert_eq!(stats.user, 48);
+        assert_eq!(stats.allocated, 64);
+        assert_eq!(stats.total, 0x1000);
+        assert_eq!(free_bytes(&stats), stats.free());
+        unsafe {
+            heap.dealloc_block(a, layout);
+            heap.dealloc_block(b, layout);
+        }
+        // buddies are merged back into one block
+        let stats = heap.stats();
+        assert_eq!(stats.allocated, 0);
+        assert_eq!(stats.peak, 64);
+        assert_eq!(stats.free_blocks[12], 1);
+        assert_eq!(stats.largest_free_block(), 0x1000);
+        assert_eq!(stats.fragmentation(), 0);
+    }
+
+    #[test]
+    fn alignment() {
+        let region = Region::new(0x10000);
+        let heap = heap(&region, 0x10000);
+        for align in [1, 8, 64, 0x1000] {
+            let layout = Layout::from_size_align(3, align).unwrap();
+            let ptr = unsafe { heap.alloc_block(layout) };
+            assert_eq!(ptr as usize % align, 0);
+            unsafe { heap.dealloc_block(ptr, layout) };
+        }
+    }
+
+    #[test]
+    fn exhaustion() {
+        let region = Region::new(0x1000);
+        let heap = heap(&region, 0x1000);
+        let layout = Layout::from_size_align(0x800, 8).unwrap();
+        let a = unsafe { heap.alloc_block(layout) };
+        let b = unsafe { heap.alloc_block(layout) };
+        assert!(!a.is_null() && !b.is_null());
+        assert!(unsafe { heap.alloc_block(layout) }.is_null());
+        assert_eq!(heap.stats().failed, 1);
+        unsafe { heap.dealloc_block(a, layout) };
+        // a free half does not fit a larger block
+        let large = Layout::from_size_align(0x1000, 8).unwrap();
+        assert!(unsafe { heap.alloc_block(large) }.is_null());
+        assert_eq!(heap.stats().failed, 2);
+        unsafe { heap.dealloc_block(b, layout) };
+        let c = unsafe { heap.alloc_block(large) };
+        assert_eq!(c as usize, region.start);
+        unsafe { heap.dealloc_block(c, large) };
+    }
+
+    #[test]
+    fn unaligned_region() {
+        let region = Region::new(0x1000);
+        let heap = unsafe { GlobalBuddyAllocator::new(0x1000, GRAN) };
+        unsafe { heap.init(region.start + 3, 0x100 - 5) };
+        // only the aligned part inside the region is used
+        let stats = heap.stats();
+        assert_eq!(stats.total, 0xf0);
+        assert_eq!(free_bytes(&stats), 0xf0);
+        let layout = Layout::from_size_align(8, 8).unwrap();
+        let mut ptrs = Vec::new();
+        loop {
+            let ptr = unsafe { heap.alloc_block(layout) } as usize;
+            if ptr == 0 {
+                break;
+            }
+            assert!(ptr >= region.start + 3 && ptr + 8 <= region.start + 0x100 - 2);
+            ptrs.push(ptr);
+        }
+        assert_eq!(ptrs.len(), 0xf0 / 8);
+    }
+
+    fn grow(min_size: usize, max_size: usize) -> Option<(usize, usize)> {
+        let size = max(min_size, 0x1000);
+        if size > max_size {
+            return None;
+        }
+        // the region is leaked, the heap never gives it back
+        let region = core::mem::ManuallyDrop::new(Region::new(size));
+        Some((region.start, size))
+    }
+
+    #[test]
+    fn growth() {
+        let region = Region::new(0x1000);
+        let heap = unsafe { GlobalBuddyAllocator::new(0x3000, GRAN) };
+        unsafe { heap.init(region.start, 0x1000) };
+        heap.set_grow_handler(grow);
+        let layout = Layout::from_size_align(0x1000, 8).unwrap();
+        let ptrs: Vec<_> = (0..3).map(|_| unsafe { heap.alloc_block(layout) }).collect();
+        assert!(ptrs.iter().all(|ptr| !ptr.is_null()));
+        assert_eq!(heap.stats().total, 0x3000);
+        // the upper bound is reached
+        assert!(unsafe { heap.alloc_block(layout) }.is_null());
+        assert_eq!(heap.stats().failed, 1);
+        for ptr in ptrs {
+            unsafe { heap.dealloc_block(ptr, layout) };
+        }
+    }
+
+    // a fake program break in a region of host memory
+    static BRK: AtomicUsize = AtomicUsize::new(0);
+
+    fn sbrk_grow(min_size: usize, _max_size: usize) -> Option<(usize, usize)> {
+        let size = max(min_size, 0x1000);
+        let start = BRK.load(Ordering::Relaxed);
+        // the region from the old break holds an aligned block of size
+        let end = ((start + size - 1) & !(size - 1)) + size;
+        BRK.store(end, Ordering::Relaxed);
+        Some((start, end - start))
+    }
+
+    fn sbrk_shrink(start: usize, size: usize) -> bool {
+        if start + size != BRK.load(Ordering::Relaxed) {
+            return false;
+        }
+        BRK.store(start, Ordering::Relaxed);
+        true
+    }
+
+    #[test]
+    fn shrink() {
+        let region = Region::new(0x10000);
+        BRK.store(region.start, Ordering::Relaxed);
+        let heap = unsafe { GlobalBuddyAllocator::new(usize::MAX, GRAN) };
+        heap.set_grow_handler(sbrk_grow);
+        heap.set_shrink_handler(sbrk_shrink, 0x1000);
+        let layout = Layout::from_size_align(0x1000, 8).unwrap();
+        let a = unsafe { heap.alloc_block(layout) };
+        let b = unsafe { heap.alloc_block(layout) };
+        assert_eq!(BRK.load(Ordering::Relaxed), region.start + 0x2000);
+        assert_eq!(heap.stats().total, 0x2000);
+        // a is not at the break, so it is kept
+        unsafe { heap.dealloc_block(a, layout) };
+        assert_eq!(BRK.load(Ordering::Relaxed), region.start + 0x2000);
+        assert_eq!(heap.stats().total, 0x2000);
+        // b is merged with a, and both are given back
+        unsafe { heap.dealloc_block(b, layout) };
+        assert_eq!(BRK.load(Ordering::Relaxed), region.start);
+        let stats = heap.stats();
+        assert_eq!(stats.total, 0);
+        assert_eq!(free_bytes(&stats), 0);
+        // small blocks are not offered
+        let small = Layout::from_size_align(0x10, 8).unwrap();
+        let c = unsafe { heap.alloc_block(small) };
+        unsafe { heap.dealloc_block(c, small) };
+        assert_eq!(heap.stats().total, 0);
+        let d = unsafe { heap.alloc_block(small) };
+        let e = unsafe { heap.alloc_block(small) };
+        unsafe { heap.dealloc_block(d, small) };
+        assert_eq!(heap.stats().total, 0x1000);
+        unsafe { heap.dealloc_block(e, small) };
+        assert_eq!(heap.stats().total, 0);
+    }
+
+    #[test]
+    fn caches() {
+        use crate::slab::{tests::SOURCE, SizeClassCache};
+        let small: &'static SizeClassCache =
+            std::boxed::Box::leak(std::boxed::Box::new(SizeClassCache::new("small", Layout::new::<[usize; 3]>(), SOURCE)));
+        let large: &'static SizeClassCache =
+            std::boxed::Box::leak(std::boxed::Box::new(SizeClassCache::new("large", Layout::new::<[usize; 6]>(), SOURCE)));
+        let region = Region::new(0x1000);
+        let heap = unsafe { GlobalBuddyAllocator::new(0x1000, GRAN) };
+        heap.add_cache(large);
+        heap.add_cache(small);
+        unsafe { heap.init(region.start, 0x1000) };
+        // allocations go to the smallest class they fit in, whatever their type is
+        let layout = Layout::new::<[usize; 3]>();
+        let object = unsafe { heap.alloc_block(layout) };
+        let other = Layout::from_size_align(20, 4).unwrap();
+        let ptr = unsafe { heap.alloc_block(other) };
+        assert_eq!(small.stats().objects, 2);
+        let medium = Layout::from_size_align(40, 8).unwrap();
+        let ptr2 = unsafe { heap.alloc_block(medium) };
+        assert_eq!(large.stats().objects, 1);
+        assert_eq!(heap.stats().allocated, 0);
+        // larger or more aligned allocations go to the buddy allocator
+        let big = Layout::from_size_align(64, 8).unwrap();
+        let ptr3 = unsafe { heap.alloc_block(big) };
+        let aligned = Layout::from_size_align(16, 16).unwrap();
+        let ptr4 = unsafe { heap.alloc_block(aligned) };
+        assert_eq!(heap.stats().allocated, 80);
+        unsafe {
+            heap.dealloc_block(object, layout);
+            heap.dealloc_block(ptr, other);
+            heap.dealloc_block(ptr2, medium);
+            heap.dealloc_block(ptr3, big);
+            heap.dealloc_block(ptr4, aligned);
+        }
+        assert_eq!(small.stats().slabs, 0);
+        assert_eq!(large.stats().slabs, 0);
+        assert_eq!(heap.stats().allocated, 0);
+        let mut names = Vec::new();
+        heap.for_each_cache(|stats| names.push(stats.name));
+        assert_eq!(names, ["large", "small"]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn cache_after_use() {
+        use crate::slab::{tests::SOURCE, SizeClassCache};
+        let cache: &'static SizeClassCache =
+            std::boxed::Box::leak(std::boxed::Box::new(SizeClassCache::new("late", Layout::new::<usize>(), SOURCE)));
+        let region = Region::new(0x1000);
+        let heap = heap(&region, 0x1000);
+        let ptr = unsafe { heap.alloc_block(Layout::new::<u8>()) };
+        assert!(!ptr.is_null());
+        heap.add_cache(cache);
+    }
+
+    // random allocations and frees checked against a model of the live blocks
+    fn random_run(seed: u64) {
+        const SIZE: usize = 0x10_0000;
+        let region = Region::new(SIZE);
+        let heap = heap(&region, SIZE);
+        let initial = heap.stats().free_blocks;
+        let mut rng = Rng(seed);
+        let mut live: Vec<(*mut u8, Layout, u8)> = Vec::new();
+        for step in 0..5000 {
+            if live.is_empty() || !rng.next().is_multiple_of(3) {
+                let size = 1 + rng.next() % 0x800;
+                let align = 1 << (rng.next() % 7);
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let ptr = unsafe { heap.alloc_block(layout) };
+                if ptr.is_null() {
+                    continue;
+                }
+                let (start, end) = (ptr as usize, ptr as usize + size);
+                assert_eq!(start % align, 0);
+                assert!(start >= region.start && end <= region.start + SIZE);
+                for &(other, other_layout, _) in live.iter() {
+                    let other = other as usize;
+                    assert!(end <= other || other + other_layout.size() <= start, "overlap at step {}", step);
+                }
+                let tag = step as u8;
+                unsafe { ptr.write_bytes(tag, size) };
+                live.push((ptr, layout, tag));
+            } else {
+                let (ptr, layout, tag) = live.swap_remove(rng.next() % live.len());
+                let data = unsafe { core::slice::from_raw_parts(ptr, layout.size()) };
+                assert!(data.iter().all(|&x| x == tag), "block changed at step {}", step);
+                unsafe { heap.dealloc_block(ptr, layout) };
+            }
+            let stats = heap.stats();
+            assert_eq!(stats.user, live.iter().map(|(_, layout, _)| layout.size()).sum::<usize>());
+            assert_eq!(free_bytes(&stats), stats.free());
+            assert!(stats.peak >= stats.allocated);
+        }
+        for (ptr, layout, _) in live {
+            unsafe { heap.dealloc_block(ptr, layout) };
+        }
+        let stats = heap.stats();
+        assert_eq!(stats.allocated, 0);
+        assert_eq!(stats.free_blocks, initial);
+    }
+
+    #[test]
+    fn random() {
+        for seed in [1, 0x2545_f491, 0xdead_beef] {
+            random_run(seed);
+        }
+    }
+}
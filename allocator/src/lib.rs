@@ -1,9 +1,17 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
+// explicit returns are the style of this project
+#![allow(clippy::needless_return, clippy::missing_safety_doc)]
 extern crate alloc;
 
 mod heap_allocator;
 mod config;
 mod linked_list;
+mod slab;
+#[cfg(feature = "debug-heap")]
+mod debug;
 
-pub use heap_allocator::GlobalBuddyAllocator;
+pub use heap_allocator::{GlobalBuddyAllocator, GrowHandler, HeapStats, ShrinkHandler};
+pub use slab::{arc_layout, ObjectCache, PageSource, SizeClassCache, SlabStats};
+#[cfg(feature = "debug-heap")]
+pub use debug::HeapCorruption;
 
@@ -31,22 +31,80 @@ impl LinkedList {
         self.head.is_null()
     }
 
+    pub fn len(&self) -> usize {
+        let mut len = 0;
+        let mut p = self.head;
+        while !p.is_null() {
+            len += 1;
+            p = unsafe { *p as *mut usize };
+        }
+        return len;
+    }
+
     pub unsafe fn search_and_delete(&mut self, addr: usize) -> bool {
         let mut p = self.head;
-        if p == null_mut() {
+        if p.is_null() {
             return false;
         }
         if p as usize == addr {
             self.head = *p as *mut usize;
             return true;
         }
-        while p != null_mut() && *p as usize != addr {
+        while !p.is_null() && *p != addr {
             p = *p as *mut usize;
         }
-        if p == null_mut() {
+        if p.is_null() {
             return false;
         }
         *p = *(addr as *mut usize);
         return true;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::LinkedList;
+
+    #[test]
+    fn push_and_pop() {
+        let mut nodes = [0usize; 4];
+        let mut list = LinkedList::new();
+        assert!(list.is_empty());
+        unsafe {
+            for node in nodes.iter_mut() {
+                list.push(node as *mut usize as usize);
+            }
+            assert_eq!(list.len(), 4);
+            for node in nodes.iter_mut().rev() {
+                assert_eq!(list.pop(), node as *mut usize as usize);
+            }
+        }
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn pop_empty() {
+        unsafe { LinkedList::new().pop() };
+    }
+
+    #[test]
+    fn search_and_delete() {
+        let mut nodes = [0usize; 4];
+        let addrs: [usize; 4] = core::array::from_fn(|i| &mut nodes[i] as *mut usize as usize);
+        let mut list = LinkedList::new();
+        unsafe {
+            for addr in addrs {
+                list.push(addr);
+            }
+            // the head, the middle and the tail
+            assert!(list.search_and_delete(addrs[3]));
+            assert!(list.search_and_delete(addrs[1]));
+            assert!(list.search_and_delete(addrs[0]));
+            assert!(!list.search_and_delete(addrs[1]));
+            assert_eq!(list.len(), 1);
+            assert_eq!(list.pop(), addrs[2]);
+            assert!(!list.search_and_delete(addrs[2]));
+        }
+    }
+}
@@ -0,0 +1,329 @@
+// slab allocator: a cache keeps objects of one size class in slabs, so they are not rounded up to a power of 2.
+// objects are routed to a cache by their layout, not by their type.
+// a slab is a block of 2^order pages aligned to its size. It starts with a header, followed by the objects,
+// so the slab of an object is found by aligning its address down. Free objects are linked through their first word.
+// a slab is given back to the page source as soon as all of its objects are freed.
+
+use crate::config::{SLAB_MAX_ORDER, SLAB_MIN_OBJECTS, SLAB_PAGE_SIZE};
+use core::alloc::Layout;
+use core::mem::{align_of, size_of};
+use core::ptr::null_mut;
+use sync::UPSafeCell;
+
+// where the pages of slabs come from
+#[derive(Clone, Copy)]
+pub struct PageSource {
+    pub alloc: fn(order: usize) -> Option<usize>, // 2^order pages aligned to their size, return the start address
+    pub dealloc: fn(addr: usize, order: usize),
+}
+
+// usage of a cache
+#[derive(Clone, Copy, Debug)]
+pub struct SlabStats {
+    pub name: &'static str,
+    pub object_size: usize,      // the size of each object, including padding
+    pub objects_per_slab: usize,
+    pub slabs: usize,            // the number of slabs taken from the page source
+    pub objects: usize,          // the number of objects in use
+}
+
+// a cache the global allocator can route allocations to, by the layout of its objects
+pub trait ObjectCache: Sync {
+    fn layout(&self) -> Layout;
+    unsafe fn alloc(&self) -> *mut u8;
+    unsafe fn dealloc(&self, ptr: *mut u8);
+    fn stats(&self) -> SlabStats;
+}
+
+// the header of a slab
+struct Slab {
+    prev: *mut Slab,
+    next: *mut Slab,
+    free: *mut usize, // the first free object
+    in_use: usize,
+}
+
+struct RawSlabCache {
+    name: &'static str,
+    layout: Layout, // the layout of each object, including padding
+    order: usize,   // each slab takes 2^order pages
+    first_offset: usize, // the offset of the first object in a slab
+    objects_per_slab: usize,
+    partial: *mut Slab, // slabs with free objects
+    full: *mut Slab,    // slabs without free objects
+    slabs: usize,
+    objects: usize,
+    source: PageSource,
+}
+
+impl RawSlabCache {
+    const fn new(name: &'static str, layout: Layout, source: PageSource) -> Self {
+        // a free object holds a link
+        let align = max(layout.align(), align_of::<usize>());
+        let size = align_up(max(layout.size(), size_of::<usize>()), align);
+        assert!(align <= SLAB_PAGE_SIZE);
+        let first_offset = align_up(size_of::<Slab>(), align);
+        let mut order = 0;
+        while order < SLAB_MAX_ORDER && ((SLAB_PAGE_SIZE << order) - first_offset) / size < SLAB_MIN_OBJECTS {
+            order += 1;
+        }
+        let objects_per_slab = ((SLAB_PAGE_SIZE << order) - first_offset) / size;
+        assert!(objects_per_slab > 0);
+        Self {
+            name,
+            layout: unsafe { Layout::from_size_align_unchecked(size, align) },
+            order,
+            first_offset,
+            objects_per_slab,
+            partial: null_mut(),
+            full: null_mut(),
+            slabs: 0,
+            objects: 0,
+            source,
+        }
+    }
+
+    fn slab_size(&self) -> usize {
+        SLAB_PAGE_SIZE << self.order
+    }
+
+    // take a new slab from the page source and add it to the partial list
+    unsafe fn grow(&mut self) -> bool {
+        let start = match (self.source.alloc)(self.order) {
+            Some(start) => start,
+            None => return false,
+        };
+        let slab = start as *mut Slab;
+        let mut free: *mut usize = null_mut();
+        for i in (0..self.objects_per_slab).rev() {
+            let object = (start + self.first_offset + i * self.layout.size()) as *mut usize;
+            *object = free as usize;
+            free = object;
+        }
+        slab.write(Slab {
+            prev: null_mut(),
+            next: null_mut(),
+            free,
+            in_use: 0,
+        });
+        push(&mut self.partial, slab);
+        self.slabs += 1;
+        return true;
+    }
+
+    unsafe fn alloc(&mut self) -> *mut u8 {
+        if self.partial.is_null() && !self.grow() {
+            return null_mut();
+        }
+        let slab = self.partial;
+        let object = (*slab).free;
+        (*slab).free = *object as *mut usize;
+        (*slab).in_use += 1;
+        if (*slab).free.is_null() {
+            remove(&mut self.partial, slab);
+            push(&mut self.full, slab);
+        }
+        self.objects += 1;
+        return object as *mut u8;
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8) {
+        let slab = (ptr as usize & !(self.slab_size() - 1)) as *mut Slab;
+        let was_full = (*slab).free.is_null();
+        let object = ptr as *mut usize;
+        *object = (*slab).free as usize;
+        (*slab).free = object;
+        (*slab).in_use -= 1;
+        self.objects -= 1;
+        if was_full {
+            remove(&mut self.full, slab);
+            push(&mut self.partial, slab);
+        }
+        if (*slab).in_use == 0 {
+            // give the empty slab back
+            remove(&mut self.partial, slab);
+            (self.source.dealloc)(slab as usize, self.order);
+            self.slabs -= 1;
+        }
+    }
+
+    fn stats(&self) -> SlabStats {
+        SlabStats {
+            name: self.name,
+            object_size: self.layout.size(),
+            objects_per_slab: self.objects_per_slab,
+            slabs: self.slabs,
+            objects: self.objects,
+        }
+    }
+}
+
+// a cache of a size class. The global allocator routes to it every allocation that fits in its layout and in
+// no smaller class, whatever the type of the object is.
+pub struct SizeClassCache {
+    layout: Layout, // kept out of inner, so that it can be read while the cache is taking a new slab
+    inner: UPSafeCell<RawSlabCache>,
+}
+
+impl SizeClassCache {
+    pub const fn new(name: &'static str, layout: Layout, source: PageSource) -> Self {
+        let inner = RawSlabCache::new(name, layout, source);
+        Self {
+            layout: inner.layout,
+            inner: unsafe { UPSafeCell::new(inner) },
+        }
+    }
+
+    // the layout of each object, including padding
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        self.inner.exclusive_access().stats()
+    }
+}
+
+impl ObjectCache for SizeClassCache {
+    fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    unsafe fn alloc(&self) -> *mut u8 {
+        self.inner.exclusive_access().alloc()
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8) {
+        self.inner.exclusive_access().dealloc(ptr)
+    }
+
+    fn stats(&self) -> SlabStats {
+        SizeClassCache::stats(self)
+    }
+}
+
+// the layout of the allocation of Arc<T>, in which the value follows a strong and a weak counter
+pub const fn arc_layout<T>() -> Layout {
+    let align = max(align_of::<T>(), align_of::<usize>());
+    let size = align_up(align_up(2 * size_of::<usize>(), align_of::<T>()) + size_of::<T>(), align);
+    unsafe { Layout::from_size_align_unchecked(size, align) }
+}
+
+unsafe fn push(head: &mut *mut Slab, slab: *mut Slab) {
+    (*slab).prev = null_mut();
+    (*slab).next = *head;
+    if !head.is_null() {
+        (**head).prev = slab;
+    }
+    *head = slab;
+}
+
+unsafe fn remove(head: &mut *mut Slab, slab: *mut Slab) {
+    if (*slab).prev.is_null() {
+        *head = (*slab).next;
+    } else {
+        (*(*slab).prev).next = (*slab).next;
+    }
+    if !(*slab).next.is_null() {
+        (*(*slab).next).prev = (*slab).prev;
+    }
+}
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b { a } else { b }
+}
+
+const fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::alloc::{alloc, dealloc};
+    use std::vec::Vec;
+
+    fn slab_layout(order: usize) -> Layout {
+        Layout::from_size_align(SLAB_PAGE_SIZE << order, SLAB_PAGE_SIZE << order).unwrap()
+    }
+
+    fn alloc_slab(order: usize) -> Option<usize> {
+        let start = unsafe { alloc(slab_layout(order)) } as usize;
+        (start != 0).then_some(start)
+    }
+
+    fn dealloc_slab(addr: usize, order: usize) {
+        unsafe { dealloc(addr as *mut u8, slab_layout(order)) };
+    }
+
+    pub(crate) const SOURCE: PageSource = PageSource {
+        alloc: alloc_slab,
+        dealloc: dealloc_slab,
+    };
+
+    #[test]
+    fn layout() {
+        // small objects still hold a link
+        let cache = SizeClassCache::new("u8", Layout::new::<u8>(), SOURCE);
+        assert_eq!(cache.layout(), Layout::new::<usize>());
+        // the layout of Arc<T> allocations
+        assert_eq!(arc_layout::<[u64; 3]>(), Layout::new::<(usize, usize, [u64; 3])>());
+        assert_eq!(arc_layout::<u8>(), Layout::new::<(usize, usize, usize)>());
+        // a size class is padded the same way
+        let cache = SizeClassCache::new("class", Layout::from_size_align(20, 4).unwrap(), SOURCE);
+        assert_eq!(cache.layout(), Layout::from_size_align(24, 8).unwrap());
+        // large objects take more pages
+        let cache = SizeClassCache::new("large", Layout::new::<[u8; 1000]>(), SOURCE);
+        assert!(cache.stats().objects_per_slab >= SLAB_MIN_OBJECTS);
+    }
+
+    #[test]
+    fn slabs_come_and_go() {
+        let cache = SizeClassCache::new("test", Layout::new::<[usize; 10]>(), SOURCE);
+        let per_slab = cache.stats().objects_per_slab;
+        let objects: Vec<_> = (0..per_slab * 2 + 1)
+            .map(|i| unsafe {
+                let object = cache.alloc() as *mut [usize; 10];
+                assert!(!object.is_null());
+                object.write([i; 10]);
+                object
+            })
+            .collect();
+        assert_eq!(cache.stats().slabs, 3);
+        assert_eq!(cache.stats().objects, per_slab * 2 + 1);
+        for (i, &object) in objects.iter().enumerate() {
+            assert_eq!(unsafe { *object }, [i; 10]);
+        }
+        // freeing one object of a full slab keeps the slab
+        let mut objects = objects.into_iter();
+        unsafe { cache.dealloc(objects.next().unwrap() as *mut u8) };
+        assert_eq!(cache.stats().slabs, 3);
+        let again = unsafe { cache.alloc() };
+        assert!(!again.is_null());
+        assert_eq!(cache.stats().slabs, 3);
+        unsafe { cache.dealloc(again) };
+        for object in objects {
+            unsafe { cache.dealloc(object as *mut u8) };
+        }
+        assert_eq!(cache.stats().slabs, 0);
+        assert_eq!(cache.stats().objects, 0);
+    }
+
+    fn no_pages(_order: usize) -> Option<usize> {
+        None
+    }
+
+    #[test]
+    fn exhausted_source() {
+        let cache = SizeClassCache::new(
+            "empty",
+            Layout::new::<usize>(),
+            PageSource {
+                alloc: no_pages,
+                dealloc: dealloc_slab,
+            },
+        );
+        assert!(unsafe { cache.alloc() }.is_null());
+        assert_eq!(cache.stats().objects, 0);
+    }
+}
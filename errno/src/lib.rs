@@ -0,0 +1,85 @@
+#![no_std]
+
+// error numbers of syscalls, shared by the kernel and user programs. A syscall returns a value >= 0 if it
+// succeeds, or the negative error number if it fails. The numbers are those of linux.
+
+pub type SyscallResult = Result<usize, Errno>;
+
+#[repr(isize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Errno {
+    EPERM = 1,    // operation not permitted
+    ENOENT = 2,   // no such file
+    ESRCH = 3,    // no such process
+    EIO = 5,      // the device failed
+    E2BIG = 7,    // the arguments of exec are too long
+    ENOEXEC = 8,  // the file is not a valid executable
+    EBADF = 9,    // bad file descriptor
+    ECHILD = 10,  // no such child process
+    EAGAIN = 11,  // not ready yet, try again
+    ENOMEM = 12,  // frames or memory limits run out, or there is no room in the address space
+    EFAULT = 14,  // a pointer can not be accessed by user
+    EEXIST = 17,  // the object exists
+    EINVAL = 22,  // an invalid argument
+    EMFILE = 24,  // too many open files
+    ENOSYS = 38,  // no such syscall
+}
+
+const ALL: [Errno; 15] = [
+    Errno::EPERM,
+    Errno::ENOENT,
+    Errno::ESRCH,
+    Errno::EIO,
+    Errno::E2BIG,
+    Errno::ENOEXEC,
+    Errno::EBADF,
+    Errno::ECHILD,
+    Errno::EAGAIN,
+    Errno::ENOMEM,
+    Errno::EFAULT,
+    Errno::EEXIST,
+    Errno::EINVAL,
+    Errno::EMFILE,
+    Errno::ENOSYS,
+];
+
+impl Errno {
+    pub fn from_code(code: isize) -> Option<Self> {
+        ALL.iter().copied().find(|&errno| errno as isize == code)
+    }
+
+    // the value a syscall returns in a0
+    pub fn encode(result: SyscallResult) -> isize {
+        match result {
+            Ok(value) => value as isize,
+            Err(errno) => -(errno as isize),
+        }
+    }
+
+    // the result of a syscall from a0. A negative value that is not an error number is taken as EINVAL.
+    pub fn decode(ret: isize) -> SyscallResult {
+        if ret >= 0 {
+            Ok(ret as usize)
+        } else {
+            Err(Self::from_code(-ret).unwrap_or(Errno::EINVAL))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_and_decode() {
+        for errno in ALL {
+            assert_eq!(Errno::encode(Err(errno)), -(errno as isize));
+            assert_eq!(Errno::decode(Errno::encode(Err(errno))), Err(errno));
+        }
+        assert_eq!(Errno::encode(Ok(42)), 42);
+        assert_eq!(Errno::decode(0), Ok(0));
+        assert_eq!(Errno::decode(-4095), Err(Errno::EINVAL));
+        // in the order of their numbers, as errno tables are
+        assert!(ALL.windows(2).all(|pair| (pair[0] as isize) < (pair[1] as isize)));
+    }
+}
@@ -3,11 +3,13 @@ use std::io::{Result, Write};
 
 fn main() {
     println!("cargo:rerun-if-changed=../../user/src/");
+    println!("cargo:rerun-if-changed=../../user/data/");
     println!("cargo:rerun-if-changed={}", TARGET_PATH);
     insert_app_data().unwrap();
 }
 
 static TARGET_PATH: &str = "../user/target/riscv64gc-unknown-none-elf/release/";
+static DATA_PATH: &str = "../user/data/";
 
 fn insert_app_data() -> Result<()> {
     let mut f = File::create("./link_app.S").unwrap();
@@ -21,6 +23,18 @@ fn insert_app_data() -> Result<()> {
         })
         .collect();
     apps.sort();
+    // data files follow the apps, they keep their extensions
+    let mut data: Vec<_> = read_dir("../../user/data")
+        .unwrap()
+        .into_iter()
+        .map(|dir_entry| dir_entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    data.sort();
+    let files: Vec<_> = apps
+        .iter()
+        .map(|app| (app.clone(), TARGET_PATH))
+        .chain(data.into_iter().map(|name| (name, DATA_PATH)))
+        .collect();
 
     writeln!(
         f,
@@ -30,13 +44,13 @@ fn insert_app_data() -> Result<()> {
     .global _num_app
 _num_app:
     .quad {}"#,
-        apps.len()
+        files.len()
     )?;
 
-    for i in 0..apps.len() {
+    for i in 0..files.len() {
         writeln!(f, r#"    .quad app_{}_start"#, i)?;
     }
-    writeln!(f, r#"    .quad app_{}_end"#, apps.len() - 1)?;
+    writeln!(f, r#"    .quad app_{}_end"#, files.len() - 1)?;
 
     writeln!(
         f,
@@ -44,11 +58,11 @@ _num_app:
     .global _app_names
 _app_names:"#
     )?;
-    for app in apps.iter() {
-        writeln!(f, r#"    .string "{}""#, app)?;
+    for (name, _) in files.iter() {
+        writeln!(f, r#"    .string "{}""#, name)?;
     }
 
-    for (idx, app) in apps.iter().enumerate() {
+    for (idx, (app, path)) in files.iter().enumerate() {
         println!("app_{}: {}", idx, app);
         writeln!(
             f,
@@ -60,7 +74,7 @@ _app_names:"#
 app_{0}_start:
     .incbin "{2}{1}"
 app_{0}_end:"#,
-            idx, app, TARGET_PATH
+            idx, app, path
         )?;
     }
     Ok(())
@@ -9,25 +9,45 @@ pub const IER: usize = 0x01;
 pub const FCR: usize = 0x02;
 pub const LSR: usize = 0x05; 
 
-pub const KERNEL_HEAP_SIZE: usize = 0x30_0000;
+pub const KERNEL_HEAP_SIZE: usize = 0x30_0000; // the initial kernel heap, in .bss section
+pub const KERNEL_HEAP_MAX_SIZE: usize = 0x200_0000; // the kernel heap grows with frames up to this size
+pub const KERNEL_HEAP_GROW_SIZE: usize = 0x4_0000; // the smallest region the kernel heap grows by
 pub const KERNEL_HEAP_GRANULARITY: usize = size_of::<usize>();
 
 pub const PAGE_SIZE_BITS :usize = 12;
 pub const PAGE_SIZE : usize = 1 << PAGE_SIZE_BITS;
+pub const FRAME_ALLOCATOR_ORDER_SIZE: usize = 16; // the largest block of the frame allocator has 2^15 frames (128 MiB)
 pub const PA_WIDTH_SV39 : usize= 56;
 pub const VA_WIDTH_SV39 : usize = 39;
 
 pub const TRAMPOLINE_START_VA: usize = !0 - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT_START_VA: usize = TRAMPOLINE_START_VA - PAGE_SIZE;
 
-pub const USER_STACK_SIZE: usize = 4096 * 8;
+pub const USER_STACK_SIZE: usize = 4096 * 8; // initial size of user stack, it grows on page faults
+pub const USER_STACK_LIMIT: usize = 0x80_0000; // the maximum size the user stack can grow to
+pub const USER_STACK_GUARD_SIZE: usize = 0x10_0000; // unmapped gap between the user stack and the elf segments
+
+pub const PIE_BASE: usize = 0x1_0000_0000; // position independent executables are loaded above this address
+pub const INTERP_BASE: usize = 0x8_0000_0000; // the interpreter of a dynamically linked program is loaded above this address
+pub const ASLR_LOAD_BITS: usize = 16; // the load base of a position independent executable moves by up to 2^16 pages
+pub const ASLR_STACK_BITS: usize = 12; // the user stack top moves by up to 2^12 pages
+pub const ASLR_HEAP_BITS: usize = 12; // the gap between the user stack and the heap is up to 2^12 pages
+
+pub const MMAP_BASE: usize = 0x20_0000_0000; // anonymous mappings are placed above this address
+pub const USER_SPACE_END: usize = 0x40_0000_0000; // the end of the lower half of sv39 address space
+pub const USER_STRING_MAX: usize = 4096; // the longest string a syscall reads from user space, with its NUL
+pub const USER_ARG_MAX: usize = 4096 * 4; // the most bytes argv and envp of exec take on the new stack, with pointers
 pub const KERNEL_STACK_SIZE: usize = 4096 * 8;
 
 pub const MM_DERICT_MAP: &[(usize, usize)] = &[
     (VIRT_TEST, 0x00_2000), // VIRT_TEST/RTC  in virt machine
-    (UART_BASE, 0x1000)
+    (UART_BASE, 0x1000),
+    (VIRTIO0, 0x1000),
 ];
 
+pub const SWAP_RAM_DISK_ORDER: usize = 14; // with the swap-ram-disk feature, 2^14 frames (64 MiB) of RAM stand in for a missing swap disk
+pub const SWAP_FREE_FRAMES_MIN: usize = 64; // pages are swapped out to keep this many frames for page tables and kernel stacks
+
 pub const INTERRUPT_PERIOD: usize = 5000000;
 
 pub const CLINT: usize = 0x200_0000;
@@ -38,10 +58,13 @@ pub const CLINT_MTIME: usize = CLINT + 0xBFF8;
 
 pub const FD_STDIN: usize = 0;
 pub const FD_STDOUT: usize = 1;
+pub const FD_STDERR: usize = 2;
+pub const FD_TABLE_SIZE: usize = 64; // the most file descriptors a process can have open
 
 
 // QEMU config
 pub const VIRT_TEST: usize = 0x10_0000;
+pub const VIRTIO0: usize = 0x1000_1000; // the first virtio mmio device, used as the swap disk
 pub const CLOCK_FREQ: usize = 12500000;
 pub const MEMORY_END: usize = 0x8800_0000;
 
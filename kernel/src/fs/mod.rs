@@ -0,0 +1,193 @@
+// files of processes. A process reaches its files through the file descriptors in its fd table: stdin, stdout and
+// stderr are the console when it starts. A file is shared by the descriptors dup'ed from one another and by the
+// processes forked from its opener, and it is closed when the last of its descriptors is closed. Pipes, disk files
+// and devices are files as the console is.
+
+mod stdio;
+
+pub use stdio::Console;
+
+use crate::config::{FD_STDERR, FD_STDIN, FD_STDOUT, FD_TABLE_SIZE};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bitflags::bitflags;
+use core::ops::Deref;
+use errno::Errno;
+
+bitflags! {
+    // events of poll, as POLLIN and POLLOUT of linux
+    pub struct PollEvents: u16 {
+        const IN = 1 << 0; // read would not block
+        const OUT = 1 << 2; // write would not block
+    }
+}
+
+// files are shared by tasks, which the kernel runs one at a time
+pub trait File: Send + Sync {
+    // read into buf, return the number of bytes read, which is less than buf only if no more is ready, or 0 at the
+    // end of the file. EBADF if the file is not open for reading.
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno>;
+    // write buf, return the number of bytes written. EBADF if the file is not open for writing.
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno>;
+    // the events of events that are ready now, without blocking
+    fn poll(&self, events: PollEvents) -> PollEvents;
+    // the last descriptor of the file is closed
+    fn close(&self) {}
+}
+
+// a file as it is opened, shared by its descriptors. The file is closed when the last of them is gone, and the
+// clones syscalls hold while they use it.
+pub struct OpenFile {
+    file: Box<dyn File>,
+}
+
+impl OpenFile {
+    pub fn new<F: File + 'static>(file: F) -> Arc<Self> {
+        return Arc::new(Self { file: Box::new(file) });
+    }
+}
+
+impl Deref for OpenFile {
+    type Target = dyn File;
+
+    fn deref(&self) -> &Self::Target {
+        return &*self.file;
+    }
+}
+
+impl Drop for OpenFile {
+    fn drop(&mut self) {
+        self.file.close();
+    }
+}
+
+// the open files of a process, indexed by file descriptor
+#[derive(Clone)]
+pub struct FdTable {
+    files: Vec<Option<Arc<OpenFile>>>,
+}
+
+impl FdTable {
+    // stdin, stdout and stderr on the console
+    pub fn new() -> Self {
+        let console = OpenFile::new(Console);
+        let mut files = Vec::new();
+        for fd in [FD_STDIN, FD_STDOUT, FD_STDERR] {
+            assert_eq!(fd, files.len());
+            files.push(Some(Arc::clone(&console)));
+        }
+        Self { files }
+    }
+
+    // the file of fd, EBADF if fd is not open
+    pub fn get(&self, fd: usize) -> Result<Arc<OpenFile>, Errno> {
+        return self.files.get(fd).cloned().flatten().ok_or(Errno::EBADF);
+    }
+
+    // open file at the lowest free fd, EMFILE if the table is full
+    pub fn alloc(&mut self, file: Arc<OpenFile>) -> Result<usize, Errno> {
+        let fd = match self.files.iter().position(Option::is_none) {
+            Some(fd) => fd,
+            None if self.files.len() < FD_TABLE_SIZE => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+            None => return Err(Errno::EMFILE),
+        };
+        self.files[fd] = Some(file);
+        return Ok(fd);
+    }
+
+    // EBADF if fd is not open
+    pub fn close(&mut self, fd: usize) -> Result<(), Errno> {
+        self.files.get_mut(fd).and_then(Option::take).ok_or(Errno::EBADF)?;
+        return Ok(());
+    }
+
+    // open the file of fd again at the lowest free fd, and return it. EBADF if fd is not open, EMFILE as alloc
+    pub fn dup(&mut self, fd: usize) -> Result<usize, Errno> {
+        let file = self.get(fd)?;
+        return self.alloc(file);
+    }
+
+    // open the file of old_fd at new_fd, which is closed first if it is open. EBADF if old_fd is not open or new_fd
+    // is beyond the table, nothing is done if they are the same.
+    pub fn dup2(&mut self, old_fd: usize, new_fd: usize) -> Result<usize, Errno> {
+        let file = self.get(old_fd)?;
+        if new_fd >= FD_TABLE_SIZE {
+            return Err(Errno::EBADF);
+        }
+        if old_fd != new_fd {
+            if self.files.len() <= new_fd {
+                self.files.resize(new_fd + 1, None);
+            }
+            self.files[new_fd] = Some(file);
+        }
+        return Ok(new_fd);
+    }
+
+    // close all the files, as the process exits
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+}
+
+#[allow(unused)]
+// descriptors share files, within a table and with its clones
+pub fn fd_table_test() {
+    use crate::config::{GREEN, RESET};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    let mut table = FdTable::new();
+    let console = table.get(FD_STDOUT).unwrap();
+    assert_eq!(Arc::strong_count(&console), 4);
+    assert_eq!(table.get(3).err(), Some(Errno::EBADF));
+    assert_eq!(table.dup(FD_STDOUT), Ok(3));
+    assert_eq!(table.close(FD_STDIN), Ok(()));
+    assert_eq!(table.close(FD_STDIN), Err(Errno::EBADF));
+    assert_eq!(table.dup(3), Ok(FD_STDIN)); // the lowest free fd
+    assert_eq!(table.dup2(3, 10), Ok(10));
+    assert_eq!(table.dup2(10, 10), Ok(10));
+    assert_eq!(table.dup2(7, 8), Err(Errno::EBADF));
+    assert_eq!(table.dup2(3, FD_TABLE_SIZE), Err(Errno::EBADF));
+    assert_eq!(Arc::strong_count(&console), 6);
+    // a clone has the same files, its descriptors are its own
+    let mut clone = table.clone();
+    assert_eq!(clone.close(10), Ok(()));
+    assert!(table.get(10).is_ok());
+    assert_eq!(Arc::strong_count(&console), 10);
+    drop(clone);
+    // the table fills up
+    while table.dup(FD_STDOUT).is_ok() {}
+    assert_eq!(table.dup(FD_STDOUT), Err(Errno::EMFILE));
+    assert_eq!(Arc::strong_count(&console), FD_TABLE_SIZE + 1);
+    table.clear();
+    assert_eq!(Arc::strong_count(&console), 1);
+    // a file is closed once, when its last descriptor is closed and the clones taken of it are dropped
+    static CLOSED: AtomicUsize = AtomicUsize::new(0);
+    struct Probe;
+    impl File for Probe {
+        fn read(&self, _buf: &mut [u8]) -> Result<usize, Errno> {
+            Ok(0)
+        }
+        fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+            Ok(buf.len())
+        }
+        fn poll(&self, events: PollEvents) -> PollEvents {
+            events
+        }
+        fn close(&self) {
+            CLOSED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    let fd = table.alloc(OpenFile::new(Probe)).unwrap();
+    assert_eq!(table.dup(fd), Ok(fd + 1));
+    let file = table.get(fd).unwrap();
+    assert_eq!(table.close(fd), Ok(()));
+    assert_eq!(table.close(fd + 1), Ok(()));
+    assert_eq!(file.write(b"probe"), Ok(5));
+    assert_eq!(CLOSED.load(Ordering::Relaxed), 0);
+    drop(file);
+    assert_eq!(CLOSED.load(Ordering::Relaxed), 1);
+    println!("{}fd_table_test passed!{}", GREEN, RESET);
+}
@@ -0,0 +1,35 @@
+// the console as a file, for stdin, stdout and stderr
+
+use super::{File, PollEvents};
+use crate::sbi;
+use crate::uart;
+use errno::Errno;
+
+pub struct Console;
+
+impl File for Console {
+    // a byte at a time, it waits for the byte
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        buf[0] = sbi::console_getchar();
+        Ok(1)
+    }
+
+    // the bytes go to the console as they are
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        for &byte in buf {
+            sbi::console_putchar(byte as usize);
+        }
+        Ok(buf.len())
+    }
+
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        let mut ready = PollEvents::OUT;
+        if uart::console_has_char() {
+            ready |= PollEvents::IN;
+        }
+        ready & events
+    }
+}
@@ -15,5 +15,7 @@ fn panic(info: &PanicInfo) -> ! {
     } else {
         println!("Panicked: {}", info.message().unwrap());
     }
+    #[cfg(feature = "debug-heap")]
+    crate::mem::allocator::print_heap_diagnostics();
     shutdown(true);
 }
\ No newline at end of file
@@ -19,12 +19,15 @@ extern crate lazy_static;
 mod lang_items;
 mod sbi;
 mod uart;
+mod virtio_blk;
 mod config;
 mod mem;
+mod fs;
 mod syscall;
 mod trap;
 mod process;
 mod time;
+mod random;
 
 
 //将汇编代码 entry.asm 转化为字符串并通过 global_asm! 宏嵌入到代码中
@@ -86,6 +89,8 @@ extern "C" fn rust_main() -> ! {
 
     mem::init();
 
+    fs::fd_table_test();
+
     task_manager::init();
 
     loader::init();
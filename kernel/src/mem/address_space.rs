@@ -1,18 +1,32 @@
-use super::frame_allocator::FrameTracker;
+use super::asid::{flush_asid, flush_page, Asid, ASID_ALLOCATOR};
+use super::frame_allocator::{alloc_frame, FrameTracker, OutOfMemory};
 use super::page_table::{PTEFlags, PageTable, PageTableEntry, VirtAddr, PPN, VPN};
+use super::page_cache::CachedFile;
+use super::shm::{SharedMemory, ShmMapping};
+use super::swap::{frames_low, swap_out_any, SwapSlot};
 use crate::config::{
-    GREEN, MEMORY_END, MM_DERICT_MAP, PAGE_SIZE, RESET, TRAMPOLINE_START_VA, TRAP_CONTEXT_START_VA,
-    USER_STACK_SIZE,
+    ASLR_HEAP_BITS, ASLR_LOAD_BITS, ASLR_STACK_BITS, GREEN, INTERP_BASE, MEMORY_END, MM_DERICT_MAP, MMAP_BASE,
+    PAGE_SIZE, PIE_BASE, RESET, TRAMPOLINE_START_VA, TRAP_CONTEXT_START_VA, USER_SPACE_END, USER_STACK_GUARD_SIZE,
+    USER_STACK_LIMIT, USER_STACK_SIZE,
 };
 use crate::mem::page_table::PhyAddr;
+use crate::process::loader::open_app_file;
+use crate::random::random;
 use alloc::collections::BTreeMap;
+use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::bitflags;
 use core::arch::asm;
-use core::cmp::min;
+use core::cmp::{max, min};
+use core::mem::size_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+use errno::Errno;
 use riscv::register::satp;
 use sync::UPSafeCell;
+use xmas_elf::program::{self, ProgramHeader};
+use xmas_elf::{header, ElfFile};
 
 lazy_static! {
     // a memory set instance through lazy_static! managing kernel space
@@ -35,6 +49,122 @@ bitflags! {
 pub enum MapType {
     Identical,
     Framed,
+    Lazy, // only the virtual range is reserved, frames are allocated on the first access (page fault)
+    File, // pages of a file, they are taken from the page cache on the first access (page fault)
+}
+
+// the kind of access that caused a page fault
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AccessType {
+    Load,
+    Store,
+    Execute,
+}
+
+// why a page fault can not be resolved
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PageFaultError {
+    SegmentationFault, // the address is not in any section, or the access is not allowed
+    StackOverflow,     // the address is in the guard gap below the user stack
+    OutOfMemory,       // no frame for the page
+    MemoryLimit,       // the page would exceed the memory limit of the process
+}
+
+impl From<OutOfMemory> for PageFaultError {
+    fn from(_: OutOfMemory) -> Self {
+        PageFaultError::OutOfMemory
+    }
+}
+
+// memory limits of a user address space, in bytes
+#[derive(Copy, Clone, Debug)]
+pub struct MemoryLimits {
+    pub resident: usize, // frames mapped in user sections and frames of the page table
+    pub mapped: usize,   // the size of all user sections, mapped or not
+}
+
+impl MemoryLimits {
+    pub const UNLIMITED: Self = Self {
+        resident: usize::MAX,
+        mapped: usize::MAX,
+    };
+}
+
+// memory used by a user address space, in bytes. A frame shared with other address spaces, after fork
+// or through shared memory, is counted by each of them.
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct MemoryUsage {
+    pub resident: usize,    // frames mapped in user sections
+    pub page_tables: usize, // frames of the page table
+    pub heap: usize,        // the size of the heap
+    pub stack: usize,       // the size of the user stack section
+    pub mapped: usize,      // the size of all user sections
+    pub peak: usize,        // the largest resident + page_tables of the program so far
+    pub swapped: usize,     // pages of user sections on the swap disk
+}
+
+// why an elf file can not be loaded
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ElfError {
+    Malformed,          // not an elf file, or its headers are broken
+    NotRiscv64,         // not a 64-bit little-endian RISC-V executable
+    BadSegment,         // a segment is out of the file or the user half, or overlaps another one
+    WritableExecutable, // a segment is both writable and executable
+    BadEntry,           // the entry point is not in an executable segment
+    BadRelocation,      // a relocation is not supported, or it is out of the segments
+    BadInterpreter,     // the interpreter is not found, or it is not a position independent executable
+    OutOfMemory,        // no frame for the user space
+}
+
+impl From<OutOfMemory> for ElfError {
+    fn from(_: OutOfMemory) -> Self {
+        ElfError::OutOfMemory
+    }
+}
+
+// the errors of syscalls that access user memory or load a program
+impl From<PageFaultError> for Errno {
+    fn from(err: PageFaultError) -> Self {
+        match err {
+            PageFaultError::OutOfMemory | PageFaultError::MemoryLimit => Errno::ENOMEM,
+            PageFaultError::SegmentationFault | PageFaultError::StackOverflow => Errno::EFAULT,
+        }
+    }
+}
+
+impl From<ElfError> for Errno {
+    fn from(err: ElfError) -> Self {
+        match err {
+            ElfError::OutOfMemory => Errno::ENOMEM,
+            _ => Errno::ENOEXEC,
+        }
+    }
+}
+
+// the user stack section ends at `top`, and grows downward on page faults, no further than `top - limit`.
+// [top - USER_STACK_LIMIT - USER_STACK_GUARD_SIZE, top - limit) is the guard gap, which is never mapped.
+#[derive(Copy, Clone)]
+struct UserStack {
+    top: VPN,
+    limit: usize,
+}
+
+impl UserStack {
+    fn lowest_vpn(&self) -> VPN {
+        return VPN(self.top.0 - self.limit / PAGE_SIZE);
+    }
+
+    fn guard_start_vpn(&self) -> VPN {
+        return VPN(self.top.0 - (USER_STACK_LIMIT + USER_STACK_GUARD_SIZE) / PAGE_SIZE);
+    }
+}
+
+// the part of a file a section maps, from page first_page of the file on
+#[derive(Clone)]
+struct FileRange {
+    file: Arc<CachedFile>,
+    first_page: usize,
 }
 
 pub struct Section {
@@ -42,7 +172,11 @@ pub struct Section {
     end: VPN,
     permisson: SectionPermisson,
     map_type: MapType,
-    v2p: BTreeMap<VPN, FrameTracker>,
+    shared: bool, // frames of a shared section stay writable and shared with the child after fork, instead of copy on write
+    v2p: BTreeMap<VPN, Arc<FrameTracker>>, // frames may be shared with other address spaces after fork (copy on write)
+    swapped: BTreeMap<VPN, Arc<SwapSlot>>, // pages on the swap disk, they are unmapped. Slots are shared after fork
+    file: Option<FileRange>, // for MapType::File
+    shm: Option<ShmMapping>, // for the mappings of shared memory objects
 }
 
 impl Section {
@@ -57,20 +191,104 @@ impl Section {
             end: end_va.to_up_vpn(),
             permisson,
             map_type,
+            shared: false,
             v2p: BTreeMap::new(),
+            swapped: BTreeMap::new(),
+            file: None,
+            shm: None,
+        }
+    }
+
+    // split the section at vpn, self keeps [start, vpn), and the returned section takes [vpn, end)
+    fn split_off(&mut self, vpn: VPN) -> Section {
+        let tail = Section {
+            start: vpn,
+            end: self.end,
+            permisson: self.permisson,
+            map_type: self.map_type,
+            shared: self.shared,
+            v2p: self.v2p.split_off(&vpn),
+            swapped: self.swapped.split_off(&vpn),
+            file: self.file.as_ref().map(|range| FileRange {
+                file: Arc::clone(&range.file),
+                first_page: range.first_page + (vpn.0 - self.start.0),
+            }),
+            shm: self.shm.clone(),
+        };
+        self.end = vpn;
+        return tail;
+    }
+
+    // remove the mappings of this section from the page table, and release its frames
+    fn unmap(&mut self, page_table: &mut PageTable) {
+        self.write_back(page_table, self.start, self.end);
+        if self.map_type == MapType::Identical {
+            let mut vpn = self.start;
+            while vpn < self.end {
+                vpn.0 += page_table.unmap(vpn); // skip the rest of a megapage
+            }
+        } else {
+            for vpn in self.v2p.keys() {
+                page_table.unmap(*vpn);
+            }
+            self.v2p.clear(); // frames go back to the allocator if no one else shares them, and are zeroed when allocated again
+            self.swapped.clear();
         }
     }
 
-    pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8]) {
+    // the pte flags of a mapped page in this section. A page shared with another address space
+    // is mapped read-only, and will be copied on write.
+    fn pte_flags(&self, frame: &Arc<FrameTracker>) -> PTEFlags {
+        let flags = PTEFlags::from_bits(self.permisson.bits).unwrap() | PTEFlags::V;
+        if !self.shared && self.permisson.contains(SectionPermisson::W) && Arc::strong_count(frame) > 1 {
+            return flags - PTEFlags::W | PTEFlags::COW;
+        }
+        return flags;
+    }
+
+    // write the pages of a shared file section in [start, end) that have been written since they were mapped, or
+    // last written back, to the file. Their dirty bits are cleared.
+    fn write_back(&self, page_table: &mut PageTable, start: VPN, end: VPN) {
+        let range = match &self.file {
+            Some(range) if self.shared => range,
+            _ => return,
+        };
+        for (vpn, frame) in self.v2p.range(start..end) {
+            let pte = page_table.find_pte(*vpn).unwrap();
+            if pte.flags().contains(PTEFlags::D) {
+                range.file.write_back(range.first_page + (vpn.0 - self.start.0));
+                *pte = PageTableEntry::new(frame.ppn, pte.flags() - PTEFlags::D);
+            }
+        }
+    }
+
+    pub fn contains(&self, vpn: VPN) -> bool {
+        return self.start <= vpn && vpn < self.end;
+    }
+
+    // whether user code is allowed to access this section in the given way
+    fn allows(&self, access: AccessType) -> bool {
+        let needed = match access {
+            AccessType::Load => SectionPermisson::R,
+            AccessType::Store => SectionPermisson::W,
+            AccessType::Execute => SectionPermisson::X,
+        };
+        return self.permisson.contains(needed | SectionPermisson::U);
+    }
+
+    // copy data to the section, starting at offset in its first page
+    pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8], offset: usize) {
         assert_eq!(self.map_type, MapType::Framed);
         let mut start: usize = 0;
+        let mut page_offset = offset;
         let len = data.len();
         for vpn in self.start..self.end {
-            let src = &data[start..min(start + PAGE_SIZE, len)];
-            let dst =
-                &mut page_table.find_and_alloc_pte(vpn).unwrap().ppn().get_page()[..src.len()];
+            let src = &data[start..min(start + PAGE_SIZE - page_offset, len)];
+            let dst = &mut page_table.find_and_alloc_pte(vpn).unwrap().ppn().get_page()
+                [page_offset..page_offset + src.len()];
             dst.copy_from_slice(src);
-            start += PAGE_SIZE;
+            start += src.len();
+            page_offset = 0;
             if start >= len {
                 break;
             }
@@ -78,7 +296,14 @@ impl Section {
     }
 }
 
-fn copy_section(section: &Section, new_address_space: &mut AddressSpace) -> Section {
+// user sections share their frames with the new address space. If the section is writable, both sides are
+// mapped read-only with the COW flag, and the page will be copied when either side writes it.
+// kernel-only sections (trap context) are copied eagerly.
+fn copy_section(
+    section: &Section,
+    parent_page_table: &mut PageTable,
+    new_address_space: &mut AddressSpace,
+) -> Result<Section, OutOfMemory> {
     println!("copy section: vpn range [{:#x}, {:#x})", section.start.0, section.end.0);
     let mut new_section = Section::new(
         section.start.into(),
@@ -86,34 +311,89 @@ fn copy_section(section: &Section, new_address_space: &mut AddressSpace) -> Sect
         section.permisson,
         section.map_type,
     );
+    new_section.shared = section.shared;
+    new_section.file = section.file.clone();
+    new_section.shm = section.shm.clone();
 
+    let flags = PTEFlags::from_bits(section.permisson.bits).unwrap();
+    let user = section.permisson.contains(SectionPermisson::U);
     for (vpn, old_frame) in section.v2p.iter() {
-        // map on page table
-        let new_frame = new_address_space
-            .page_table
-            .map_and_alloc(*vpn, PTEFlags::from_bits(section.permisson.bits).unwrap());
-        let src_data = old_frame.ppn.get_page();
-        let dst_dara = new_frame.ppn.get_page();
-        // copy data
-        dst_dara.copy_from_slice(src_data);
-        new_section.v2p.insert(*vpn, new_frame);
+        if !user {
+            let new_frame = new_address_space.page_table.map_and_alloc(*vpn, flags)?;
+            // copy data
+            new_frame.ppn.get_page().copy_from_slice(old_frame.ppn.get_page());
+            new_section.v2p.insert(*vpn, Arc::new(new_frame));
+            continue;
+        }
+        if section.permisson.contains(SectionPermisson::W) && !section.shared {
+            // write protect the parent's page, it may be writable if it has been copied before
+            let pte = parent_page_table.find_pte(*vpn).unwrap();
+            *pte = PageTableEntry::new(old_frame.ppn, (pte.flags() - PTEFlags::W) | PTEFlags::COW);
+            new_address_space
+                .page_table
+                .map(*vpn, old_frame.ppn, (flags - PTEFlags::W) | PTEFlags::COW)?;
+        } else {
+            new_address_space.page_table.map(*vpn, old_frame.ppn, flags)?;
+        }
+        new_section.v2p.insert(*vpn, Arc::clone(old_frame));
     }
-    return new_section;
+    // a swapped page is read into a frame of its own by whichever side faults on it first
+    new_section.swapped = section.swapped.clone();
+    return Ok(new_section);
 }
 
 pub struct AddressSpace {
     page_table: PageTable,
     sections: Vec<Section>,
+    user_stack: Option<UserStack>, // None for kernel space
+    asid: Option<Asid>, // assigned when the address space is activated for the first time
+    limits: MemoryLimits,
+    peak: usize, // the largest resident memory so far
+    swap_hand: VPN, // the clock hand, pages from here on are looked at first when one is swapped out
 }
 
 impl AddressSpace {
-    pub fn new() -> Self {
-        Self {
-            page_table: PageTable::new(),
+    pub fn new() -> Result<Self, OutOfMemory> {
+        Ok(Self {
+            page_table: PageTable::new()?,
             sections: Vec::new(),
+            user_stack: None,
+            asid: None,
+            limits: MemoryLimits::UNLIMITED,
+            peak: 0,
+            swap_hand: VPN(0),
+        })
+    }
+
+    // take a new ASID if this address space has none, or its ASID has expired. It must be called before
+    // the satp of this address space is written.
+    pub fn update_asid(&mut self) {
+        let mut asid_allocator = ASID_ALLOCATOR.exclusive_access();
+        if self.asid.map_or(true, |asid| !asid_allocator.is_current(&asid)) {
+            self.asid = Some(asid_allocator.alloc());
         }
     }
 
+    // the ASID whose TLB entries may map this address space. None if there is no such entry.
+    fn live_asid(&self) -> Option<Asid> {
+        self.asid
+            .filter(|asid| ASID_ALLOCATOR.exclusive_access().is_current(asid))
+    }
+
+    // flush the TLB after the page table is changed
+    fn flush_tlb(&self) {
+        if let Some(asid) = self.live_asid() {
+            flush_asid(asid);
+        }
+    }
+
+    fn flush_tlb_page(&self, vpn: VPN) {
+        if let Some(asid) = self.live_asid() {
+            flush_page(asid, VirtAddr::from(vpn).0);
+        }
+    }
+
+    // if frames run out, the pages mapped so far are released, and the address space is left unchanged
     pub fn add_section(
         &mut self,
         start: VirtAddr,
@@ -121,33 +401,56 @@ impl AddressSpace {
         permisson: SectionPermisson,
         map_type: MapType,
         data: Option<&[u8]>,
-    ) {
+    ) -> Result<(), OutOfMemory> {
         let mut section = Section::new(start, end, permisson, map_type);
+        let page_cnt = section.end.0 - section.start.0;
+        let resident_cnt = if map_type == MapType::Framed { page_cnt } else { 0 };
+        if permisson.contains(SectionPermisson::U) && !self.charge(resident_cnt, page_cnt) {
+            return Err(OutOfMemory);
+        }
         // println!(
         //     "new section va range: [{:#x}, {:#x})",
         //     start.0, end.0
         // );
-        for vpn in section.start..section.end {
-            match map_type {
-                MapType::Identical => {
-                    let ppn = PPN(vpn.0); // for kernel space, mapping is identical, and there's no need to allocate a new frame.
-                    self.page_table
-                        .map(vpn, ppn, PTEFlags::from_bits(permisson.bits).unwrap());
-                }
-                MapType::Framed => {
-                    let frame = self
+        match map_type {
+            MapType::Identical => {
+                // for kernel space, mapping is identical, and there's no need to allocate a new frame.
+                // megapages are used where the alignment allows
+                self.page_table.map_linear(
+                    section.start,
+                    section.end,
+                    PPN(section.start.0),
+                    PTEFlags::from_bits(permisson.bits).unwrap(),
+                )?;
+            }
+            MapType::Framed => {
+                for vpn in section.start..section.end {
+                    match self
                         .page_table
-                        .map_and_alloc(vpn, PTEFlags::from_bits(permisson.bits).unwrap());
-                    section.v2p.insert(vpn, frame);
+                        .map_and_alloc(vpn, PTEFlags::from_bits(permisson.bits).unwrap())
+                    {
+                        Ok(frame) => {
+                            section.v2p.insert(vpn, Arc::new(frame));
+                        }
+                        Err(err) => {
+                            section.unmap(&mut self.page_table); // release the pages mapped so far
+                            return Err(err);
+                        }
+                    }
+                    // let ppn = self.translate(vpn).unwrap();
+                    // println!("vpn {:#x} -> ppn {:#x}", vpn.0, ppn.0);
                 }
             }
-            // let ppn = self.translate(vpn).unwrap();
-            // println!("vpn {:#x} -> ppn {:#x}", vpn.0, ppn.0);
+            MapType::Lazy | MapType::File => {} // nothing to map, pages are mapped when they are accessed
         }
         if let Some(data) = data {
-            section.copy_data(&mut self.page_table, data)
+            section.copy_data(&mut self.page_table, data, start.page_offset())
+        }
+        if map_type != MapType::Lazy {
+            self.flush_tlb();
         }
         self.sections.push(section);
+        return Ok(());
     }
 
     pub fn delete_section(&mut self, start: VirtAddr) {
@@ -157,27 +460,21 @@ impl AddressSpace {
             .enumerate()
             .find(|(_, section)| section.start == start.to_down_vpn())
             .unwrap();
-        for vpn in section.start..section.end {
-            let ppn = self.page_table.translate(vpn).unwrap();
-            ppn.get_page().iter_mut().for_each(|x| *x = 0); // clear the page
-            self.page_table.unmap(vpn);
-        }
-        if section.map_type == MapType::Framed {
-            section.v2p.clear(); // free physical frames
-        }
+        section.unmap(&mut self.page_table);
         self.sections.remove(idx);
+        self.flush_tlb();
     }
 
     //section trampoline is mapped, but not added in `sections` of each address space.
-    pub fn map_trampoline(&mut self) {
+    pub fn map_trampoline(&mut self) -> Result<(), OutOfMemory> {
         let trampoline_vpn: VPN = VirtAddr::from(TRAMPOLINE_START_VA).to_down_vpn();
         let trampoline_ppn: PPN = PhyAddr::from(strampoline as usize).to_down_ppn();
         self.page_table
-            .map(trampoline_vpn, trampoline_ppn, PTEFlags::R | PTEFlags::X);
+            .map(trampoline_vpn, trampoline_ppn, PTEFlags::R | PTEFlags::X)
     }
 
     //add a trap context section in the address space. Allocate physical frames trap context.
-    pub fn add_trap_context(&mut self) {
+    pub fn add_trap_context(&mut self) -> Result<(), OutOfMemory> {
         let trap_context_start = VirtAddr::from(TRAP_CONTEXT_START_VA);
         let trap_context_end = VirtAddr::from(TRAMPOLINE_START_VA);
         self.add_section(
@@ -186,7 +483,7 @@ impl AddressSpace {
             SectionPermisson::R | SectionPermisson::W,
             MapType::Framed,
             None,
-        );
+        )
     }
 
     pub fn get_pte(&self, vpn: VPN) -> Option<PageTableEntry> {
@@ -197,6 +494,18 @@ impl AddressSpace {
         return self.page_table.translate(vpn);
     }
 
+    // write data to mapped pages at va, whatever their permission is
+    fn write_bytes(&self, va: usize, data: &[u8]) {
+        let mut written = 0;
+        while written < data.len() {
+            let va = VirtAddr::from(va + written);
+            let page = self.translate(va.to_down_vpn()).unwrap().get_page();
+            let len = min(PAGE_SIZE - va.page_offset(), data.len() - written);
+            page[va.page_offset()..va.page_offset() + len].copy_from_slice(&data[written..written + len]);
+            written += len;
+        }
+    }
+
     //set satp register
     #[no_mangle]
     pub fn activate(&self) {
@@ -212,12 +521,565 @@ impl AddressSpace {
     }
 
     pub fn clear(&mut self) {
+        for section in self.sections.iter() {
+            section.write_back(&mut self.page_table, section.start, section.end);
+        }
         self.sections.clear(); // each v2p in sections will be cleared, and the physical frames will be freed.
         self.page_table.clear(); // attention is this correct?
     }
 
+    // the satp of this address space, with its ASID
     pub fn get_satp(&self) -> usize {
-        return self.page_table.get_satp();
+        return self.page_table.get_satp() | self.asid.map_or(0, |asid| asid.satp_bits());
+    }
+
+    fn user_sections(&self) -> impl Iterator<Item = &Section> {
+        return self.sections.iter().filter(|section| section.permisson.contains(SectionPermisson::U));
+    }
+
+    fn resident_size(&self) -> usize {
+        return self.user_sections().map(|section| section.v2p.len()).sum::<usize>() * PAGE_SIZE
+            + self.page_table.frame_cnt() * PAGE_SIZE;
+    }
+
+    // whether resident_cnt more frames and mapped_cnt more pages of sections are within the limits.
+    // the peak is recorded if they are
+    fn charge(&mut self, resident_cnt: usize, mapped_cnt: usize) -> bool {
+        let resident = self.resident_size() + resident_cnt * PAGE_SIZE;
+        let mapped = self.user_sections().map(|section| section.end.0 - section.start.0).sum::<usize>() + mapped_cnt;
+        if resident > self.limits.resident || mapped * PAGE_SIZE > self.limits.mapped {
+            return false;
+        }
+        self.peak = self.peak.max(resident);
+        return true;
+    }
+
+    // record the peak after frames are taken, it may be above the charged usage if page tables grew
+    fn update_peak(&mut self) {
+        self.peak = self.peak.max(self.resident_size());
+    }
+
+    // the heap is not known by the address space, it is left 0
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let page_tables = self.page_table.frame_cnt() * PAGE_SIZE;
+        let stack = self.user_stack.and_then(|stack| {
+            self.sections
+                .iter()
+                .find(|section| section.end == stack.top && section.start != stack.top)
+        });
+        return MemoryUsage {
+            resident: self.resident_size() - page_tables,
+            page_tables,
+            heap: 0,
+            stack: stack.map_or(0, |section| (section.end.0 - section.start.0) * PAGE_SIZE),
+            mapped: self.user_sections().map(|section| section.end.0 - section.start.0).sum::<usize>() * PAGE_SIZE,
+            peak: self.peak,
+            swapped: self.user_sections().map(|section| section.swapped.len()).sum::<usize>() * PAGE_SIZE,
+        };
+    }
+
+    pub fn get_limits(&self) -> MemoryLimits {
+        return self.limits;
+    }
+
+    // the limits are not changed if the address space already uses more
+    pub fn set_limits(&mut self, limits: MemoryLimits) -> bool {
+        let old_limits = self.limits;
+        self.limits = limits;
+        if !self.charge(0, 0) {
+            self.limits = old_limits;
+            return false;
+        }
+        return true;
+    }
+
+    pub fn get_stack_limit(&self) -> usize {
+        return self.user_stack.map_or(USER_STACK_LIMIT, |stack| stack.limit);
+    }
+
+    // set the maximum size the user stack can grow to, no more than USER_STACK_LIMIT.
+    // the limit is not changed if the stack is already larger
+    pub fn set_stack_limit(&mut self, limit: usize) -> bool {
+        let top = match self.user_stack {
+            Some(stack) => stack.top,
+            None => return false,
+        };
+        let limit = limit.min(USER_STACK_LIMIT) / PAGE_SIZE * PAGE_SIZE;
+        let size = self
+            .sections
+            .iter()
+            .find(|section| section.end == top && section.start != top)
+            .map_or(0, |section| (top.0 - section.start.0) * PAGE_SIZE);
+        if size > limit {
+            return false;
+        }
+        self.user_stack.as_mut().unwrap().limit = limit;
+        return true;
+    }
+
+    // grow the user stack section downward to cover vpn, if vpn is below the stack.
+    fn grow_stack(&mut self, vpn: VPN) -> Result<(), PageFaultError> {
+        let stack = match self.user_stack {
+            Some(stack) if stack.guard_start_vpn() <= vpn && vpn < stack.top => stack,
+            _ => return Ok(()),
+        };
+        if vpn < stack.lowest_vpn() {
+            return Err(PageFaultError::StackOverflow);
+        }
+        // the stack may have been unmapped by user
+        let is_stack = |section: &Section| section.end == stack.top && section.start != stack.top; // not the empty heap
+        let grown_cnt = match self.sections.iter().find(|section| is_stack(section)) {
+            Some(section) => section.start.0.saturating_sub(vpn.0),
+            None => return Ok(()),
+        };
+        if !self.charge(0, grown_cnt) {
+            return Err(PageFaultError::MemoryLimit);
+        }
+        let section = self.sections.iter_mut().find(|section| is_stack(section)).unwrap();
+        section.start = section.start.min(vpn);
+        return Ok(());
+    }
+
+    // handle a page fault caused by user code at va. Lazy pages are allocated here, swapped pages are read back,
+    // the user stack grows here, and copy-on-write pages are copied on store. Return an error if the process
+    // should be killed, which includes running out of frames.
+    pub fn handle_page_fault(&mut self, va: VirtAddr, access: AccessType) -> Result<(), PageFaultError> {
+        return self.resolve_fault(va.to_down_vpn(), access, None);
+    }
+
+    // pages in keep are not swapped out to find a frame for the page
+    fn resolve_fault(&mut self, vpn: VPN, access: AccessType, keep: Option<(VPN, VPN)>) -> Result<(), PageFaultError> {
+        if access != AccessType::Execute {
+            self.grow_stack(vpn)?;
+        }
+        let idx = match self.sections.iter().position(|section| section.contains(vpn)) {
+            Some(idx) => idx,
+            None => return Err(PageFaultError::SegmentationFault),
+        };
+        if !self.sections[idx].allows(access) {
+            return Err(PageFaultError::SegmentationFault);
+        }
+        let pte = self.page_table.get_pte(vpn);
+        if pte.map_or(true, |pte| !pte.is_valid()) {
+            let section = &self.sections[idx];
+            let swapped = section.swapped.contains_key(&vpn);
+            let file_page = section
+                .file
+                .as_ref()
+                .map(|range| (Arc::clone(&range.file), range.first_page + (vpn.0 - section.start.0)));
+            if !swapped && section.map_type != MapType::Lazy && file_page.is_none() {
+                return Err(PageFaultError::SegmentationFault);
+            }
+            if let Some((file, page)) = &file_page {
+                if *page >= file.page_cnt() {
+                    return Err(PageFaultError::SegmentationFault); // beyond the end of the file
+                }
+            }
+            // first access to a lazy page, map a zeroed frame, read the page back from the swap disk, or map the
+            // page of the file from the page cache. The frame is charged to this address space
+            if !self.charge(1, 0) {
+                return Err(PageFaultError::MemoryLimit);
+            }
+            let frame = match file_page {
+                Some((file, page)) if !swapped => match file.page(page) {
+                    Some(frame) => frame,
+                    None => file.read_page(page, self.alloc_user_frame(keep)?),
+                },
+                _ => Arc::new(self.alloc_user_frame(keep)?),
+            };
+            let section = &mut self.sections[idx];
+            if let Some(slot) = section.swapped.get(&vpn) {
+                slot.read(frame.ppn.get_page());
+            }
+            self.page_table.map(vpn, frame.ppn, section.pte_flags(&frame))?;
+            section.swapped.remove(&vpn); // the slot is released after the page is mapped again
+            section.v2p.insert(vpn, frame);
+            self.update_peak();
+            self.flush_tlb_page(vpn);
+            return Ok(());
+        }
+        let pte = self.page_table.find_pte(vpn).unwrap();
+        if access != AccessType::Store || !pte.is_cow() {
+            return Err(PageFaultError::SegmentationFault);
+        }
+        // if other sharers have gone, the page can be written in place. Otherwise the copy is a new frame, which is
+        // charged like any other
+        if Arc::strong_count(self.sections[idx].v2p.get(&vpn).unwrap()) > 1 {
+            if !self.charge(1, 0) {
+                return Err(PageFaultError::MemoryLimit);
+            }
+            let new_frame = self.alloc_user_frame(keep)?;
+            let section = &mut self.sections[idx];
+            new_frame.ppn.get_page().copy_from_slice(section.v2p.get(&vpn).unwrap().ppn.get_page());
+            section.v2p.insert(vpn, Arc::new(new_frame));
+            self.update_peak();
+        }
+        let section = &self.sections[idx];
+        let frame = section.v2p.get(&vpn).unwrap();
+        *self.page_table.find_pte(vpn).unwrap() = PageTableEntry::new(frame.ppn, section.pte_flags(frame));
+        self.flush_tlb_page(vpn);
+        return Ok(());
+    }
+
+    // the kernel accesses user memory through physical addresses, which bypasses lazy mapping, swapping and the
+    // write protection of copy-on-write pages. Resolve them in [start, start + len) as user code would, and check
+    // that the range is in the user half and its pages allow the access by user. The pages of the range are not
+    // swapped out while others of them are resolved.
+    pub fn prepare_user_access(&mut self, start: usize, len: usize, access: AccessType) -> Result<(), PageFaultError> {
+        if start.checked_add(len).map_or(true, |end| end > USER_SPACE_END) {
+            return Err(PageFaultError::SegmentationFault);
+        }
+        let start_vpn = VirtAddr::from(start).to_down_vpn();
+        let end_vpn = VirtAddr::from(start + len).to_up_vpn();
+        let needed = match access {
+            AccessType::Load => PTEFlags::U | PTEFlags::R,
+            AccessType::Store => PTEFlags::U | PTEFlags::W,
+            AccessType::Execute => PTEFlags::U | PTEFlags::X,
+        };
+        for vpn in start_vpn..end_vpn {
+            match self.page_table.get_pte(vpn) {
+                Some(pte) if pte.is_valid() && !(access == AccessType::Store && pte.is_cow()) => {}
+                _ => self.resolve_fault(vpn, access, Some((start_vpn, end_vpn)))?,
+            }
+            // a page mapped for the kernel, or a read-only page that is not copy on write
+            if !self.page_table.get_pte(vpn).map_or(false, |pte| pte.flags().contains(needed)) {
+                return Err(PageFaultError::SegmentationFault);
+            }
+        }
+        return Ok(());
+    }
+
+    // a frame for a user page. When frames run low, a page of another process is swapped out for it, or a page
+    // of this address space outside keep if no other process has one. Only page faults come here, see swap.rs.
+    fn alloc_user_frame(&mut self, keep: Option<(VPN, VPN)>) -> Result<FrameTracker, OutOfMemory> {
+        while frames_low() && (swap_out_any() || self.swap_out(keep)) {}
+        return alloc_frame().ok_or(OutOfMemory);
+    }
+
+    // swap out a private page of a user section, which is not in keep. The page is chosen by the clock: from
+    // swap_hand on, a page that has been accessed since it was last looked at gets a second chance, and its
+    // accessed bit is cleared. Return false if there is no such page, or no free swap slot.
+    pub fn swap_out(&mut self, keep: Option<(VPN, VPN)>) -> bool {
+        let kept = |vpn: VPN| keep.map_or(false, |(start, end)| start <= vpn && vpn < end);
+        let mut candidates: Vec<(usize, VPN)> = Vec::new();
+        for (idx, section) in self.sections.iter().enumerate() {
+            if !section.permisson.contains(SectionPermisson::U) || section.shared {
+                continue;
+            }
+            for (vpn, frame) in section.v2p.iter() {
+                if Arc::strong_count(frame) == 1 && !kept(*vpn) {
+                    candidates.push((idx, *vpn));
+                }
+            }
+        }
+        if candidates.is_empty() {
+            return false;
+        }
+        candidates.sort_by_key(|(_, vpn)| *vpn);
+        let start = candidates.partition_point(|(_, vpn)| *vpn < self.swap_hand);
+        // each page is looked at twice at most, it has no accessed bit the second time
+        for i in 0..2 * candidates.len() {
+            let (idx, vpn) = candidates[(start + i) % candidates.len()];
+            let pte = self.page_table.find_pte(vpn).unwrap();
+            if pte.flags().contains(PTEFlags::A) {
+                *pte = PageTableEntry::new(pte.ppn(), pte.flags() - PTEFlags::A);
+                self.flush_tlb_page(vpn);
+                continue;
+            }
+            let section = &mut self.sections[idx];
+            let slot = match SwapSlot::write(section.v2p[&vpn].ppn.get_page()) {
+                Some(slot) => slot,
+                None => return false,
+            };
+            section.v2p.remove(&vpn);
+            section.swapped.insert(vpn, Arc::new(slot));
+            self.page_table.unmap(vpn);
+            self.flush_tlb_page(vpn);
+            self.swap_hand = VPN(vpn.0 + 1);
+            return true;
+        }
+        return false;
+    }
+
+    // whether [start, end) is inside user space, and is not used by any section or reserved for the stack
+    fn is_free_area(&self, start: VPN, end: VPN) -> bool {
+        return self.is_replaceable_area(start, end)
+            && !self.sections.iter().any(|section| section.start < end && start < section.end);
+    }
+
+    // whether [start, end) would be free once user unmaps the sections in it: it is in the user half, away from
+    // the stack and the gap kept for it, and has no sections other than user mappings munmap can remove
+    fn is_replaceable_area(&self, start: VPN, end: VPN) -> bool {
+        if start.0 == 0 || end > VirtAddr::from(USER_SPACE_END).to_down_vpn() {
+            return false;
+        }
+        if let Some(stack) = self.user_stack {
+            if stack.guard_start_vpn() < end && start < stack.top {
+                return false;
+            }
+        }
+        return self
+            .sections
+            .iter()
+            .filter(|section| section.start < end && start < section.end)
+            .all(|section| section.start < section.end && section.permisson.contains(SectionPermisson::U));
+    }
+
+    // the frames and the pages of user sections in [start, end), as charge() counts them
+    fn pages_in(&self, start: VPN, end: VPN) -> (usize, usize) {
+        let mut frame_cnt = 0;
+        let mut page_cnt = 0;
+        for section in self.user_sections().filter(|section| section.start < end && start < section.end) {
+            frame_cnt += section.v2p.range(start..end).count();
+            page_cnt += min(section.end, end).0 - max(section.start, start).0;
+        }
+        return (frame_cnt, page_cnt);
+    }
+
+    // find a free area of page_cnt pages above MMAP_BASE
+    fn find_free_area(&self, page_cnt: usize) -> Option<VPN> {
+        let mut start = VirtAddr::from(MMAP_BASE).to_down_vpn();
+        let limit = VirtAddr::from(USER_SPACE_END).to_down_vpn();
+        while start.0 + page_cnt <= limit.0 {
+            let end = VPN(start.0 + page_cnt);
+            match self
+                .sections
+                .iter()
+                .filter(|section| section.start < end && start < section.end)
+                .map(|section| section.end)
+                .max()
+            {
+                Some(occupied_end) => start = occupied_end, // skip the occupied sections and try again
+                None => return Some(start),
+            }
+        }
+        return None;
+    }
+
+    // make vpn a section boundary, by splitting the section that contains it
+    fn split_section_at(&mut self, vpn: VPN) {
+        if let Some(section) = self
+            .sections
+            .iter_mut()
+            .find(|section| section.start < vpn && vpn < section.end)
+        {
+            let tail = section.split_off(vpn);
+            self.sections.push(tail);
+        }
+    }
+
+    // whether all the sections overlapping [start, end) can be changed by user
+    fn is_user_range(&self, start: VPN, end: VPN) -> bool {
+        return self
+            .sections
+            .iter()
+            .filter(|section| section.start < end && start < section.end)
+            .all(|section| section.permisson.contains(SectionPermisson::U));
+    }
+
+    // choose the area of page_cnt pages for a new mapping at start, as mmap does. A fixed area is checked, with
+    // the memory limits for the new mapping of resident_cnt frames, before the old mappings there are unmapped.
+    fn choose_area(&mut self, start: VirtAddr, page_cnt: usize, resident_cnt: usize, fixed: bool) -> Option<VPN> {
+        if page_cnt == 0 || start.page_offset() != 0 {
+            return None;
+        }
+        let start_vpn = start.to_down_vpn();
+        let end_vpn = VPN(start_vpn.0 + page_cnt);
+        if fixed {
+            if !self.is_replaceable_area(start_vpn, end_vpn) {
+                return None;
+            }
+            let (old_frame_cnt, old_page_cnt) = self.pages_in(start_vpn, end_vpn);
+            if !self.charge(resident_cnt.saturating_sub(old_frame_cnt), page_cnt - old_page_cnt) {
+                return None;
+            }
+            assert!(self.munmap(start, page_cnt * PAGE_SIZE) && self.is_free_area(start_vpn, end_vpn));
+            return Some(start_vpn);
+        } else if self.is_free_area(start_vpn, end_vpn) {
+            return Some(start_vpn);
+        }
+        return self.find_free_area(page_cnt);
+    }
+
+    // map an anonymous area of len bytes with permisson (U is added). A private area is mapped lazily,
+    // and copied on write after fork. A shared area is mapped eagerly, so that its frames are shared after fork.
+    // If start is 0 or the area is occupied, another free area is chosen, unless fixed is set, which replaces
+    // the old mappings. Return the start of the area, or None if failed.
+    pub fn mmap(
+        &mut self,
+        start: VirtAddr,
+        len: usize,
+        permisson: SectionPermisson,
+        shared: bool,
+        fixed: bool,
+    ) -> Option<VirtAddr> {
+        let page_cnt = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+        let start_vpn = self.choose_area(start, page_cnt, if shared { page_cnt } else { 0 }, fixed)?;
+        let map_type = if shared { MapType::Framed } else { MapType::Lazy };
+        self.add_section(
+            start_vpn.into(),
+            VPN(start_vpn.0 + page_cnt).into(),
+            permisson | SectionPermisson::U,
+            map_type,
+            None,
+        )
+        .ok()?;
+        self.sections.last_mut().unwrap().shared = shared;
+        return Some(start_vpn.into());
+    }
+
+    // map the frames of a shared memory object with permisson (U is added), the area is chosen as mmap does.
+    // the section stays shared after fork. Return the start of the area, or None if failed.
+    pub fn map_shared(
+        &mut self,
+        object: &Arc<SharedMemory>,
+        start: VirtAddr,
+        permisson: SectionPermisson,
+        fixed: bool,
+    ) -> Option<VirtAddr> {
+        let page_cnt = object.frames.len();
+        let start_vpn = self.choose_area(start, page_cnt, page_cnt, fixed)?;
+        if !self.charge(page_cnt, page_cnt) {
+            return None;
+        }
+        let mut section = Section::new(
+            start_vpn.into(),
+            VPN(start_vpn.0 + page_cnt).into(),
+            permisson | SectionPermisson::U,
+            MapType::Framed,
+        );
+        section.shared = true;
+        for (i, frame) in object.frames.iter().enumerate() {
+            let vpn = VPN(start_vpn.0 + i);
+            if self.page_table.map(vpn, frame.ppn, section.pte_flags(frame)).is_err() {
+                section.unmap(&mut self.page_table); // release the pages mapped so far
+                return None;
+            }
+            section.v2p.insert(vpn, Arc::clone(frame));
+        }
+        section.shm = Some(ShmMapping::new(Arc::clone(object)));
+        self.flush_tlb();
+        self.sections.push(section);
+        return Some(start_vpn.into());
+    }
+
+    // map len bytes of file with permisson (U is added), at an area chosen by the kernel. Pages are read from the
+    // page cache on the first access. A shared mapping writes to the file, a private one is copied on write.
+    // Return the start of the area, or None if len is 0 or beyond the file.
+    pub fn mmap_file(&mut self, file: Arc<CachedFile>, len: usize, permisson: SectionPermisson, shared: bool) -> Option<VirtAddr> {
+        let page_cnt = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+        if page_cnt > file.page_cnt() {
+            return None;
+        }
+        let start_vpn = self.choose_area(VirtAddr::from(0), page_cnt, 0, false)?;
+        if !self.charge(0, page_cnt) {
+            return None;
+        }
+        let mut section = Section::new(
+            start_vpn.into(),
+            VPN(start_vpn.0 + page_cnt).into(),
+            permisson | SectionPermisson::U,
+            MapType::File,
+        );
+        section.shared = shared;
+        section.file = Some(FileRange { file, first_page: 0 });
+        self.sections.push(section);
+        return Some(start_vpn.into());
+    }
+
+    // write the pages of shared file mappings in [start, start + len) that have been written back to their files.
+    // Return false if the range can not be changed by user.
+    pub fn msync(&mut self, start: VirtAddr, len: usize) -> bool {
+        let start_vpn = start.to_down_vpn();
+        let end_vpn = VirtAddr::from(start.0 + len).to_up_vpn();
+        if start.page_offset() != 0 || !self.is_user_range(start_vpn, end_vpn) {
+            return false;
+        }
+        for section in self.sections.iter() {
+            section.write_back(&mut self.page_table, start_vpn, end_vpn);
+        }
+        self.flush_tlb(); // so that the dirty bits are set again on the next write
+        return true;
+    }
+
+    // unmap the shared memory section that starts at start. Return false if there is no such section.
+    pub fn unmap_shared(&mut self, start: VirtAddr) -> bool {
+        let end = match self
+            .sections
+            .iter()
+            .find(|section| section.shm.is_some() && section.start == start.to_down_vpn() && start.page_offset() == 0)
+        {
+            Some(section) => section.end,
+            None => return false,
+        };
+        return self.munmap(start, (end.0 - start.to_down_vpn().0) * PAGE_SIZE);
+    }
+
+    // unmap the pages in [start, start + len), sections are split if they are partially unmapped.
+    // Return false if the range contains pages that user can not unmap.
+    pub fn munmap(&mut self, start: VirtAddr, len: usize) -> bool {
+        let start_vpn = start.to_down_vpn();
+        let end_vpn = VirtAddr::from(start.0 + len).to_up_vpn();
+        if start.page_offset() != 0 || !self.is_user_range(start_vpn, end_vpn) {
+            return false;
+        }
+        self.split_section_at(start_vpn);
+        self.split_section_at(end_vpn);
+        let page_table = &mut self.page_table;
+        self.sections.retain_mut(|section| {
+            // the empty heap section is kept for sbrk
+            if section.start < section.end && start_vpn <= section.start && section.end <= end_vpn {
+                section.unmap(page_table);
+                return false;
+            }
+            return true;
+        });
+        self.flush_tlb();
+        return true;
+    }
+
+    // change the permisson (U is added) of the pages in [start, start + len), sections are split if they are
+    // partially changed. Return false if some pages in the range are not mapped or can not be changed by user.
+    pub fn mprotect(&mut self, start: VirtAddr, len: usize, permisson: SectionPermisson) -> bool {
+        let start_vpn = start.to_down_vpn();
+        let end_vpn = VirtAddr::from(start.0 + len).to_up_vpn();
+        if start.page_offset() != 0 || !self.is_user_range(start_vpn, end_vpn) {
+            return false;
+        }
+        // the whole range should be covered by sections
+        let mut ranges: Vec<(VPN, VPN)> = self
+            .sections
+            .iter()
+            .filter(|section| section.start < end_vpn && start_vpn < section.end)
+            .map(|section| (section.start, section.end))
+            .collect();
+        ranges.sort();
+        let mut covered = start_vpn;
+        for (section_start, section_end) in ranges {
+            if section_start > covered {
+                break;
+            }
+            covered = covered.max(section_end);
+        }
+        if covered < end_vpn {
+            return false;
+        }
+
+        self.split_section_at(start_vpn);
+        self.split_section_at(end_vpn);
+        for section in self
+            .sections
+            .iter_mut()
+            .filter(|section| start_vpn <= section.start && section.end <= end_vpn)
+        {
+            section.permisson = permisson | SectionPermisson::U;
+            for (vpn, frame) in section.v2p.iter() {
+                let pte = self.page_table.find_pte(*vpn).unwrap();
+                *pte = PageTableEntry::new(frame.ppn, section.pte_flags(frame));
+            }
+        }
+        self.flush_tlb();
+        return true;
     }
 
     #[allow(unused)]
@@ -229,12 +1091,13 @@ impl AddressSpace {
         {
             let new_brk_vpn = new_brk.to_up_vpn();
             for vpn in new_brk_vpn..heap.end {
-                if heap.map_type == MapType::Framed {
-                    heap.v2p.remove(&vpn);
+                if heap.map_type == MapType::Identical || heap.v2p.remove(&vpn).is_some() {
+                    self.page_table.unmap(vpn);
                 }
-                self.page_table.unmap(vpn);
+                heap.swapped.remove(&vpn);
             }
             heap.end = new_brk_vpn;
+            self.flush_tlb();
             return true;
         } else {
             return false;
@@ -248,25 +1111,52 @@ impl AddressSpace {
             .find(|section| section.start == heap_bottom.to_down_vpn())
         {
             let new_brk_vpn = new_brk.to_up_vpn();
+            let heap_start = heap.start;
+            let grown_cnt = new_brk_vpn.0.saturating_sub(heap.end.0);
+            let resident_cnt = if heap.map_type == MapType::Framed { grown_cnt } else { 0 };
+            if !self.charge(resident_cnt, grown_cnt) {
+                return false; // the heap would exceed the memory limits
+            }
+            if self
+                .sections
+                .iter()
+                .any(|section| section.start != heap_start && section.start < new_brk_vpn && heap_start < section.end)
+            {
+                return false; // the heap would run into another section
+            }
+            let heap = self
+                .sections
+                .iter_mut()
+                .find(|section| section.start == heap_start)
+                .unwrap();
             for vpn in heap.end..new_brk_vpn {
-                match heap.map_type {
+                let result = match heap.map_type {
                     MapType::Identical => {
                         let ppn = PPN(vpn.0); // for kernel space, mapping is identical, and there's no need to allocate a new frame.
                         self.page_table.map(
                             vpn,
                             ppn,
                             PTEFlags::from_bits(heap.permisson.bits).unwrap(),
-                        );
+                        )
                     }
-                    MapType::Framed => {
-                        let frame = self
-                            .page_table
-                            .map_and_alloc(vpn, PTEFlags::from_bits(heap.permisson.bits).unwrap());
-                        heap.v2p.insert(vpn, frame);
+                    MapType::Framed => self
+                        .page_table
+                        .map_and_alloc(vpn, PTEFlags::from_bits(heap.permisson.bits).unwrap())
+                        .map(|frame| {
+                            heap.v2p.insert(vpn, Arc::new(frame));
+                        }),
+                    MapType::Lazy | MapType::File => {
+                        break; // reserve the range only
                     }
+                };
+                if result.is_err() {
+                    heap.end = vpn; // keep the pages mapped so far, they are released when the heap shrinks
+                    self.flush_tlb();
+                    return false;
                 }
             }
             heap.end = new_brk_vpn;
+            self.flush_tlb();
             return true;
         } else {
             return false;
@@ -301,9 +1191,233 @@ pub fn test_space() {
             .is_executable(),
         false,
     );
+    // physical memory is mapped identically, partly by megapages
+    for pa in [ekernel as usize, (ekernel as usize + MEMORY_END) / 2, MEMORY_END - PAGE_SIZE] {
+        let vpn = VirtAddr::from(pa).to_down_vpn();
+        assert_eq!(kernel_space.translate(vpn).unwrap().0, vpn.0);
+    }
     println!("{}remap_test passed!{}", GREEN, RESET);
 }
 
+// a minimal riscv64 executable, segments are (flags, offset, vaddr, file size, mem size)
+fn test_elf(machine: u16, entry: usize, segments: &[(u32, usize, usize, usize, usize)]) -> Vec<u8> {
+    let mut elf = vec![0u8; 0x200];
+    elf[..8].copy_from_slice(&[0x7f, 0x45, 0x4c, 0x46, 2, 1, 1, 0]); // magic, 64-bit, little endian, version
+    elf[16..18].copy_from_slice(&2u16.to_le_bytes()); // executable
+    elf[18..20].copy_from_slice(&machine.to_le_bytes());
+    elf[20..24].copy_from_slice(&1u32.to_le_bytes());
+    elf[24..32].copy_from_slice(&(entry as u64).to_le_bytes());
+    elf[32..40].copy_from_slice(&64u64.to_le_bytes()); // program headers follow the elf header
+    elf[52..54].copy_from_slice(&64u16.to_le_bytes());
+    elf[54..56].copy_from_slice(&56u16.to_le_bytes());
+    elf[56..58].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+    elf[58..60].copy_from_slice(&64u16.to_le_bytes());
+    for (i, &(flags, offset, va, file_size, mem_size)) in segments.iter().enumerate() {
+        let ph = &mut elf[64 + i * 56..64 + (i + 1) * 56];
+        ph[0..4].copy_from_slice(&1u32.to_le_bytes()); // load
+        ph[4..8].copy_from_slice(&flags.to_le_bytes());
+        for (j, value) in [offset, va, va, file_size, mem_size, PAGE_SIZE].iter().enumerate() {
+            ph[8 + j * 8..16 + j * 8].copy_from_slice(&(*value as u64).to_le_bytes());
+        }
+    }
+    return elf;
+}
+
+pub fn elf_test() {
+    const X: u32 = 1;
+    const W: u32 = 2;
+    const R: u32 = 4;
+    let text = (R | X, 0x100, 0x1000, 0x100, 0x100);
+    let data = (R | W, 0x100, 0x2000, 0x80, 0x1000);
+    let load = |machine, entry, segments: &[_]| user_space_from_elf(&test_elf(machine, entry, segments)).map(|_| ());
+
+    assert_eq!(load(EM_RISCV, 0x1000, &[text, data]), Ok(()));
+    // the machine type
+    assert_eq!(load(62, 0x1000, &[text]), Err(ElfError::NotRiscv64)); // x86-64
+    assert_eq!(user_space_from_elf(&test_elf(EM_RISCV, 0x1000, &[text])[..40]).map(|_| ()), Err(ElfError::Malformed));
+    // the program header table is in the file
+    for ph_offset in [0x200 - 0x30, u64::MAX - 0x30] {
+        let mut elf = test_elf(EM_RISCV, 0x1000, &[text]);
+        elf[32..40].copy_from_slice(&ph_offset.to_le_bytes());
+        assert_eq!(user_space_from_elf(&elf).map(|_| ()), Err(ElfError::Malformed));
+    }
+    // file ranges
+    assert_eq!(load(EM_RISCV, 0x1000, &[(R | X, 0x180, 0x1000, 0x100, 0x100)]), Err(ElfError::BadSegment));
+    assert_eq!(load(EM_RISCV, 0x1000, &[(R | X, usize::MAX, 0x1000, 0x100, 0x100)]), Err(ElfError::BadSegment));
+    assert_eq!(load(EM_RISCV, 0x1000, &[(R | X, 0x100, 0x1000, 0x100, 0x80)]), Err(ElfError::BadSegment));
+    // segments in the user half, clear of the trampoline and the trap context
+    for va in [USER_SPACE_END - 0x80, TRAP_CONTEXT_START_VA, TRAMPOLINE_START_VA, usize::MAX - 0x80] {
+        assert_eq!(load(EM_RISCV, 0x1000, &[text, (R | W, 0x100, va, 0x80, 0x100)]), Err(ElfError::BadSegment));
+    }
+    // overlapping segments, even in one page
+    assert_eq!(load(EM_RISCV, 0x1000, &[text, (R | W, 0x100, 0x1800, 0x80, 0x100)]), Err(ElfError::BadSegment));
+    // W+X
+    assert_eq!(load(EM_RISCV, 0x1000, &[(R | W | X, 0x100, 0x1000, 0x100, 0x100)]), Err(ElfError::WritableExecutable));
+    // the entry point
+    assert_eq!(load(EM_RISCV, 0x2000, &[text, data]), Err(ElfError::BadEntry));
+    assert_eq!(load(EM_RISCV, 0x1100, &[text, data]), Err(ElfError::BadEntry));
+    pie_test();
+    interpreter_test();
+    initial_stack_test();
+    println!("{}elf_test passed!{}", GREEN, RESET);
+}
+
+fn initial_stack_test() {
+    use super::user_ptr::UserPtr;
+    let argv = [String::from("echo"), String::from("hello"), String::new()];
+    let envp = [String::from("PATH=/bin")];
+    let (mut space, sp, _, _) = user_space_from_elf_with_args(&test_pie(R_RISCV_RELATIVE), &argv, &envp).unwrap();
+    let mut word = |i: usize| UserPtr::<usize>::new(sp.0 + i * 8).read(&mut space).unwrap();
+    let words: Vec<usize> = (0..7).map(&mut word).collect();
+    assert_eq!(sp.0 % 16, 0);
+    // argc, argv ending with NULL, envp ending with NULL, then the auxiliary vector
+    assert_eq!(words[0], 3);
+    assert_eq!((words[4], words[6]), (0, 0));
+    assert_ne!(word(7), AT_NULL);
+    let mut string = |va: usize| UserPtr::<u8>::new(va).read_string(&mut space).unwrap();
+    assert_eq!([string(words[1]), string(words[2]), string(words[3])], argv);
+    assert_eq!(string(words[5]), envp[0]);
+    // the strings are above the vectors, in order
+    assert!(words[1] > sp.0 + 7 * 8 && words[1] < words[2] && words[3] < words[5]);
+}
+
+// dyntest in user/ is started by the dynamic loader ld, which links it with libuser.
+// the kernel side of PT_INTERP is checked here, with a program and an interpreter made up by hand
+fn interpreter_test() {
+    // the program asks for /lib/ld.so, which is a position independent executable
+    let mut program = test_pie(R_RISCV_RELATIVE);
+    let ph = &mut program[64 + 2 * 56..64 + 3 * 56];
+    ph[0] = 3; // interp
+    ph[8..16].copy_from_slice(&0x1e0u64.to_le_bytes());
+    ph[32..40].copy_from_slice(&11u64.to_le_bytes());
+    program[0x1e0..0x1eb].copy_from_slice(b"/lib/ld.so\0");
+    let interpreter = test_pie(R_RISCV_RELATIVE);
+    let open = |name: &str| (name == "ld.so").then_some(interpreter.as_slice());
+
+    let (space, sp, _, entry) = load_user_space(&program, &[], &[], &open).unwrap();
+    let read_word = |va: usize| {
+        let va = VirtAddr::from(va);
+        read_u64(space.translate(va.to_down_vpn()).unwrap().get_page(), va.page_offset()) as usize
+    };
+    // the process starts in the interpreter, which is relocated by the kernel
+    let base = entry - 0x1000;
+    assert!(base >= INTERP_BASE);
+    assert_eq!(read_word(base + 0x2070), entry);
+    // argc, argv and envp, then the auxiliary vector
+    assert_eq!([read_word(sp.0), read_word(sp.0 + 8), read_word(sp.0 + 16)], [0, 0, 0]);
+    let mut auxv = BTreeMap::new();
+    let mut va = sp.0 + 24;
+    while read_word(va) != AT_NULL {
+        auxv.insert(read_word(va), read_word(va + 8));
+        va += 16;
+    }
+    assert_eq!(auxv[&AT_BASE], base);
+    assert_eq!(auxv[&AT_PAGESZ], PAGE_SIZE);
+    assert_eq!(auxv[&AT_PHNUM], 3);
+    // the program is left for the interpreter to link
+    let program_entry = auxv[&AT_ENTRY];
+    assert!(program_entry >= PIE_BASE && program_entry < INTERP_BASE);
+    assert_eq!(read_word(program_entry - 0x1000 + 0x2070), 0);
+    assert!(auxv[&AT_RANDOM] > va && auxv[&AT_RANDOM] < USER_SPACE_END);
+
+    // the interpreter is missing, or it is not position independent
+    assert_eq!(load_user_space(&program, &[], &[], &|_| None).map(|_| ()), Err(ElfError::BadInterpreter));
+    let fixed = test_elf(EM_RISCV, 0x1000, &[(5, 0x100, 0x1000, 0x80, 0x80)]);
+    assert_eq!(load_user_space(&program, &[], &[], &|_| Some(fixed.as_slice())).map(|_| ()), Err(ElfError::BadInterpreter));
+}
+
+// a position independent executable, whose data segment holds the dynamic section, and a relocation
+// of the given type for the word at va 0x2070
+fn test_pie(relocation_type: u64) -> Vec<u8> {
+    let segments = [(5, 0x100, 0x1000, 0x80, 0x80), (6, 0x180, 0x2000, 0x80, 0x1000), (6, 0x180, 0x2000, 0x40, 0x40)];
+    let mut elf = test_elf(EM_RISCV, 0x1000, &segments);
+    elf[16] = 3; // shared object
+    elf[64 + 2 * 56] = 2; // the third segment is the dynamic section
+    for (i, (tag, value)) in [(DT_RELA, 0x2040), (DT_RELASZ, 24), (DT_RELAENT, 24), (DT_NULL, 0)].iter().enumerate() {
+        elf[0x180 + i * 16..0x188 + i * 16].copy_from_slice(&tag.to_le_bytes());
+        elf[0x188 + i * 16..0x190 + i * 16].copy_from_slice(&(*value as u64).to_le_bytes());
+    }
+    for (i, value) in [0x2070, relocation_type, 0x1000].iter().enumerate() {
+        elf[0x1c0 + i * 8..0x1c8 + i * 8].copy_from_slice(&(*value as u64).to_le_bytes());
+    }
+    return elf;
+}
+
+fn pie_test() {
+    let aslr = aslr_enabled();
+    // the relocated word holds the address of the entry
+    let read_word = |space: &AddressSpace, va: usize| {
+        let va = VirtAddr::from(va);
+        read_u64(space.translate(va.to_down_vpn()).unwrap().get_page(), va.page_offset()) as usize
+    };
+    set_aslr(false);
+    let (space, stack, heap, entry) = user_space_from_elf(&test_pie(R_RISCV_RELATIVE)).unwrap();
+    assert_eq!(entry, PIE_BASE + 0x1000);
+    assert_eq!(read_word(&space, PIE_BASE + 0x2070), entry);
+    // the same layout for each exec
+    let (_, stack_again, heap_again, _) = user_space_from_elf(&test_pie(R_RISCV_RELATIVE)).unwrap();
+    assert_eq!((stack.0, heap.0), (stack_again.0, heap_again.0));
+    assert!(stack.0 < heap.0 && heap.0 - stack.0 < PAGE_SIZE); // only the initial stack is between them
+
+    set_aslr(true);
+    let mut layouts = Vec::new();
+    for _ in 0..8 {
+        let (space, stack, heap, entry) = user_space_from_elf(&test_pie(R_RISCV_RELATIVE)).unwrap();
+        let base = entry - 0x1000;
+        assert!(base >= PIE_BASE && base % PAGE_SIZE == 0);
+        assert_eq!(read_word(&space, base + 0x2070), entry);
+        assert!(heap.0 >= base + 0x3000 + USER_STACK_GUARD_SIZE + USER_STACK_LIMIT && heap.0 > stack.0);
+        layouts.push((base, stack.0 - base, heap.0 - stack.0));
+    }
+    assert!(layouts.iter().any(|layout| layout.0 != layouts[0].0));
+    assert!(layouts.iter().any(|layout| layout.1 != layouts[0].1));
+    assert!(layouts.iter().any(|layout| layout.2 != layouts[0].2));
+    set_aslr(aslr);
+
+    // R_RISCV_64 needs a symbol
+    assert_eq!(user_space_from_elf(&test_pie(2)).map(|_| ()), Err(ElfError::BadRelocation));
+    // a relocation out of the segments
+    let mut elf = test_pie(R_RISCV_RELATIVE);
+    elf[0x1c0..0x1c8].copy_from_slice(&0x8000u64.to_le_bytes());
+    assert_eq!(user_space_from_elf(&elf).map(|_| ()), Err(ElfError::BadRelocation));
+}
+
+#[allow(unused)]
+// pages of a lazy section are swapped out by the clock, and read back on page faults
+pub fn swap_space_test() {
+    let mut space = AddressSpace::new().unwrap();
+    let start = VirtAddr::from(MMAP_BASE);
+    let page_cnt = 8;
+    space.mmap(start, page_cnt * PAGE_SIZE, SectionPermisson::R | SectionPermisson::W, false, false).unwrap();
+    for i in 0..page_cnt {
+        let vpn = VPN(start.to_down_vpn().0 + i);
+        space.handle_page_fault(vpn.into(), AccessType::Store).unwrap();
+        space.translate(vpn).unwrap().get_page().fill(i as u8 + 1);
+    }
+    // the kept pages are skipped
+    let keep = (start.to_down_vpn(), VPN(start.to_down_vpn().0 + 4));
+    if !space.swap_out(Some(keep)) {
+        println!("swap_space_test skipped, there is no swap disk");
+        return;
+    }
+    while space.swap_out(Some(keep)) {}
+    let usage = space.memory_usage();
+    assert_eq!(usage.swapped, (page_cnt - 4) * PAGE_SIZE);
+    assert!(space.translate(VPN(keep.1 .0)).is_none() && space.translate(keep.0).is_some());
+    // the clock goes on from the first page, which gets a second chance as it has been accessed
+    let pte = space.page_table.find_pte(keep.0).unwrap();
+    *pte = PageTableEntry::new(pte.ppn(), pte.flags() | PTEFlags::A);
+    assert!(space.swap_out(None));
+    assert!(space.translate(keep.0).is_some() && space.translate(VPN(keep.0 .0 + 1)).is_none());
+    for i in 0..page_cnt {
+        let vpn = VPN(start.to_down_vpn().0 + i);
+        space.handle_page_fault(vpn.into(), AccessType::Load).unwrap();
+        assert!(space.translate(vpn).unwrap().get_page().iter().all(|&byte| byte == i as u8 + 1));
+    }
+    assert_eq!(space.memory_usage().swapped, 0);
+    println!("{}swap_space_test passed!{}", GREEN, RESET);
+}
+
 extern "C" {
     fn stext();
     fn etext();
@@ -319,12 +1433,14 @@ extern "C" {
 
 // build kernel space
 pub fn kernel_space() -> AddressSpace {
-    let mut kernel_space = AddressSpace::new();
+    // the kernel can not run without its address space, so running out of frames here is fatal
+    let mut kernel_space = AddressSpace::new().unwrap();
+    kernel_space.asid = Some(Asid::KERNEL);
 
     println!("{}start build kernel space!{}", GREEN, RESET);
 
     //add trampoline section
-    kernel_space.map_trampoline();
+    kernel_space.map_trampoline().unwrap();
 
     //add text section of kernel
 
@@ -338,7 +1454,7 @@ pub fn kernel_space() -> AddressSpace {
         SectionPermisson::R | SectionPermisson::X,
         MapType::Identical,
         None,
-    );
+    ).unwrap();
 
     //add rodata section of kernel
 
@@ -352,7 +1468,7 @@ pub fn kernel_space() -> AddressSpace {
         SectionPermisson::R,
         MapType::Identical,
         None,
-    );
+    ).unwrap();
 
     //add data section of kernel
     print!(
@@ -365,7 +1481,7 @@ pub fn kernel_space() -> AddressSpace {
         SectionPermisson::R | SectionPermisson::W,
         MapType::Identical,
         None,
-    );
+    ).unwrap();
 
     //add bss section of kernel
     print!(
@@ -378,7 +1494,7 @@ pub fn kernel_space() -> AddressSpace {
         SectionPermisson::R | SectionPermisson::W,
         MapType::Identical,
         None,
-    );
+    ).unwrap();
 
     // map physical frames [ekernel, MEMORY_END), so that the kernel can access the whole physical memory
     print!(
@@ -391,7 +1507,7 @@ pub fn kernel_space() -> AddressSpace {
         SectionPermisson::R | SectionPermisson::W,
         MapType::Identical,
         None,
-    );
+    ).unwrap();
 
     //map IO space
     // print!("map IO space! ");
@@ -402,7 +1518,7 @@ pub fn kernel_space() -> AddressSpace {
             SectionPermisson::R | SectionPermisson::W,
             MapType::Identical,
             None,
-        );
+        ).unwrap();
     }
 
     // kernel stack is allocated for each app when creating a new task
@@ -413,97 +1529,445 @@ pub fn kernel_space() -> AddressSpace {
 }
 
 // build user space from elf data
-// return user space, user stack start address, and entry point of elf
-pub fn user_space_from_elf(elf_data: &[u8]) -> (AddressSpace, VirtAddr, usize) {
+const EM_RISCV: u16 = 243; // e_machine of RISC-V
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+// check the elf header and that the program header table is in the file, return the elf file.
+// xmas-elf slices the headers out of the file without checking the bounds, so they are checked here first.
+fn parse_elf(elf_data: &[u8]) -> Result<ElfFile, ElfError> {
+    if elf_data.len() < ELF_HEADER_SIZE {
+        return Err(ElfError::Malformed);
+    }
+    let elf = ElfFile::new(elf_data).map_err(|_| ElfError::Malformed)?;
+    if elf.header.pt1.magic != [0x7f, 0x45, 0x4c, 0x46] {
+        return Err(ElfError::Malformed);
+    }
+    let machine = u16::from_le_bytes([elf_data[18], elf_data[19]]);
+    if elf.header.pt1.class() != header::Class::SixtyFour
+        || elf.header.pt1.data() != header::Data::LittleEndian
+        || machine != EM_RISCV
+        || !matches!(elf.header.pt2.type_().as_type(), header::Type::Executable | header::Type::SharedObject)
+    {
+        return Err(ElfError::NotRiscv64);
+    }
+    let pt2 = &elf.header.pt2;
+    let table_end = (pt2.ph_count() as usize)
+        .checked_mul(PROGRAM_HEADER_SIZE)
+        .and_then(|size| (pt2.ph_offset() as usize).checked_add(size));
+    if pt2.ph_entry_size() as usize != PROGRAM_HEADER_SIZE || table_end.map_or(true, |end| end > elf_data.len()) {
+        return Err(ElfError::Malformed);
+    }
+    return Ok(elf);
+}
+
+// a loadable segment, checked against the file and the user half of the address space
+struct Segment<'a> {
+    start: VirtAddr,
+    end: VirtAddr,
+    permission: SectionPermisson,
+    data: &'a [u8],
+}
+
+// the part of the file a segment holds
+fn segment_data<'a>(elf: &ElfFile<'a>, ph: &ProgramHeader) -> Result<&'a [u8], ElfError> {
+    let offset = ph.offset() as usize;
+    let file_end = offset.checked_add(ph.file_size() as usize).ok_or(ElfError::BadSegment)?;
+    return elf.input.get(offset..file_end).ok_or(ElfError::BadSegment);
+}
+
+// a segment of a position independent executable is moved by base
+fn check_segment<'a>(elf: &ElfFile<'a>, ph: &ProgramHeader, base: usize) -> Result<Segment<'a>, ElfError> {
+    let va = base.checked_add(ph.virtual_addr() as usize).ok_or(ElfError::BadSegment)?;
+    let mem_size = ph.mem_size() as usize;
+    // the file range is in the file, and the segment is in the user half,
+    // so it stays clear of the trampoline and the trap context at the top
+    let data = segment_data(elf, ph)?;
+    let end = va.checked_add(mem_size).ok_or(ElfError::BadSegment)?;
+    if data.len() > mem_size || end > USER_SPACE_END {
+        return Err(ElfError::BadSegment);
+    }
+    let flags = ph.flags();
+    if flags.is_write() && flags.is_execute() {
+        return Err(ElfError::WritableExecutable);
+    }
+    let mut permission = SectionPermisson::U;
+    if flags.is_read() {
+        permission |= SectionPermisson::R;
+    }
+    if flags.is_write() {
+        permission |= SectionPermisson::W;
+    }
+    if flags.is_execute() {
+        permission |= SectionPermisson::X;
+    }
+    return Ok(Segment {
+        start: va.into(),
+        end: end.into(),
+        permission,
+        data,
+    });
+}
+
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const DT_REL: u64 = 17;
+const RELA_SIZE: usize = 24;
+const R_RISCV_NONE: u64 = 0;
+const R_RISCV_RELATIVE: u64 = 3;
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    return u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+}
+
+// the bytes of [va, va + len) that come from the file
+fn file_bytes<'a>(segments: &[Segment<'a>], va: usize, len: usize) -> Option<&'a [u8]> {
+    let segment = segments.iter().find(|segment| segment.start.0 <= va && va < segment.end.0)?;
+    let offset = va - segment.start.0;
+    return segment.data.get(offset..offset.checked_add(len)?);
+}
+
+// apply the relocations of a position independent executable loaded at base.
+// only R_RISCV_RELATIVE is supported, which needs no symbol.
+fn relocate(user_space: &AddressSpace, segments: &[Segment], dynamic: &[u8], base: usize) -> Result<(), ElfError> {
+    let (mut rela, mut rela_size, mut rela_entry_size) = (None, 0, RELA_SIZE);
+    for entry in dynamic.chunks_exact(16) {
+        let value = read_u64(entry, 8) as usize;
+        match read_u64(entry, 0) {
+            DT_NULL => break,
+            DT_RELA => rela = Some(value),
+            DT_RELASZ => rela_size = value,
+            DT_RELAENT => rela_entry_size = value,
+            DT_REL => return Err(ElfError::BadRelocation), // RISC-V only uses rela
+            _ => {}
+        }
+    }
+    let rela = match rela {
+        Some(rela) => rela,
+        None => return Ok(()),
+    };
+    if rela_entry_size != RELA_SIZE {
+        return Err(ElfError::BadRelocation);
+    }
+    let table = base
+        .checked_add(rela)
+        .and_then(|va| file_bytes(segments, va, rela_size))
+        .ok_or(ElfError::BadRelocation)?;
+    for entry in table.chunks_exact(RELA_SIZE) {
+        let (offset, info, addend) = (read_u64(entry, 0) as usize, read_u64(entry, 8), read_u64(entry, 16) as usize);
+        match info & 0xffff_ffff {
+            R_RISCV_NONE => {}
+            R_RISCV_RELATIVE => {
+                let va = base.checked_add(offset).ok_or(ElfError::BadRelocation)?;
+                if !segments.iter().any(|segment| segment.start.0 <= va && va.saturating_add(8) <= segment.end.0) {
+                    return Err(ElfError::BadRelocation);
+                }
+                user_space.write_bytes(va, &(base.wrapping_add(addend) as u64).to_le_bytes());
+            }
+            _ => return Err(ElfError::BadRelocation),
+        }
+    }
+    return Ok(());
+}
+
+// randomize the layout of user address spaces
+static ASLR: AtomicBool = AtomicBool::new(cfg!(not(feature = "no-aslr")));
+
+// ASLR is on unless the kernel is built with the no-aslr feature
+pub fn set_aslr(enabled: bool) {
+    ASLR.store(enabled, Ordering::Relaxed);
+}
+
+pub fn aslr_enabled() -> bool {
+    return ASLR.load(Ordering::Relaxed);
+}
+
+// a random number of pages below 2^bits, in bytes. 0 if ASLR is off
+fn random_offset(bits: usize) -> usize {
+    if !aslr_enabled() {
+        return 0;
+    }
+    return (random() & ((1 << bits) - 1)) * PAGE_SIZE;
+}
+
+// an elf file whose segments are checked, and moved by base if it is position independent
+struct ElfImage<'a> {
+    elf: ElfFile<'a>,
+    base: usize,
+    segments: Vec<Segment<'a>>,
+    dynamic: &'a [u8],
+    interpreter: Option<&'a str>, // the path in PT_INTERP
+    entry: usize,
+}
+
+impl<'a> ElfImage<'a> {
+    // a position independent executable is loaded at pie_base
+    fn new(elf_data: &'a [u8], pie_base: usize) -> Result<Self, ElfError> {
+        let elf = parse_elf(elf_data)?;
+        let base = match elf.header.pt2.type_().as_type() {
+            header::Type::SharedObject => pie_base,
+            _ => 0,
+        };
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut dynamic: &[u8] = &[];
+        let mut interpreter = None;
+        for i in 0..elf.header.pt2.ph_count() {
+            let ph = elf.program_header(i).map_err(|_| ElfError::Malformed)?;
+            match ph.get_type().map_err(|_| ElfError::Malformed)? {
+                program::Type::Load => {}
+                program::Type::Dynamic => {
+                    dynamic = segment_data(&elf, &ph)?;
+                    continue;
+                }
+                program::Type::Interp => {
+                    let path = segment_data(&elf, &ph)?.split(|&c| c == 0).next().unwrap();
+                    interpreter = Some(core::str::from_utf8(path).map_err(|_| ElfError::BadInterpreter)?);
+                    continue;
+                }
+                _ => continue,
+            }
+            let segment = check_segment(&elf, &ph, base)?;
+            // segments can not share a page, for each page has one permission
+            if overlaps(&segments, &segment) {
+                return Err(ElfError::BadSegment);
+            }
+            segments.push(segment);
+        }
+        let entry = base.checked_add(elf.header.pt2.entry_point() as usize).ok_or(ElfError::BadEntry)?;
+        if !segments.iter().any(|segment| {
+            segment.permission.contains(SectionPermisson::X) && segment.start.0 <= entry && entry < segment.end.0
+        }) {
+            return Err(ElfError::BadEntry);
+        }
+        return Ok(Self {
+            elf,
+            base,
+            segments,
+            dynamic,
+            interpreter,
+            entry,
+        });
+    }
+
+    // the address of the program headers in the user space, if they are loaded
+    fn phdr(&self) -> Option<usize> {
+        let phoff = self.elf.header.pt2.ph_offset() as usize;
+        let input = self.elf.input.as_ptr() as usize;
+        return self.segments.iter().find_map(|segment| {
+            let offset = segment.data.as_ptr() as usize - input;
+            (offset <= phoff && phoff < offset + segment.data.len()).then(|| segment.start.0 + phoff - offset)
+        });
+    }
+}
+
+fn overlaps(segments: &[Segment], segment: &Segment) -> bool {
+    return segments.iter().any(|other| {
+        segment.start.to_down_vpn() < other.end.to_up_vpn() && other.start.to_down_vpn() < segment.end.to_up_vpn()
+    });
+}
+
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_BASE: usize = 7;
+const AT_ENTRY: usize = 9;
+const AT_RANDOM: usize = 25;
+
+// build the initial stack below top in the System V layout, return the stack pointer.
+// sp -> argc, argv pointers ending with NULL, envp pointers ending with NULL, auxv pairs ending with AT_NULL,
+// followed by the NUL-terminated strings of argv and envp, and 16 random bytes.
+fn push_initial_stack(
+    user_space: &mut AddressSpace,
+    top: usize,
+    argv: &[String],
+    envp: &[String],
+    auxv: &[(usize, usize)],
+) -> Result<usize, ElfError> {
+    let random_bytes = top - 16;
+    let strings_start = random_bytes - argv.iter().chain(envp).map(|string| string.len() + 1).sum::<usize>();
+    // the strings follow each other from strings_start
+    let mut addrs = Vec::new();
+    let mut va = strings_start;
+    for string in argv.iter().chain(envp) {
+        addrs.push(va);
+        va += string.len() + 1;
+    }
+    let (argv_addrs, envp_addrs) = addrs.split_at(argv.len());
+    let mut words: Vec<usize> = vec![argv.len()];
+    words.extend_from_slice(argv_addrs);
+    words.push(0); // the end of argv
+    words.extend_from_slice(envp_addrs);
+    words.push(0); // the end of envp
+    for &(key, value) in auxv {
+        words.extend_from_slice(&[key, value]);
+    }
+    words.extend_from_slice(&[AT_RANDOM, random_bytes, AT_NULL, 0]);
+    let sp = (strings_start - words.len() * size_of::<usize>()) & !0xf;
+    user_space
+        .prepare_user_access(sp, top - sp, AccessType::Store)
+        .map_err(|_| ElfError::OutOfMemory)?;
+    for (i, word) in words.iter().enumerate() {
+        user_space.write_bytes(sp + i * size_of::<usize>(), &word.to_le_bytes());
+    }
+    for (string, &va) in argv.iter().chain(envp).zip(&addrs) {
+        user_space.write_bytes(va, string.as_bytes());
+        user_space.write_bytes(va + string.len(), &[0]);
+    }
+    for i in 0..2 {
+        user_space.write_bytes(random_bytes + i * 8, &(random() as u64).to_le_bytes());
+    }
+    return Ok(sp);
+}
+
+// return user space, user stack pointer, heap start address, and entry point of elf, which is the entry of
+// the interpreter if the elf has one. The interpreter is found by the file name in its path, and it links
+// the program itself; the kernel only relocates a position independent program that has no interpreter.
+// position independent files are loaded at random bases, and the stack and the heap are moved by random
+// offsets. a malformed elf is rejected before anything is mapped.
+pub fn user_space_from_elf(elf_data: &[u8]) -> Result<(AddressSpace, VirtAddr, VirtAddr, usize), ElfError> {
+    return user_space_from_elf_with_args(elf_data, &[], &[]);
+}
+
+// as user_space_from_elf, and argv and envp are copied onto the initial stack
+pub fn user_space_from_elf_with_args(
+    elf_data: &[u8],
+    argv: &[String],
+    envp: &[String],
+) -> Result<(AddressSpace, VirtAddr, VirtAddr, usize), ElfError> {
+    return load_user_space(elf_data, argv, envp, &|name| open_app_file(name));
+}
+
+fn load_user_space<'a>(
+    elf_data: &'a [u8],
+    argv: &[String],
+    envp: &[String],
+    open: &dyn Fn(&str) -> Option<&'a [u8]>,
+) -> Result<(AddressSpace, VirtAddr, VirtAddr, usize), ElfError> {
+    let program = ElfImage::new(elf_data, PIE_BASE + random_offset(ASLR_LOAD_BITS))?;
+    let interpreter = match program.interpreter {
+        Some(path) => {
+            let name = path.rsplit('/').next().unwrap();
+            let data = open(name).ok_or(ElfError::BadInterpreter)?;
+            let interpreter = ElfImage::new(data, INTERP_BASE + random_offset(ASLR_LOAD_BITS))?;
+            if interpreter.base == 0 || interpreter.interpreter.is_some() {
+                return Err(ElfError::BadInterpreter);
+            }
+            if interpreter.segments.iter().any(|segment| overlaps(&program.segments, segment)) {
+                return Err(ElfError::BadSegment);
+            }
+            Some(interpreter)
+        }
+        None => None,
+    };
+    let images = [Some(&program), interpreter.as_ref()];
+    let segments = || images.iter().flatten().flat_map(|image| image.segments.iter());
+
+    // the stack and the heap are placed above the segments
+    let max_end_vpn = segments().map(|segment| segment.end.to_up_vpn()).max().unwrap();
+    let guard_start: VirtAddr = max_end_vpn.into();
+    // leave a guard gap and the room for the stack to grow above elf segments
+    let user_stack_end = guard_start.0 + USER_STACK_GUARD_SIZE + USER_STACK_LIMIT + random_offset(ASLR_STACK_BITS);
+    let user_heap_start = user_stack_end + random_offset(ASLR_HEAP_BITS);
+    if user_heap_start > USER_SPACE_END {
+        return Err(ElfError::BadSegment);
+    }
+    let (user_stack_end, user_heap_start) = (VirtAddr::from(user_stack_end), VirtAddr::from(user_heap_start));
+
     // println!("start build a user space!");
-    let mut user_space = AddressSpace::new();
+    let mut user_space = AddressSpace::new()?;
 
     //map trampoline to the highest page
-    user_space.map_trampoline();
-
-    // map program headers of elf, with U flag
-    let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
-    let elf_header = elf.header;
-    let magic = elf_header.pt1.magic;
-    assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!"); // check magic number to ensure it's a valid elf file
-
-    // add sections
-    let program_header_cnt = elf_header.pt2.ph_count();
-    let mut max_end_vpn = VPN(0);
-    for i in 0..program_header_cnt {
-        let ph = elf.program_header(i).unwrap();
-        if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
-            // this section should be loaded into memory
-            let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
-            let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
-            let mut map_perm = SectionPermisson::U;
-            let ph_flags = ph.flags();
-            if ph_flags.is_read() {
-                map_perm |= SectionPermisson::R;
-            }
-            if ph_flags.is_write() {
-                map_perm |= SectionPermisson::W;
-            }
-            if ph_flags.is_execute() {
-                map_perm |= SectionPermisson::X;
-            }
-
-            max_end_vpn = end_va.to_up_vpn();
-            user_space.add_section(
-                start_va,
-                end_va,
-                map_perm,
-                MapType::Framed,
-                Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-            )
-        }
-    }
-
-    // add guard page
-    let guard_start: VirtAddr = max_end_vpn.into();
-    let user_stack_start = VirtAddr::from(guard_start.0 + PAGE_SIZE);
+    user_space.map_trampoline()?;
+
+    // map the segments with U flag
+    for segment in segments() {
+        user_space.add_section(segment.start, segment.end, segment.permission, MapType::Framed, Some(segment.data))?;
+    }
+
+    let first = interpreter.as_ref().unwrap_or(&program); // the image the process starts in
+    relocate(&user_space, &first.segments, first.dynamic, first.base)?;
 
-    // add stack
-    let user_stack_end = VirtAddr::from(user_stack_start.0 + USER_STACK_SIZE);
+    // add stack, it grows downward on page faults
+    let user_stack_start = VirtAddr::from(user_stack_end.0 - USER_STACK_SIZE);
+    user_space.user_stack = Some(UserStack {
+        top: user_stack_end.to_down_vpn(),
+        limit: USER_STACK_LIMIT,
+    });
     // println!("add stack");
     user_space.add_section(
         user_stack_start,
         user_stack_end,
         SectionPermisson::U | SectionPermisson::R | SectionPermisson::W,
-        MapType::Framed,
+        MapType::Lazy,
         None,
-    );
+    )?;
+
+    // tell the interpreter where the program is
+    let mut auxv = Vec::new();
+    if let Some(phdr) = program.phdr() {
+        auxv.push((AT_PHDR, phdr));
+    }
+    auxv.push((AT_PHENT, program.elf.header.pt2.ph_entry_size() as usize));
+    auxv.push((AT_PHNUM, program.elf.header.pt2.ph_count() as usize));
+    auxv.push((AT_PAGESZ, PAGE_SIZE));
+    auxv.push((AT_BASE, interpreter.as_ref().map_or(0, |interpreter| interpreter.base)));
+    auxv.push((AT_ENTRY, program.entry));
+    let user_sp = push_initial_stack(&mut user_space, user_stack_end.0, argv, envp, &auxv)?;
 
     // add heap
-    let user_heap_start = user_stack_end;
     let user_heap_end = user_heap_start;
     // println!("add heap");
     user_space.add_section(
         user_heap_start,
         user_heap_end,
         SectionPermisson::U | SectionPermisson::R | SectionPermisson::W,
-        MapType::Framed,
+        MapType::Lazy,
         None,
-    );
+    )?;
 
     // add trap context
     // println!("add trap context");
-    user_space.add_trap_context();
+    user_space.add_trap_context()?;
 
-    return (
+    return Ok((
         user_space,
-        user_stack_end,
-        elf.header.pt2.entry_point() as usize,
-    );
+        VirtAddr::from(user_sp),
+        user_heap_start,
+        first.entry,
+    ));
 }
 
-pub fn copy_address_space(parent_address_space: &AddressSpace) -> AddressSpace {
-    let mut address_space = AddressSpace::new();
-    address_space.map_trampoline();
+// the parent's writable pages become copy-on-write, so the parent address space is modified too.
+// If frames run out, the parent keeps working, its pages are made writable again on the next store.
+pub fn copy_address_space(parent_address_space: &mut AddressSpace) -> Result<AddressSpace, OutOfMemory> {
+    let mut address_space = AddressSpace::new()?;
+    address_space.user_stack = parent_address_space.user_stack;
+    address_space.limits = parent_address_space.limits;
+    address_space.map_trampoline()?;
     // println!("finish map trampoline");
+    let mut result = Ok(());
     for section in parent_address_space.sections.iter() {
-        let new_section = copy_section(section, &mut address_space);
-        address_space.sections.push(new_section);
+        match copy_section(
+            section,
+            &mut parent_address_space.page_table,
+            &mut address_space,
+        ) {
+            Ok(new_section) => address_space.sections.push(new_section),
+            Err(err) => {
+                result = Err(err);
+                break;
+            }
+        }
+    }
+    // writable pages of the parent have become read-only
+    parent_address_space.flush_tlb();
+    // the child is charged for the frames it shares with the parent
+    if result.is_ok() && !address_space.charge(0, 0) {
+        result = Err(OutOfMemory);
     }
-    return address_space;
+    return result.map(|_| address_space);
 }
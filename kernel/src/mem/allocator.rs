@@ -1,5 +1,13 @@
-use allocator::GlobalBuddyAllocator;
+use allocator::{arc_layout, GlobalBuddyAllocator, HeapStats, PageSource, SizeClassCache, ObjectCache};
 use crate::config::*;
+use core::cmp::max;
+use crate::mem::frame_allocator::{FrameAllocator, FrameTracker, FRAME_ALLOCATOR};
+use crate::process::task_manager::TaskControlBlock;
+use crate::mem::page_table::PhyAddr;
+use crate::mem::address_space::Section;
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::{align_of, size_of};
+use core::sync::atomic::{AtomicBool, Ordering};
 
 // the memory space for kernel heap, in .bss section
 static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
@@ -7,9 +15,41 @@ static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 //implement global allocator traits to allow use of alloc crate
 #[global_allocator]
 static KERNEL_HEAP_ALLOCATOR: GlobalBuddyAllocator = unsafe {
-    GlobalBuddyAllocator::new(KERNEL_HEAP_SIZE, KERNEL_HEAP_GRANULARITY)
+    GlobalBuddyAllocator::new(KERNEL_HEAP_MAX_SIZE, KERNEL_HEAP_GRANULARITY)
 };
 
+// slabs take frames directly, once the frame allocator is initialized
+static SLABS_FROM_FRAMES: AtomicBool = AtomicBool::new(false);
+
+const SLAB_PAGE_SOURCE: PageSource = PageSource {
+    alloc: alloc_slab,
+    dealloc: dealloc_slab,
+};
+
+// size classes fitted to hot kernel objects, so that they are not rounded up to a power of 2 by the buddy
+// allocator. They are named after the objects they are sized for, but allocations are routed by layout only: any
+// allocation goes to the smallest class it fits in, so other objects of similar sizes share them.
+static FRAME_TRACKER_SIZED_CLASS: SizeClassCache =
+    SizeClassCache::new("Arc<FrameTracker>-sized", arc_layout::<FrameTracker>(), SLAB_PAGE_SOURCE);
+// BTreeMap nodes of 8-byte keys and values, as in the v2p maps of sections and the ready queue
+static BTREE_LEAF_SIZED_CLASS: SizeClassCache = SizeClassCache::new("BTreeMap leaf-sized", btree_node_layout(0), SLAB_PAGE_SOURCE);
+static BTREE_INTERNAL_SIZED_CLASS: SizeClassCache =
+    SizeClassCache::new("BTreeMap internal node-sized", btree_node_layout(12), SLAB_PAGE_SOURCE);
+// sections live in the Vec of their address space, which starts with room for 4 of them
+static SECTIONS_SIZED_CLASS: SizeClassCache = SizeClassCache::new(
+    "Vec<Section>-sized",
+    unsafe { Layout::from_size_align_unchecked(4 * size_of::<Section>(), align_of::<Section>()) },
+    SLAB_PAGE_SOURCE,
+);
+static TASK_SIZED_CLASS: SizeClassCache =
+    SizeClassCache::new("Arc<TaskControlBlock>-sized", arc_layout::<TaskControlBlock>(), SLAB_PAGE_SOURCE);
+
+// a node of BTreeMap<K, V> with 8-byte K and V holds a parent link, 11 keys and values, two u16 padded to 8 bytes,
+// and the edges if it is an internal node
+const fn btree_node_layout(edges: usize) -> Layout {
+    unsafe { Layout::from_size_align_unchecked(8 + 11 * 16 + 8 + edges * 8, 8) }
+}
+
 #[alloc_error_handler]
 /// panic when heap allocation error occurs
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
@@ -21,10 +61,97 @@ pub fn init_heap_allocator() {
     println!("start init kernel heap!");
     unsafe {
         println!("heap start: {:#x}, end: {:#x}", HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE + HEAP_SPACE.as_ptr() as usize);
+        KERNEL_HEAP_ALLOCATOR.add_cache(&FRAME_TRACKER_SIZED_CLASS);
+        KERNEL_HEAP_ALLOCATOR.add_cache(&BTREE_LEAF_SIZED_CLASS);
+        KERNEL_HEAP_ALLOCATOR.add_cache(&BTREE_INTERNAL_SIZED_CLASS);
+        KERNEL_HEAP_ALLOCATOR.add_cache(&SECTIONS_SIZED_CLASS);
+        KERNEL_HEAP_ALLOCATOR.add_cache(&TASK_SIZED_CLASS);
         KERNEL_HEAP_ALLOCATOR.init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
     }
 }
 
+// let the kernel heap grow with frames, it should be called after the frame allocator is initialized
+pub fn init_heap_growth() {
+    KERNEL_HEAP_ALLOCATOR.set_grow_handler(grow_heap);
+    SLABS_FROM_FRAMES.store(true, Ordering::Relaxed);
+}
+
+pub fn heap_stats() -> HeapStats {
+    KERNEL_HEAP_ALLOCATOR.stats()
+}
+
+#[cfg(feature = "debug-heap")]
+// print the corruption the debug heap has found, and the outstanding allocations to find it among.
+// it is called when the kernel panics
+pub fn print_heap_diagnostics() {
+    if let Some(corruption) = KERNEL_HEAP_ALLOCATOR.check() {
+        println!("kernel heap corruption: {}", corruption);
+        dump_heap();
+    }
+}
+
+#[cfg(feature = "debug-heap")]
+// print the outstanding allocations of the kernel heap
+fn dump_heap() {
+    let mut cnt = 0;
+    let mut size = 0;
+    KERNEL_HEAP_ALLOCATOR.for_each_allocation(|addr, len| {
+        println!("  {:#x}: {} bytes", addr, len);
+        cnt += 1;
+        size += len;
+    });
+    println!("kernel heap: {} outstanding allocations, {} bytes", cnt, size);
+}
+
+pub fn print_slab_usage() {
+    KERNEL_HEAP_ALLOCATOR.for_each_cache(|stats| {
+        println!(
+            "size class {}: object size = {}, {} objects in {} slabs, {} objects per slab",
+            stats.name, stats.object_size, stats.objects, stats.slabs, stats.objects_per_slab
+        );
+    });
+}
+
+fn slab_layout(order: usize) -> Layout {
+    Layout::from_size_align(PAGE_SIZE << order, PAGE_SIZE << order).unwrap()
+}
+
+// before the frame allocator is initialized, slabs are taken from the initial heap. The heap does not grow
+// until then, so these slabs are all in HEAP_SPACE.
+fn alloc_slab(order: usize) -> Option<usize> {
+    if !SLABS_FROM_FRAMES.load(Ordering::Relaxed) {
+        let ptr = unsafe { KERNEL_HEAP_ALLOCATOR.alloc(slab_layout(order)) };
+        return (!ptr.is_null()).then_some(ptr as usize);
+    }
+    let ppn = FRAME_ALLOCATOR.exclusive_access().alloc_contiguous(order)?;
+    return Some(PhyAddr::from(ppn).0);
+}
+
+fn dealloc_slab(addr: usize, order: usize) {
+    let heap_space = unsafe { HEAP_SPACE.as_ptr() as usize..HEAP_SPACE.as_ptr() as usize + KERNEL_HEAP_SIZE };
+    if heap_space.contains(&addr) {
+        unsafe { KERNEL_HEAP_ALLOCATOR.dealloc(addr as *mut u8, slab_layout(order)) };
+        return;
+    }
+    FRAME_ALLOCATOR.exclusive_access().dealloc_contiguous(PhyAddr(addr).to_down_ppn(), order);
+}
+
+// take a contiguous block of frames for the kernel heap, the frames are never given back.
+// the frame allocator does not use the heap, so it is safe to be called in the middle of a heap allocation.
+fn grow_heap(min_size: usize, max_size: usize) -> Option<(usize, usize)> {
+    let mut size = max(min_size, KERNEL_HEAP_GROW_SIZE);
+    if size > max_size {
+        size = max(min_size, PAGE_SIZE);
+    }
+    if size > max_size {
+        return None;
+    }
+    let order = (size / PAGE_SIZE).trailing_zeros() as usize;
+    let ppn = FRAME_ALLOCATOR.exclusive_access().alloc_contiguous(order)?;
+    println!("kernel heap grows by {:#x}, start: {:#x}", size, PhyAddr::from(ppn).0);
+    return Some((PhyAddr::from(ppn).0, size));
+}
+
 #[allow(unused)]
 pub fn heap_test() {
     println!("start test heap!");
@@ -59,4 +186,73 @@ pub fn heap_test() {
     println!("heap_test passed!");
 }
 
+#[allow(unused)]
+/// a test for heap growth, the vector is too large for the initial heap
+pub fn heap_grow_test() {
+    use alloc::vec::Vec;
+    let total = heap_stats().total;
+    let mut v: Vec<u8> = Vec::with_capacity(KERNEL_HEAP_SIZE);
+    v.resize(KERNEL_HEAP_SIZE, 1);
+    assert!(v.iter().all(|&x| x == 1));
+    assert!(heap_stats().total > total);
+    assert!(heap_stats().total <= KERNEL_HEAP_MAX_SIZE);
+    drop(v);
+    println!("heap_grow_test passed!");
+}
 
+
+
+#[allow(unused)]
+/// a test for slab caches
+pub fn slab_test() {
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use crate::mem::frame_allocator::alloc_frame;
+    use alloc::boxed::Box;
+    use alloc::collections::BTreeMap;
+    // hot objects go to their size classes
+    let objects = FRAME_TRACKER_SIZED_CLASS.stats().objects;
+    let frame = Arc::new(alloc_frame().unwrap());
+    assert_eq!(FRAME_TRACKER_SIZED_CLASS.stats().objects, objects + 1);
+    // so do other objects that fit in them
+    let small = Box::new([0u8; 20]);
+    assert_eq!(FRAME_TRACKER_SIZED_CLASS.stats().objects, objects + 2);
+    drop(small);
+    drop(frame);
+    assert_eq!(FRAME_TRACKER_SIZED_CLASS.stats().objects, objects);
+    let objects = BTREE_LEAF_SIZED_CLASS.stats().objects;
+    let mut map = BTreeMap::new();
+    map.insert(0usize, Arc::new(alloc_frame().unwrap()));
+    assert_eq!(BTREE_LEAF_SIZED_CLASS.stats().objects, objects + 1);
+    let objects = BTREE_INTERNAL_SIZED_CLASS.stats().objects;
+    for i in 1..12 {
+        map.insert(i, Arc::new(alloc_frame().unwrap()));
+    }
+    assert_eq!(BTREE_INTERNAL_SIZED_CLASS.stats().objects, objects + 1);
+    drop(map);
+    assert_eq!(BTREE_INTERNAL_SIZED_CLASS.stats().objects, objects);
+
+    // a cache takes slabs as it grows, and gives them back when they are empty
+    static TEST_CACHE: SizeClassCache = SizeClassCache::new("test", Layout::new::<[usize; 100]>(), SLAB_PAGE_SOURCE);
+    let free_frame_cnt = FRAME_ALLOCATOR.exclusive_access().free_frame_cnt();
+    let objects_per_slab = TEST_CACHE.stats().objects_per_slab;
+    let objects: Vec<_> = (0..objects_per_slab * 2 + 1)
+        .map(|i| unsafe {
+            let object = TEST_CACHE.alloc() as *mut [usize; 100];
+            assert!(!object.is_null());
+            object.write([i; 100]);
+            object
+        })
+        .collect();
+    assert_eq!(TEST_CACHE.stats().slabs, 3);
+    for (i, &object) in objects.iter().enumerate() {
+        assert!(unsafe { &*object }.iter().all(|&x| x == i));
+    }
+    for object in objects {
+        unsafe { TEST_CACHE.dealloc(object as *mut u8) };
+    }
+    assert_eq!(TEST_CACHE.stats().slabs, 0);
+    assert_eq!(FRAME_ALLOCATOR.exclusive_access().free_frame_cnt(), free_frame_cnt);
+    print_slab_usage();
+    println!("slab_test passed!");
+}
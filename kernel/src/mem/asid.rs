@@ -0,0 +1,137 @@
+// hardware address space identifiers, kept in satp to tag TLB entries.
+// kernel space always uses ASID 0, user address spaces take ASIDs from 1 to max_asid.
+// ASIDs are not recycled one by one. When they run out, a new generation starts: the whole TLB is flushed, and
+// every user address space takes a new ASID the next time it is activated.
+
+use core::arch::asm;
+use sync::UPSafeCell;
+
+const SATP_ASID_SHIFT: usize = 44;
+const SATP_ASID_MASK: usize = 0xffff; // sv39 reserves 16 bits for ASID, the hardware may implement fewer of them
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Asid {
+    value: usize,
+    generation: usize, // generation 0 never expires, it is used by kernel space
+}
+
+impl Asid {
+    pub const KERNEL: Asid = Asid { value: 0, generation: 0 };
+
+    pub fn value(&self) -> usize {
+        self.value
+    }
+
+    // the ASID field of satp
+    pub fn satp_bits(&self) -> usize {
+        self.value << SATP_ASID_SHIFT
+    }
+}
+
+pub struct AsidAllocator {
+    max_asid: usize, // 0 if the hardware does not support ASID, then every address space shares ASID 0
+    generation: usize,
+    next: usize,
+}
+
+impl AsidAllocator {
+    pub fn new() -> Self {
+        Self {
+            max_asid: 0,
+            generation: 1,
+            next: 1,
+        }
+    }
+
+    pub fn init(&mut self, max_asid: usize) {
+        self.max_asid = max_asid;
+        println!("AsidAllocator: {} ASIDs for user address spaces", max_asid);
+    }
+
+    pub fn alloc(&mut self) -> Asid {
+        if self.max_asid == 0 {
+            return Asid { value: 0, generation: self.generation };
+        }
+        if self.next > self.max_asid {
+            // start a new generation, ASIDs of the old generation are dropped with their TLB entries
+            self.generation += 1;
+            self.next = 1;
+            flush_all();
+        }
+        let asid = Asid { value: self.next, generation: self.generation };
+        self.next += 1;
+        return asid;
+    }
+
+    // whether the asid can still be used, an expired one must be replaced before activating its address space
+    pub fn is_current(&self, asid: &Asid) -> bool {
+        asid.generation == 0 || asid.generation == self.generation
+    }
+}
+
+lazy_static! {
+    pub static ref ASID_ALLOCATOR: UPSafeCell<AsidAllocator> = unsafe { UPSafeCell::new(AsidAllocator::new()) };
+}
+
+// find out how many ASID bits the hardware implements: write all ones to the ASID field of satp, and read it back.
+// it should be called after kernel space is activated.
+pub fn init_asid_allocator() {
+    let probed: usize;
+    unsafe {
+        asm!(
+            "csrr {satp}, satp",
+            "or {probed}, {satp}, {ones}",
+            "csrw satp, {probed}",
+            "csrr {probed}, satp",
+            "csrw satp, {satp}",
+            "sfence.vma",
+            satp = out(reg) _,
+            probed = out(reg) probed,
+            ones = in(reg) SATP_ASID_MASK << SATP_ASID_SHIFT,
+        );
+    }
+    let max_asid = (probed >> SATP_ASID_SHIFT) & SATP_ASID_MASK;
+    ASID_ALLOCATOR.exclusive_access().init(max_asid);
+}
+
+// whether the hardware supports ASID. If not, the TLB has to be flushed on every switch of address space.
+pub fn asid_supported() -> bool {
+    ASID_ALLOCATOR.exclusive_access().max_asid > 0
+}
+
+pub fn flush_all() {
+    unsafe {
+        asm!("sfence.vma");
+    }
+}
+
+// flush the TLB entries of the given asid
+pub fn flush_asid(asid: Asid) {
+    unsafe {
+        asm!("sfence.vma zero, {0}", in(reg) asid.value());
+    }
+}
+
+// flush the TLB entry of va in the given asid
+pub fn flush_page(asid: Asid, va: usize) {
+    unsafe {
+        asm!("sfence.vma {0}, {1}", in(reg) va, in(reg) asid.value());
+    }
+}
+
+#[allow(unused)]
+/// a simple test for ASID rollover
+pub fn asid_test() {
+    let mut allocator = AsidAllocator::new();
+    allocator.init(3);
+    let first: [Asid; 3] = core::array::from_fn(|_| allocator.alloc());
+    assert_eq!(first.map(|asid| asid.value()), [1, 2, 3]);
+    assert!(first.iter().all(|asid| allocator.is_current(asid)));
+    // ASIDs run out, the old generation expires
+    let asid = allocator.alloc();
+    assert_eq!(asid.value(), 1);
+    assert!(allocator.is_current(&asid));
+    assert!(first.iter().all(|asid| !allocator.is_current(asid)));
+    assert!(allocator.is_current(&Asid::KERNEL));
+    println!("asid_test passed!");
+}
@@ -2,68 +2,166 @@ use super::page_table::*;
 use crate::config::*;
 use sync::UPSafeCell;
 use alloc::vec::Vec;
+use core::cmp::min;
 use core::fmt::{self, Debug, Formatter};
+use errno::Errno;
 use lazy_static::lazy_static;
 
+// physical frames have run out
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct OutOfMemory;
+
+impl From<OutOfMemory> for Errno {
+    fn from(_: OutOfMemory) -> Self {
+        Errno::ENOMEM
+    }
+}
+
 pub trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PPN>;
     fn dealloc(&mut self, ppn: PPN);
+    // allocate 2^order physically contiguous frames, the first ppn is aligned to 2^order
+    fn alloc_contiguous(&mut self, order: usize) -> Option<PPN>;
+    fn dealloc_contiguous(&mut self, ppn: PPN, order: usize);
+}
+
+const NIL: usize = usize::MAX;
+const FREE: u8 = 1 << 6; // the first frame of a free block, the low bits are the order of the block
+const ALLOCATED: u8 = 1 << 7; // the first frame of an allocated block, the low bits are the order of the block
+
+// a free block is linked into its free list through its first frame
+struct FreeBlock {
+    prev: usize,
+    next: usize,
 }
 
-pub struct StackFrameAllocator {
+fn free_block(ppn: usize) -> &'static mut FreeBlock {
+    unsafe { &mut *((ppn << PAGE_SIZE_BITS) as *mut FreeBlock) }
+}
+
+// a buddy allocator of frames. free_list[k] links free blocks of 2^k frames, and the state of each frame is kept
+// in a byte, so that the buddy of a block is found without searching, and no heap memory is used.
+pub struct BuddyFrameAllocator {
     start: PPN,
     end: PPN,
-    recycled: Vec<PPN>,
+    free_list: [usize; FRAME_ALLOCATOR_ORDER_SIZE], // the first ppn of the first free block, NIL if empty
+    state: &'static mut [u8], // state of frames in [start, end), stored in the first frames of the range
+    free_frame_cnt: usize,
 }
 
-impl FrameAllocator for StackFrameAllocator {
+impl FrameAllocator for BuddyFrameAllocator {
     fn new() -> Self {
         Self {
             start: PPN::new(),
             end: PPN::new(),
-            recycled: Vec::new(),
+            free_list: [NIL; FRAME_ALLOCATOR_ORDER_SIZE],
+            state: &mut [],
+            free_frame_cnt: 0,
         }
     }
 
     fn alloc(&mut self) -> Option<PPN> {
-        let result: Option<PPN>;
-        if let Some(ppn) = self.recycled.pop() {
-            result = Some(ppn);
-        } else {
-            if self.start == self.end {
-                println!("FrameAllocator: no available frame!");
-                return None;
-            } else {
-                result = Some(PPN(self.start.0));
-                self.start.0 += 1;
-            }
+        self.alloc_contiguous(0)
+    }
+
+    fn dealloc(&mut self, ppn: PPN) {
+        self.dealloc_contiguous(ppn, 0)
+    }
+
+    fn alloc_contiguous(&mut self, order: usize) -> Option<PPN> {
+        if order >= FRAME_ALLOCATOR_ORDER_SIZE {
+            return None;
+        }
+        // find the smallest free block that is large enough. Running out is not an error here, callers handle it.
+        let target_order = (order..FRAME_ALLOCATOR_ORDER_SIZE).find(|&k| self.free_list[k] != NIL)?;
+        let ppn = self.free_list[target_order];
+        self.remove(ppn, target_order);
+        // split the block, the upper halves go back to the free lists
+        for k in (order..target_order).rev() {
+            self.push(ppn + (1 << k), k);
         }
-        let ppn = result.unwrap();
-        //clear the page
-        let page = ppn.get_page();
-        for i in 0..4096 {
-            page[i] = 0;
+        self.state[ppn - self.start.0] = ALLOCATED | order as u8;
+        self.free_frame_cnt -= 1 << order;
+        //clear the pages
+        for i in 0..(1 << order) {
+            PPN(ppn + i).get_page().fill(0);
         }
-        return result;
+        return Some(PPN(ppn));
     }
 
-    fn dealloc(&mut self, ppn: PPN) {
-        if ppn >= self.start || self.recycled.iter().find(|&x| *x == ppn).is_some() {
-            panic!("Frame ppn = {:#x} has not been allocated!", ppn.0);
+    fn dealloc_contiguous(&mut self, ppn: PPN, order: usize) {
+        let mut ppn = ppn.0;
+        if ppn < self.start.0 || ppn >= self.end.0 || self.state[ppn - self.start.0] != ALLOCATED | order as u8 {
+            panic!("Frame ppn = {:#x}, order = {} has not been allocated!", ppn, order);
+        }
+        self.state[ppn - self.start.0] = 0;
+        self.free_frame_cnt += 1 << order;
+        // merge with the buddy as long as it is free
+        let mut order = order;
+        while order + 1 < FRAME_ALLOCATOR_ORDER_SIZE {
+            let buddy = ppn ^ (1 << order);
+            if buddy < self.start.0 || buddy >= self.end.0 || self.state[buddy - self.start.0] != FREE | order as u8 {
+                break;
+            }
+            self.remove(buddy, order);
+            ppn = min(ppn, buddy);
+            order += 1;
         }
-        ppn.get_page().iter_mut().for_each(|x| *x = 0); //clear the page
-        self.recycled.push(ppn);
+        self.push(ppn, order);
     }
 }
 
-impl StackFrameAllocator {
+impl BuddyFrameAllocator {
     pub fn init(&mut self, start: PPN, end: PPN) {
         self.start = start;
         self.end = end;
+        // the state array takes the first frames of the range
+        let frame_cnt = end.0 - start.0;
+        let state_frame_cnt = (frame_cnt + PAGE_SIZE - 1) / PAGE_SIZE;
+        self.state = unsafe { core::slice::from_raw_parts_mut(PhyAddr::from(start).0 as *mut u8, frame_cnt) };
+        self.state.fill(0);
+        // cut the rest into blocks, each of them is aligned to its size
+        let mut ppn = start.0 + state_frame_cnt;
+        while ppn < end.0 {
+            let mut order = min(ppn.trailing_zeros() as usize, FRAME_ALLOCATOR_ORDER_SIZE - 1);
+            while ppn + (1 << order) > end.0 {
+                order -= 1;
+            }
+            self.push(ppn, order);
+            self.free_frame_cnt += 1 << order;
+            ppn += 1 << order;
+        }
         println!(
             "FrameAllocator: start ppn:{:#x}, end ppn:{:#x}, total frame number = {}",
-            self.start.0,self.end.0,self.end.0 - self.start.0)
+            self.start.0,self.end.0,self.free_frame_cnt)
+    }
+
+    pub fn free_frame_cnt(&self) -> usize {
+        self.free_frame_cnt
+    }
+
+    fn push(&mut self, ppn: usize, order: usize) {
+        let head = self.free_list[order];
+        *free_block(ppn) = FreeBlock { prev: NIL, next: head };
+        if head != NIL {
+            free_block(head).prev = ppn;
+        }
+        self.free_list[order] = ppn;
+        self.state[ppn - self.start.0] = FREE | order as u8;
+    }
+
+    fn remove(&mut self, ppn: usize, order: usize) {
+        let FreeBlock { prev, next } = *free_block(ppn);
+        if prev != NIL {
+            free_block(prev).next = next;
+        } else {
+            self.free_list[order] = next;
+        }
+        if next != NIL {
+            free_block(next).prev = prev;
+        }
+        self.state[ppn - self.start.0] = 0;
     }
 }
 
@@ -85,6 +183,28 @@ impl Drop for FrameTracker {
     }
 }
 
+// tracks 2^order physically contiguous frames starting from ppn
+pub struct ContiguousFrameTracker {
+    pub ppn: PPN,
+    pub order: usize,
+}
+
+impl ContiguousFrameTracker {
+    pub fn new(ppn: PPN, order: usize) -> Self {
+        Self { ppn, order }
+    }
+
+    pub fn frame_cnt(&self) -> usize {
+        1 << self.order
+    }
+}
+
+impl Drop for ContiguousFrameTracker {
+    fn drop(&mut self) {
+        FRAME_ALLOCATOR.exclusive_access().dealloc_contiguous(self.ppn, self.order);
+    }
+}
+
 impl Debug for FrameTracker {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.write_fmt(format_args!("FrameTracker:PPN={:#x}", self.ppn.0))
@@ -92,8 +212,8 @@ impl Debug for FrameTracker {
 }
 
 lazy_static! {
-    pub static ref FRAME_ALLOCATOR: UPSafeCell<StackFrameAllocator> =
-        unsafe { UPSafeCell::new(StackFrameAllocator::new()) };
+    pub static ref FRAME_ALLOCATOR: UPSafeCell<BuddyFrameAllocator> =
+        unsafe { UPSafeCell::new(BuddyFrameAllocator::new()) };
 }
 
 pub fn init_frame_allocator() {
@@ -112,6 +232,14 @@ pub fn alloc_frame() -> Option<FrameTracker> {
         .map(|ppn| FrameTracker::new(ppn))
 }
 
+// allocate 2^order physically contiguous frames
+pub fn alloc_contiguous_frames(order: usize) -> Option<ContiguousFrameTracker> {
+    FRAME_ALLOCATOR
+        .exclusive_access()
+        .alloc_contiguous(order)
+        .map(|ppn| ContiguousFrameTracker::new(ppn, order))
+}
+
 #[allow(unused)]
 /// a simple test for frame allocator
 pub fn frame_allocator_test() {
@@ -127,5 +255,27 @@ pub fn frame_allocator_test() {
         v.push(frame);
     }
     drop(v);
+
+    let free_frame_cnt = FRAME_ALLOCATOR.exclusive_access().free_frame_cnt();
+    let first = alloc_contiguous_frames(5).unwrap().ppn;
+    // contiguous blocks are aligned to their size and do not overlap
+    let mut blocks: Vec<ContiguousFrameTracker> = Vec::new();
+    for order in [3, 0, 5, 1, 3] {
+        let block = alloc_contiguous_frames(order).unwrap();
+        assert_eq!(block.ppn.0 & (block.frame_cnt() - 1), 0);
+        for other in blocks.iter() {
+            assert!(
+                block.ppn.0 + block.frame_cnt() <= other.ppn.0 || other.ppn.0 + other.frame_cnt() <= block.ppn.0
+            );
+        }
+        blocks.push(block);
+    }
+    // free them in reverse, so that each block is merged back into the one it was split from
+    while blocks.pop().is_some() {}
+    assert_eq!(FRAME_ALLOCATOR.exclusive_access().free_frame_cnt(), free_frame_cnt);
+    // freed buddies are merged, so the same block can be allocated again
+    let block = alloc_contiguous_frames(5).unwrap();
+    assert!(block.ppn == first);
+    drop(block);
     println!("frame_allocator_test passed!");
 }
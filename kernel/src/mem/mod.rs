@@ -2,10 +2,18 @@ pub mod allocator;
 pub mod page_table;
 pub mod frame_allocator;
 pub mod address_space;
+pub mod asid;
+pub mod shm;
+pub mod page_cache;
+pub mod swap;
+pub mod user_ptr;
 
 use frame_allocator::{frame_allocator_test,init_frame_allocator};
-use address_space::{KERNEL_SPACE,test_space};
-use allocator::{init_heap_allocator,heap_test};
+use address_space::{KERNEL_SPACE,test_space,elf_test,swap_space_test};
+use asid::{asid_test,init_asid_allocator};
+use swap::{init_swap,swap_test};
+use user_ptr::user_ptr_test;
+use allocator::{init_heap_allocator,init_heap_growth,heap_test,heap_grow_test,slab_test};
 
 pub fn init() {
 
@@ -17,8 +25,28 @@ pub fn init() {
 
     frame_allocator_test();
 
+    init_heap_growth();
+
+    heap_grow_test();
+
+    slab_test();
+
     KERNEL_SPACE.exclusive_access().activate(); // use SV39 mode and set the root ppn into satp register
 
+    init_asid_allocator();
+
+    asid_test();
+
     test_space();
 
+    elf_test();
+
+    user_ptr_test();
+
+    init_swap();
+
+    swap_test();
+
+    swap_space_test();
+
 }
\ No newline at end of file
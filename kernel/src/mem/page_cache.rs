@@ -0,0 +1,82 @@
+// the page cache of files in the app image. A file mapped into address spaces is cached page by page, the pages
+// are read on the first page fault and shared by all the mappings. Shared mappings write their pages back to the
+// file, private ones copy a page on the first write. A file leaves the cache when its last mapping is gone.
+// The app image is read only, so the pages written back are kept in WRITTEN_PAGES, which the cache reads them from
+// later. exec loads programs from the app image, so it does not see them.
+
+use super::frame_allocator::FrameTracker;
+use crate::config::PAGE_SIZE;
+use crate::process::loader::open_app_file;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::cmp::min;
+use sync::UPSafeCell;
+
+pub struct CachedFile {
+    name: String,
+    size: usize,
+    pages: UPSafeCell<BTreeMap<usize, Arc<FrameTracker>>>, // page index -> frame
+}
+
+impl CachedFile {
+    pub fn page_cnt(&self) -> usize {
+        return (self.size + PAGE_SIZE - 1) / PAGE_SIZE;
+    }
+
+    // the frame of page idx, if it has been read
+    pub fn page(&self, idx: usize) -> Option<Arc<FrameTracker>> {
+        return self.pages.exclusive_access().get(&idx).cloned();
+    }
+
+    // read page idx of the file into frame, which caches it from now on. The rest of the last page is zeroed.
+    pub fn read_page(&self, idx: usize, frame: FrameTracker) -> Arc<FrameTracker> {
+        assert!(idx < self.page_cnt());
+        let start = idx * PAGE_SIZE;
+        let len = min(PAGE_SIZE, self.size - start);
+        let page = frame.ppn.get_page();
+        match WRITTEN_PAGES.exclusive_access().get(&self.name).and_then(|pages| pages.get(&idx)) {
+            Some(written) => page[..len].copy_from_slice(written),
+            None => page[..len].copy_from_slice(&open_app_file(&self.name).unwrap()[start..start + len]),
+        }
+        page[len..].fill(0);
+        let frame = Arc::new(frame);
+        self.pages.exclusive_access().insert(idx, Arc::clone(&frame));
+        return frame;
+    }
+
+    // write the cached page idx back to the file
+    pub fn write_back(&self, idx: usize) {
+        if let Some(frame) = self.page(idx) {
+            let start = idx * PAGE_SIZE;
+            let len = min(PAGE_SIZE, self.size - start);
+            let data = frame.ppn.get_page()[..len].to_vec();
+            WRITTEN_PAGES.exclusive_access().entry(self.name.clone()).or_default().insert(idx, data);
+        }
+    }
+}
+
+lazy_static! {
+    static ref CACHED_FILES: UPSafeCell<BTreeMap<String, Weak<CachedFile>>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+    // file name -> page index -> the data written back to the page
+    static ref WRITTEN_PAGES: UPSafeCell<BTreeMap<String, BTreeMap<usize, Vec<u8>>>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+// the cached file of name, it is added to the cache if it is not there. None if there is no such file.
+pub fn open_cached_file(name: &str) -> Option<Arc<CachedFile>> {
+    let mut files = CACHED_FILES.exclusive_access();
+    files.retain(|_, file| file.strong_count() > 0);
+    if let Some(file) = files.get(name).and_then(Weak::upgrade) {
+        return Some(file);
+    }
+    let file = Arc::new(CachedFile {
+        name: String::from(name),
+        size: open_app_file(name)?.len(),
+        pages: unsafe { UPSafeCell::new(BTreeMap::new()) },
+    });
+    files.insert(String::from(name), Arc::downgrade(&file));
+    return Some(file);
+}
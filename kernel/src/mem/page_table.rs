@@ -1,8 +1,8 @@
 use core::{iter::Step, usize};
 
-use super::frame_allocator::{alloc_frame, FrameTracker};
+use super::frame_allocator::{alloc_frame, FrameTracker, OutOfMemory};
 use crate::config::*;
-use alloc::{string::String, vec::Vec};
+use alloc::vec::Vec;
 use bitflags::bitflags;
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
@@ -140,6 +140,7 @@ bitflags! {
         const G = 1 << 5;
         const A = 1 << 6;
         const D = 1 << 7;
+        const COW = 1 << 8; // RSW bit, marks a read-only shared page that will be copied on write
     }
 }
 
@@ -164,6 +165,10 @@ impl PageTableEntry {
         return PPN(self.bits >> 10);
     }
 
+    pub fn flags(&self) -> PTEFlags {
+        return PTEFlags::from_bits_truncate(self.bits & ((1 << 10) - 1));
+    }
+
     pub fn is_valid(&self) -> bool {
         return self.bits & PTEFlags::V.bits != 0;
     }
@@ -175,8 +180,20 @@ impl PageTableEntry {
     pub fn is_executable(&self) -> bool {
         return self.bits & PTEFlags::X.bits != 0;
     }
+
+    pub fn is_cow(&self) -> bool {
+        return self.bits & PTEFlags::COW.bits != 0;
+    }
+
+    // a valid pte with any of R/W/X maps a page, otherwise it points to the next level of page table
+    pub fn is_leaf(&self) -> bool {
+        return self.is_valid() && self.flags().intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X);
+    }
 }
 
+// the number of 4 KiB pages mapped by a leaf pte on each level: 1 GiB, 2 MiB and 4 KiB pages
+const LEVEL_PAGE_CNT: [usize; 3] = [512 * 512, 512, 1];
+
 pub struct PageTable {
     root_ppn: PPN,
     //the frames of the nodes on page table tree. When a PageTable is dropped, all the frames will be recycled.
@@ -184,10 +201,10 @@ pub struct PageTable {
 }
 
 impl PageTable {
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self, OutOfMemory> {
         //allocate a frame for the root node
         let mut frames = Vec::new();
-        let root_frame = alloc_frame().unwrap();
+        let root_frame = alloc_frame().ok_or(OutOfMemory)?;
         let root_ppn = root_frame.ppn;
         // println!(
         //     "alloc frame {} at ppn: {:#x} for root",
@@ -195,7 +212,7 @@ impl PageTable {
         //     root_ppn.0
         // );
         frames.push(root_frame);
-        Self { root_ppn, frames }
+        Ok(Self { root_ppn, frames })
     }
 
     // switch to a page table specified by satp
@@ -209,12 +226,24 @@ impl PageTable {
         }
     }
 
+    // the number of frames of the page table tree
+    pub fn frame_cnt(&self) -> usize {
+        return self.frames.len();
+    }
+
     pub fn get_satp(&self) -> usize {
         return 8usize << 60 | self.root_ppn.0;
     }
 
     //find the pte of the given vpn, if a node on the page table tree dosen't exist, allocate a new frame for it.
+    //return None if frames run out.
     pub fn find_and_alloc_pte(&mut self, vpn: VPN) -> Option<&mut PageTableEntry> {
+        self.find_and_alloc_pte_at(vpn, 3)
+    }
+
+    //find the pte of the given vpn on the given level (1: 1 GiB page, 2: 2 MiB page, 3: 4 KiB page),
+    //if a node on the page table tree dosen't exist, allocate a new frame for it. return None if frames run out.
+    fn find_and_alloc_pte_at(&mut self, vpn: VPN, level: usize) -> Option<&mut PageTableEntry> {
         let idx3: usize = vpn.0 & 511;
         let idx2: usize = (vpn.0 >> 9) & 511;
         let idx1: usize = (vpn.0 >> 18) & 511;
@@ -225,7 +254,7 @@ impl PageTable {
         // );
         let mut ppn = self.root_ppn;
         let mut result: Option<&mut PageTableEntry> = None;
-        for i in 0..3 {
+        for i in 0..level {
             let pte = ppn.get_pte(idx[i]);
             // println!(
             //     "fine pte of offset {:#x} at page of ppn {:#x}, and valid = {}",
@@ -233,9 +262,14 @@ impl PageTable {
             //     ppn.0,
             //     pte.is_valid()
             // );
-            if !pte.is_valid() && i != 2 {
+            if i == level - 1 {
+                result = Some(pte);
+                break;
+            }
+            assert!(!pte.is_leaf(), "vpn {:#x} has been mapped by a megapage", vpn.0);
+            if !pte.is_valid() {
                 //allocate a new frame, and set the pte
-                let frame = alloc_frame().unwrap();
+                let frame = alloc_frame()?;
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
                 // println!(
                 //     "alloc frame {} at ppn: {:#x} for level {},valid = {}",
@@ -245,63 +279,117 @@ impl PageTable {
                 //     pte.is_valid()
                 // );
                 self.frames.push(frame);
-            }
-                if i == 2 {
-                result = Some(pte);
-                break;
             }
             ppn = pte.ppn();
         }
         return result;
     }
 
-    //find the pte of the given vpn, if a node on the page table tree dosen't exist, return None.
-    pub fn find_pte(&self, vpn: VPN) -> Option<&mut PageTableEntry> {
+    //find the leaf pte that maps vpn, and the number of pages it maps. If there is no megapage, the pte on the
+    //last level is returned even if it is invalid. if a node on the page table tree dosen't exist, return None.
+    fn find_leaf(&self, vpn: VPN) -> Option<(&mut PageTableEntry, usize)> {
         let idx3: usize = vpn.0 & 511;
         let idx2: usize = (vpn.0 >> 9) & 511;
         let idx1: usize = (vpn.0 >> 18) & 511;
         let idx: [usize; 3] = [idx1, idx2, idx3];
         // println!("find pte for vpn: {:#x}, idx: [{:#x}, {:#x}, {:#x}]", vpn.0, idx1, idx2, idx3);
         let mut ppn = self.root_ppn;
-        let mut result: Option<&mut PageTableEntry> = None;
         for i in 0..3 {
             let pte = ppn.get_pte(idx[i]);
             // println!("fine pte of offset {} at ppn {}, and valid = {}", idx[i], ppn.0, pte.is_valid());
-            if i == 2 {
-                result = Some(pte);
-                break;
+            if i == 2 || pte.is_leaf() {
+                return Some((pte, LEVEL_PAGE_CNT[i]));
             }
             if !pte.is_valid() {
                 return None;
             }
             ppn = pte.ppn();
         }
-        return result;
+        return None;
+    }
+
+    //whether the pte of vpn on the given level is unused, and a leaf can be put there
+    fn is_free_at(&self, vpn: VPN, level: usize) -> bool {
+        let idx: [usize; 3] = [(vpn.0 >> 18) & 511, (vpn.0 >> 9) & 511, vpn.0 & 511];
+        let mut ppn = self.root_ppn;
+        for i in 0..level {
+            let pte = ppn.get_pte(idx[i]);
+            if i == level - 1 || !pte.is_valid() {
+                return !pte.is_valid();
+            }
+            if pte.is_leaf() {
+                return false;
+            }
+            ppn = pte.ppn();
+        }
+        return false;
+    }
+
+    //find the pte of the given vpn, which may be a megapage. if a node on the page table tree dosen't exist, return None.
+    pub fn find_pte(&self, vpn: VPN) -> Option<&mut PageTableEntry> {
+        return self.find_leaf(vpn).map(|(pte, _)| pte);
     }
 
     //construct map between vpn and ppn 
-    pub fn map(&mut self, vpn: VPN, ppn: PPN, flags: PTEFlags) {
-        let pte = self.find_and_alloc_pte(vpn).unwrap();
+    pub fn map(&mut self, vpn: VPN, ppn: PPN, flags: PTEFlags) -> Result<(), OutOfMemory> {
+        let pte = self.find_and_alloc_pte(vpn).ok_or(OutOfMemory)?;
         assert!(!pte.is_valid(), "vpn {:#x} has been mapped already", vpn.0);
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        Ok(())
+    }
+
+    //map [start, end) to the physical pages from ppn, using the largest pages that alignment allows.
+    //if frames run out, the pages mapped so far are unmapped.
+    pub fn map_linear(&mut self, start: VPN, end: VPN, ppn: PPN, flags: PTEFlags) -> Result<(), OutOfMemory> {
+        let mut vpn = start;
+        let mut ppn = ppn;
+        while vpn < end {
+            // a megapage can not replace a node of page table that has been built
+            let level = (1..=3)
+                .find(|&level| {
+                    let page_cnt = LEVEL_PAGE_CNT[level - 1];
+                    vpn.0 % page_cnt == 0
+                        && ppn.0 % page_cnt == 0
+                        && vpn.0 + page_cnt <= end.0
+                        && (level == 3 || self.is_free_at(vpn, level))
+                })
+                .unwrap();
+            let pte = match self.find_and_alloc_pte_at(vpn, level) {
+                Some(pte) => pte,
+                None => {
+                    let mut mapped = start;
+                    while mapped < vpn {
+                        mapped.0 += self.unmap(mapped);
+                    }
+                    return Err(OutOfMemory);
+                }
+            };
+            assert!(!pte.is_valid(), "vpn {:#x} has been mapped already", vpn.0);
+            *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+            vpn.0 += LEVEL_PAGE_CNT[level - 1];
+            ppn.0 += LEVEL_PAGE_CNT[level - 1];
+        }
+        Ok(())
     }
 
     //allocate a physical frame for virtual page, and construct map between vpn and ppn 
-    pub fn map_and_alloc(&mut self, vpn: VPN, flags: PTEFlags) -> FrameTracker {
-        let frame = alloc_frame().unwrap();
-        self.map(vpn, frame.ppn, flags | PTEFlags::V);
-        return frame;
+    pub fn map_and_alloc(&mut self, vpn: VPN, flags: PTEFlags) -> Result<FrameTracker, OutOfMemory> {
+        let frame = alloc_frame().ok_or(OutOfMemory)?;
+        self.map(vpn, frame.ppn, flags | PTEFlags::V)?;
+        return Ok(frame);
     }
 
-    #[allow(unused)]
-    pub fn unmap(&mut self, vpn: VPN) {
-        let pte = self.find_pte(vpn).unwrap();
+    //unmap the page that starts at vpn, and return the number of pages it maps. A megapage can only be unmapped as a whole.
+    pub fn unmap(&mut self, vpn: VPN) -> usize {
+        let (pte, page_cnt) = self.find_leaf(vpn).unwrap();
         assert!(
             pte.is_valid(),
             "vpn {:?} is invalid before unmapping",
             vpn.0
         );
+        assert!(vpn.0 % page_cnt == 0, "vpn {:#x} is inside a megapage", vpn.0);
         *pte = PageTableEntry::empty();
+        return page_cnt;
     }
 
     // get the value from find_pte(), from a reference to a value
@@ -309,75 +397,17 @@ impl PageTable {
         self.find_pte(vpn).map(|pte| *pte)
     }
 
+    // return None if the vpn is not mapped, e.g. a lazy page that has not been accessed yet
     pub fn translate(&self, vpn: VPN) -> Option<PPN> {
-        let pte = self.get_pte(vpn);
-        return pte.map(|pte| pte.ppn());
+        let leaf = self.find_leaf(vpn);
+        return leaf
+            .filter(|(pte, _)| pte.is_valid())
+            .map(|(pte, page_cnt)| PPN(pte.ppn().0 + vpn.0 % page_cnt));
     }
 
     pub fn clear(&mut self) {
         self.frames.clear();
     }
 }
-
-// from a start ptr get bytes with length len
-pub fn physical_bytes_of_user_ptr(satp: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
-    let page_table = PageTable::new_from_satp(satp);
-    let mut start = ptr as usize;
-    let end = start + len;
-    let mut bytes = Vec::new();
-    while start < end {
-        let start_va = VirtAddr::from(start);
-        let mut vpn = start_va.to_down_vpn();
-        let ppn = page_table.translate(vpn).unwrap();
-        vpn.step();
-        let mut end_va: VirtAddr = vpn.into();
-        end_va = end_va.min(VirtAddr::from(end));
-        if end_va.page_offset() == 0 {
-            bytes.push(&mut ppn.get_page()[start_va.page_offset()..]);
-        } else {
-            bytes.push(&mut ppn.get_page()[start_va.page_offset()..end_va.page_offset()]);
-        }
-        start = end_va.into();
-    }
-    return bytes;
-}
-
-// from a start ptr get a string (read until '\0')
-pub fn get_string(satp: usize, ptr: *const u8) -> String {
-    let page_table = PageTable::new_from_satp(satp);
-    let mut string = String::new();
-    let va: VirtAddr = (ptr as usize).into();
-    let mut vpn: VPN = va.to_down_vpn();
-    let mut offset = va.page_offset();
-    loop {
-        let ppn = page_table.translate(vpn);
-        let data = ppn.unwrap().get_page();
-        while offset < PAGE_SIZE {
-            let ch = data[offset] as char;
-            if ch != '\0' {
-                string.push(ch);
-                offset += 1;
-            } else {
-                return string;
-            }
-        }
-        vpn.step();
-        offset = 0;
-    }
-}
-
-pub fn write_into<T>(satp: usize, ptr: *mut T, value: T) {
-    let page_table = PageTable::new_from_satp(satp);
-    let va: VirtAddr = (ptr as usize).into();
-    let vpn: VPN = va.to_down_vpn();
-    let offset = va.page_offset();
-    let ppn = page_table.translate(vpn).unwrap();
-    let pa_aligned: PhyAddr = ppn.into();
-    let pa = pa_aligned.0 + offset;
-    let ptr = pa as *mut T;
-    unsafe {
-        ptr.write(value);
-    }
-}
    
     
@@ -0,0 +1,127 @@
+// shared memory objects. An object is a set of frames that can be mapped into several address spaces, it is
+// found by its id, or by its name if it has one. Each mapping holds the frames, so after an object is destroyed,
+// its frames live until the last mapping is unmapped. An object without a name can not be opened again, so it is
+// destroyed as soon as its last mapping goes; a named object stays until shm_destroy.
+
+use super::frame_allocator::{alloc_frame, FrameTracker, OutOfMemory};
+use crate::config::PAGE_SIZE;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use errno::Errno;
+use sync::UPSafeCell;
+
+pub struct SharedMemory {
+    id: usize,
+    pub frames: Vec<Arc<FrameTracker>>,
+    mappings: AtomicUsize, // the number of ShmMappings of the object
+}
+
+impl SharedMemory {
+    // zeroed frames for size bytes
+    fn new(id: usize, size: usize) -> Result<Self, OutOfMemory> {
+        let page_cnt = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+        let mut frames = Vec::with_capacity(page_cnt);
+        for _ in 0..page_cnt {
+            frames.push(Arc::new(alloc_frame().ok_or(OutOfMemory)?));
+        }
+        return Ok(Self {
+            id,
+            frames,
+            mappings: AtomicUsize::new(0),
+        });
+    }
+
+    pub fn size(&self) -> usize {
+        return self.frames.len() * PAGE_SIZE;
+    }
+}
+
+struct SharedMemoryTable {
+    objects: BTreeMap<usize, (Option<String>, Arc<SharedMemory>)>, // id -> (name, object)
+    names: BTreeMap<String, usize>,
+    next_id: usize,
+}
+
+lazy_static! {
+    static ref SHARED_MEMORY: UPSafeCell<SharedMemoryTable> = unsafe {
+        UPSafeCell::new(SharedMemoryTable {
+            objects: BTreeMap::new(),
+            names: BTreeMap::new(),
+            next_id: 1,
+        })
+    };
+}
+
+// create an object of size bytes, or open the object of name if there is one.
+// return its id, EINVAL if size is 0 or the existing object is smaller than size, or ENOMEM if frames run out.
+pub fn shm_create(name: Option<&str>, size: usize) -> Result<usize, Errno> {
+    if size == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mut table = SHARED_MEMORY.exclusive_access();
+    if let Some(&id) = name.and_then(|name| table.names.get(name)) {
+        return (table.objects[&id].1.size() >= size).then_some(id).ok_or(Errno::EINVAL);
+    }
+    let id = table.next_id;
+    let object = Arc::new(SharedMemory::new(id, size)?);
+    table.next_id += 1;
+    if let Some(name) = name {
+        table.names.insert(String::from(name), id);
+    }
+    table.objects.insert(id, (name.map(String::from), object));
+    return Ok(id);
+}
+
+pub fn shm_get(id: usize) -> Option<Arc<SharedMemory>> {
+    return SHARED_MEMORY.exclusive_access().objects.get(&id).map(|(_, object)| Arc::clone(object));
+}
+
+// remove the object, it can not be opened or mapped any more, but the existing mappings are kept.
+// return false if there is no such object.
+pub fn shm_destroy(id: usize) -> bool {
+    let mut table = SHARED_MEMORY.exclusive_access();
+    match table.objects.remove(&id) {
+        Some((name, _)) => {
+            if let Some(name) = name {
+                table.names.remove(&name);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+// a mapping of an object, held by the section it is mapped in. It is cloned when the section is split or copied
+// by fork, and dropped when the section is unmapped.
+pub struct ShmMapping {
+    object: Arc<SharedMemory>,
+}
+
+impl ShmMapping {
+    pub fn new(object: Arc<SharedMemory>) -> Self {
+        object.mappings.fetch_add(1, Ordering::Relaxed);
+        return Self { object };
+    }
+}
+
+impl Clone for ShmMapping {
+    fn clone(&self) -> Self {
+        return Self::new(Arc::clone(&self.object));
+    }
+}
+
+impl Drop for ShmMapping {
+    fn drop(&mut self) {
+        if self.object.mappings.fetch_sub(1, Ordering::Relaxed) > 1 {
+            return;
+        }
+        // the last mapping is gone, an object without a name can not be used any more
+        let mut table = SHARED_MEMORY.exclusive_access();
+        if matches!(table.objects.get(&self.object.id), Some((None, _))) {
+            table.objects.remove(&self.object.id);
+        }
+    }
+}
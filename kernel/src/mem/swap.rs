@@ -0,0 +1,208 @@
+// swap space for user pages. When frames run low, private pages of user sections are written to a swap slot and
+// unmapped, and they are read back on the next page fault. The swap disk is the first virtio block device if
+// qemu has one. Without it there is no swap, unless the swap-ram-disk feature makes a block of RAM stand in for it.
+// Pages are swapped out only by page faults, through AddressSpace::alloc_user_frame. Other frames, for page
+// tables, kernel stacks, fork, MAP_SHARED mmap, shm and Framed sbrk, are taken directly and fail with ENOMEM
+// when the free frames run out; SWAP_FREE_FRAMES_MIN frames are kept for them.
+
+use super::frame_allocator::{alloc_contiguous_frames, ContiguousFrameTracker, FRAME_ALLOCATOR};
+use super::page_table::PhyAddr;
+use crate::config::{GREEN, PAGE_SIZE, RESET, SWAP_FREE_FRAMES_MIN, SWAP_RAM_DISK_ORDER, VIRTIO0};
+use crate::process::task_manager::{get_task, task_pids};
+use crate::virtio_blk::{VirtioBlock, SECTOR_SIZE};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use sync::UPSafeCell;
+
+// a disk of pages
+pub trait SwapDevice: Send {
+    fn slot_cnt(&self) -> usize;
+    fn read(&mut self, slot: usize, page: &mut [u8]);
+    fn write(&mut self, slot: usize, page: &[u8]);
+}
+
+// physically contiguous frames that stand in for a disk
+pub struct RamDisk {
+    frames: ContiguousFrameTracker,
+}
+
+impl RamDisk {
+    fn page(&self, slot: usize) -> &'static mut [u8] {
+        let start = PhyAddr::from(self.frames.ppn).0 + slot * PAGE_SIZE;
+        return unsafe { core::slice::from_raw_parts_mut(start as *mut u8, PAGE_SIZE) };
+    }
+}
+
+impl SwapDevice for RamDisk {
+    fn slot_cnt(&self) -> usize {
+        return self.frames.frame_cnt();
+    }
+
+    fn read(&mut self, slot: usize, page: &mut [u8]) {
+        page.copy_from_slice(self.page(slot));
+    }
+
+    fn write(&mut self, slot: usize, page: &[u8]) {
+        self.page(slot).copy_from_slice(page);
+    }
+}
+
+impl SwapDevice for VirtioBlock {
+    fn slot_cnt(&self) -> usize {
+        return self.size() / PAGE_SIZE;
+    }
+
+    // a failed request means the disk is broken, and the pages on it are lost
+    fn read(&mut self, slot: usize, page: &mut [u8]) {
+        assert!(VirtioBlock::read(self, slot * PAGE_SIZE / SECTOR_SIZE, page), "swap disk read failed");
+    }
+
+    fn write(&mut self, slot: usize, page: &[u8]) {
+        assert!(VirtioBlock::write(self, slot * PAGE_SIZE / SECTOR_SIZE, page), "swap disk write failed");
+    }
+}
+
+struct SwapSpace {
+    device: Box<dyn SwapDevice>,
+    used: Vec<u64>, // a bit for each slot
+    next: usize,    // where to look for a free slot
+    used_cnt: usize,
+}
+
+impl SwapSpace {
+    fn new(device: Box<dyn SwapDevice>) -> Self {
+        let slot_cnt = device.slot_cnt();
+        Self {
+            device,
+            used: vec![0; (slot_cnt + 63) / 64],
+            next: 0,
+            used_cnt: 0,
+        }
+    }
+
+    fn alloc(&mut self) -> Option<usize> {
+        let slot_cnt = self.device.slot_cnt();
+        if self.used_cnt == slot_cnt {
+            return None;
+        }
+        while self.used[self.next / 64] & 1 << (self.next % 64) != 0 {
+            self.next = (self.next + 1) % slot_cnt;
+        }
+        let slot = self.next;
+        self.used[slot / 64] |= 1 << (slot % 64);
+        self.used_cnt += 1;
+        return Some(slot);
+    }
+
+    fn dealloc(&mut self, slot: usize) {
+        assert!(self.used[slot / 64] & 1 << (slot % 64) != 0, "swap slot {} has not been allocated!", slot);
+        self.used[slot / 64] &= !(1 << (slot % 64));
+        self.used_cnt -= 1;
+    }
+}
+
+lazy_static! {
+    // None until init_swap, or if there is no swap disk
+    static ref SWAP_SPACE: UPSafeCell<Option<SwapSpace>> = unsafe { UPSafeCell::new(None) };
+    // the pid whose pages are swapped out next
+    static ref SWAP_HAND: UPSafeCell<usize> = unsafe { UPSafeCell::new(0) };
+}
+
+pub fn init_swap() {
+    let device: Box<dyn SwapDevice> = match VirtioBlock::new(VIRTIO0) {
+        Some(disk) => Box::new(disk),
+        None if cfg!(feature = "swap-ram-disk") => {
+            // take the largest block of RAM the frame allocator has
+            match (0..=SWAP_RAM_DISK_ORDER).rev().find_map(alloc_contiguous_frames) {
+                Some(frames) => Box::new(RamDisk { frames }),
+                None => return,
+            }
+        }
+        None => return,
+    };
+    println!("swap: {} slots", device.slot_cnt());
+    *SWAP_SPACE.exclusive_access() = Some(SwapSpace::new(device));
+}
+
+// tracks a slot of the swap disk that holds a page, like FrameTracker does a frame
+pub struct SwapSlot {
+    slot: usize,
+}
+
+impl SwapSlot {
+    // write page to a new slot, return None if there is no swap disk or it is full
+    pub fn write(page: &[u8]) -> Option<Self> {
+        let mut swap_space = SWAP_SPACE.exclusive_access();
+        let swap_space = swap_space.as_mut()?;
+        let slot = swap_space.alloc()?;
+        swap_space.device.write(slot, page);
+        return Some(Self { slot });
+    }
+
+    pub fn read(&self, page: &mut [u8]) {
+        SWAP_SPACE.exclusive_access().as_mut().unwrap().device.read(self.slot, page);
+    }
+}
+
+impl Drop for SwapSlot {
+    fn drop(&mut self) {
+        SWAP_SPACE.exclusive_access().as_mut().unwrap().dealloc(self.slot);
+    }
+}
+
+// whether frames are so few that pages should be swapped out
+pub fn frames_low() -> bool {
+    return FRAME_ALLOCATOR.exclusive_access().free_frame_cnt() < SWAP_FREE_FRAMES_MIN;
+}
+
+// swap out a page of a process that is not being changed by the kernel now. The processes take turns, and in each
+// of them the page is chosen by the clock. Return false if no page can be swapped out.
+pub fn swap_out_any() -> bool {
+    if SWAP_SPACE.exclusive_access().is_none() {
+        return false;
+    }
+    let pids = task_pids();
+    let hand = *SWAP_HAND.exclusive_access();
+    let start = pids.partition_point(|&pid| pid < hand);
+    for i in 0..pids.len() {
+        let pid = pids[(start + i) % pids.len()];
+        let task = match get_task(pid) {
+            Some(task) => task,
+            None => continue,
+        };
+        let swapped_out = match task.inner.try_exclusive_access() {
+            Some(mut inner) => inner.address_space.swap_out(None),
+            None => false, // the current process, or a process being forked
+        };
+        if swapped_out {
+            *SWAP_HAND.exclusive_access() = pid + 1;
+            return true;
+        }
+    }
+    return false;
+}
+
+#[allow(unused)]
+// the swap disk keeps what is written to each slot
+pub fn swap_test() {
+    if SWAP_SPACE.exclusive_access().is_none() {
+        println!("swap_test skipped, there is no swap disk");
+        return;
+    }
+    let mut page = vec![0u8; PAGE_SIZE];
+    let slots: Vec<SwapSlot> = (0..8)
+        .map(|i| {
+            page.fill(i as u8 + 1);
+            SwapSlot::write(&page).unwrap()
+        })
+        .collect();
+    for (i, slot) in slots.iter().enumerate().rev() {
+        slot.read(&mut page);
+        assert!(page.iter().all(|&byte| byte == i as u8 + 1));
+    }
+    let used_cnt = SWAP_SPACE.exclusive_access().as_ref().unwrap().used_cnt;
+    drop(slots);
+    assert_eq!(SWAP_SPACE.exclusive_access().as_ref().unwrap().used_cnt, used_cnt - 8);
+    println!("{}swap_test passed!{}", GREEN, RESET);
+}
@@ -0,0 +1,179 @@
+// pointers into user space, as syscalls get them. The kernel reaches user memory through the physical addresses
+// of its pages, so every access is checked against the address space first: the range must be in the user half,
+// and its pages mapped for user with the permission of the access. Lazy, swapped and copy-on-write pages are
+// resolved as user code would. A bad pointer is EFAULT (ENOMEM if its pages can not be mapped), it never panics
+// the kernel or writes where user code could not.
+
+use super::address_space::{AccessType, AddressSpace};
+use super::page_table::VirtAddr;
+use crate::config::{GREEN, PAGE_SIZE, RESET, USER_STRING_MAX};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::marker::PhantomData;
+use core::mem::{size_of, MaybeUninit};
+use errno::Errno;
+
+// len bytes at addr in user space
+#[derive(Copy, Clone, Debug)]
+pub struct UserSlice {
+    addr: usize,
+    len: usize,
+}
+
+impl UserSlice {
+    pub fn new(addr: usize, len: usize) -> Self {
+        Self { addr, len }
+    }
+
+    // check that the slice can be accessed, resolving its pages. They may be unmapped or swapped out again as soon
+    // as space is not borrowed.
+    pub fn check(&self, space: &mut AddressSpace, access: AccessType) -> Result<(), Errno> {
+        space.prepare_user_access(self.addr, self.len, access)?;
+        return Ok(());
+    }
+
+    // the bytes of the slice in each of its pages, after they are checked for access. They borrow space, which
+    // keeps the pages mapped while they are used.
+    pub fn pages<'a>(&self, space: &'a mut AddressSpace, access: AccessType) -> Result<Vec<&'a mut [u8]>, Errno> {
+        self.check(space, access)?;
+        let mut pages = Vec::new();
+        let mut done = 0;
+        while done < self.len {
+            let va = VirtAddr::from(self.addr + done);
+            let len = min(PAGE_SIZE - va.page_offset(), self.len - done);
+            let page = space.translate(va.to_down_vpn()).unwrap().get_page();
+            pages.push(&mut page[va.page_offset()..va.page_offset() + len]);
+            done += len;
+        }
+        return Ok(pages);
+    }
+
+    // copy in, buf has the length of the slice
+    pub fn read(&self, space: &mut AddressSpace, buf: &mut [u8]) -> Result<(), Errno> {
+        assert_eq!(buf.len(), self.len);
+        let mut done = 0;
+        for page in self.pages(space, AccessType::Load)? {
+            buf[done..done + page.len()].copy_from_slice(page);
+            done += page.len();
+        }
+        return Ok(());
+    }
+
+    // copy out, data has the length of the slice
+    pub fn write(&self, space: &mut AddressSpace, data: &[u8]) -> Result<(), Errno> {
+        assert_eq!(data.len(), self.len);
+        let mut done = 0;
+        for page in self.pages(space, AccessType::Store)? {
+            let len = page.len();
+            page.copy_from_slice(&data[done..done + len]);
+            done += len;
+        }
+        return Ok(());
+    }
+}
+
+// a T at addr in user space. T is plain data, any bytes user code writes are a valid T.
+pub struct UserPtr<T> {
+    addr: usize,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T: Copy> UserPtr<T> {
+    pub fn new(addr: usize) -> Self {
+        Self { addr, _marker: PhantomData }
+    }
+
+    pub fn is_null(&self) -> bool {
+        return self.addr == 0;
+    }
+
+    // the pointer to the count-th T after this one, as pointer arithmetic in user space
+    pub fn add(&self, count: usize) -> Self {
+        return Self::new(self.addr.wrapping_add(count.wrapping_mul(size_of::<T>())));
+    }
+
+    fn slice(&self) -> UserSlice {
+        return UserSlice::new(self.addr, size_of::<T>());
+    }
+
+    pub fn read(&self, space: &mut AddressSpace) -> Result<T, Errno> {
+        let mut value = MaybeUninit::<T>::uninit();
+        let bytes = unsafe { core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>()) };
+        self.slice().read(space, bytes)?;
+        return Ok(unsafe { value.assume_init() });
+    }
+
+    pub fn write(&self, space: &mut AddressSpace, value: &T) -> Result<(), Errno> {
+        let bytes = unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+        return self.slice().write(space, bytes);
+    }
+}
+
+impl UserPtr<u8> {
+    // read the NUL-terminated string here. EFAULT if it is not readable, or longer than USER_STRING_MAX with its NUL
+    pub fn read_string(&self, space: &mut AddressSpace) -> Result<String, Errno> {
+        let mut string = String::new();
+        let mut read = 0;
+        while read < USER_STRING_MAX {
+            // up to the end of the page, no further than the bound
+            let addr = self.addr + read;
+            let len = min(PAGE_SIZE - VirtAddr::from(addr).page_offset(), USER_STRING_MAX - read);
+            for &byte in UserSlice::new(addr, len).pages(space, AccessType::Load)?[0].iter() {
+                if byte == 0 {
+                    return Ok(string);
+                }
+                string.push(byte as char);
+            }
+            read += len;
+        }
+        return Err(Errno::EFAULT);
+    }
+}
+
+#[allow(unused)]
+// user pointers reach the pages user code could, with its permissions
+pub fn user_ptr_test() {
+    use super::address_space::{user_space_from_elf, SectionPermisson};
+    use crate::config::{MMAP_BASE, TRAP_CONTEXT_START_VA, USER_SPACE_END};
+    use crate::process::loader::open_app_file;
+
+    let (mut space, user_sp, _, entry) = user_space_from_elf(open_app_file("hello_world").unwrap()).unwrap();
+    let data = MMAP_BASE;
+    space.mmap(data.into(), 2 * PAGE_SIZE, SectionPermisson::R | SectionPermisson::W, false, false).unwrap();
+    // a value across two lazy pages
+    let ptr = UserPtr::<u64>::new(data + PAGE_SIZE - 4);
+    assert_eq!(ptr.read(&mut space), Ok(0));
+    ptr.write(&mut space, &0x0123_4567_89ab_cdef).unwrap();
+    assert_eq!(ptr.read(&mut space), Ok(0x0123_4567_89ab_cdef));
+    let stack = UserPtr::<usize>::new(user_sp.0 - 8);
+    stack.write(&mut space, &42).unwrap();
+    assert_eq!(stack.read(&mut space), Ok(42));
+
+    // strings end with NUL, within the bound
+    UserSlice::new(data, 6).write(&mut space, b"hello\0").unwrap();
+    assert_eq!(UserPtr::<u8>::new(data).read_string(&mut space).as_deref(), Ok("hello"));
+    let long = UserSlice::new(data, USER_STRING_MAX);
+    long.write(&mut space, &[b'a'; USER_STRING_MAX]).unwrap();
+    assert_eq!(UserPtr::<u8>::new(data).read_string(&mut space), Err(Errno::EFAULT));
+    long.write(&mut space, &[0; USER_STRING_MAX]).unwrap();
+
+    // unmapped, read-only, kernel-only or outside the user half
+    assert_eq!(UserPtr::<u8>::new(0).read(&mut space), Err(Errno::EFAULT));
+    assert_eq!(UserPtr::<u8>::new(data + 2 * PAGE_SIZE).read(&mut space), Err(Errno::EFAULT));
+    assert_eq!(UserPtr::<u32>::new(entry).write(&mut space, &0), Err(Errno::EFAULT));
+    assert!(UserPtr::<u32>::new(entry).read(&mut space).is_ok());
+    assert_eq!(UserPtr::<usize>::new(TRAP_CONTEXT_START_VA).write(&mut space, &0), Err(Errno::EFAULT));
+    assert_eq!(UserPtr::<usize>::new(TRAP_CONTEXT_START_VA).read(&mut space), Err(Errno::EFAULT));
+    assert_eq!(UserSlice::new(USER_SPACE_END - 4, 8).pages(&mut space, AccessType::Load).err(), Some(Errno::EFAULT));
+    assert_eq!(UserSlice::new(usize::MAX - 4, 8).pages(&mut space, AccessType::Load).err(), Some(Errno::EFAULT));
+    println!("{}user_ptr_test passed!{}", GREEN, RESET);
+}
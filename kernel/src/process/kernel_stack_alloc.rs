@@ -1,6 +1,7 @@
 //!Implementation of [`PidAllocator`]
 use crate::config::{KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE_START_VA};
 use crate::mem::address_space::{MapType, SectionPermisson, KERNEL_SPACE};
+use crate::mem::frame_allocator::OutOfMemory;
 use crate::mem::page_table::VirtAddr;
 
 /// Return (bottom, top) of a kernel stack in kernel space.
@@ -17,7 +18,7 @@ pub struct KernelStack {
 
 impl KernelStack {
     // allocate a kernel stack for a task
-    pub fn new(pid: usize) -> Self {
+    pub fn new(pid: usize) -> Result<Self, OutOfMemory> {
         let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(pid);
         KERNEL_SPACE.exclusive_access().add_section(
             kernel_stack_bottom.into(),
@@ -25,8 +26,8 @@ impl KernelStack {
             SectionPermisson::R | SectionPermisson::W,
             MapType::Framed,
             None,
-        );
-        KernelStack { pid }
+        )?;
+        Ok(KernelStack { pid })
     }
 
     #[allow(unused)]
@@ -29,7 +29,7 @@ pub fn get_app_data(app_id: usize) -> &'static [u8] {
 }
 
 #[allow(unused)]
-// get app data from name
+// get app data from name, or the data of a file in user/data
 pub fn open_app_file(name: &str) -> Option<&'static [u8]> {
     let num_app = get_app_num();
     (0..num_app)
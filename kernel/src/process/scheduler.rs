@@ -1,6 +1,7 @@
 use super::context::TaskContext;
 use super::switch::__switch;
 use super::task_manager::{get_task, TaskControlBlock, INIT_TASK};
+use crate::mem::address_space::{AccessType, PageFaultError};
 use crate::process::loader::open_app_file;
 use crate::sbi;
 use crate::syscall::process_manager::PM_SERVICE;
@@ -16,6 +17,7 @@ const PM_WAITPID: usize = 3;
 const PM_SUSPEND_AND_RUN_NEXT: usize = 4;
 const PM_EXIT_AND_RUN_NEXT: usize = 5;
 const PM_FETCH: usize = 6;
+const PM_CANCEL_FORK: usize = 7;
 
 pub struct Scheduler {
     current: Option<Arc<TaskControlBlock>>,
@@ -142,9 +144,11 @@ pub fn exit_current_and_run_next(exit_code: i32) {
         }
     }
 
-    // release resources
+    // release resources, the memory usage is kept for the parent
     let mut cur_task_inner = cur_task.inner.exclusive_access();
+    cur_task_inner.exit_usage = Some(cur_task_inner.memory_usage());
     cur_task_inner.address_space.clear();
+    cur_task_inner.fd_table.clear();
     drop(cur_task_inner); 
 
     // switch to next task
@@ -152,11 +156,13 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     switch_in_pid(next_pid);
 }
 
+// the satp to return to current task with, a new ASID is taken if its ASID has expired
 pub fn get_current_satp() -> usize {
     let scheduler = SCHEDULER.exclusive_access();
     let cur_task = scheduler.get_current().unwrap();
     drop(scheduler);
-    let cur_task_inner = cur_task.inner.exclusive_access();
+    let mut cur_task_inner = cur_task.inner.exclusive_access();
+    cur_task_inner.address_space.update_asid();
     let satp = cur_task_inner.address_space.get_satp();
     drop(cur_task_inner);
     return satp;
@@ -172,6 +178,13 @@ pub fn get_current_trap_ctx() -> &'static mut TrapContext {
     return trap_ctx;
 }
 
+// a page fault happened in current task, try to resolve it by lazy allocation, stack growth or copy on write
+pub fn handle_page_fault(va: usize, access: AccessType) -> Result<(), PageFaultError> {
+    let cur_task = get_current_task();
+    let mut cur_task_inner = cur_task.inner.exclusive_access();
+    return cur_task_inner.address_space.handle_page_fault(va.into(), access);
+}
+
 pub fn get_current_task() -> Arc<TaskControlBlock> {
     return SCHEDULER.exclusive_access().get_current().unwrap();
 }
@@ -218,6 +231,11 @@ pub fn fork_process() -> usize {
     return PM_SERVICE.exclusive_access().result1 as usize; // the pid of child process
 }
 
+// the child process of current process can not be built, remove it from PM and release its pid
+pub fn cancel_fork_process(child_pid: usize) {
+    call_pm_service(PM_CANCEL_FORK, child_pid as i32);
+}
+
 pub fn waitpid_process(pid: isize) -> (isize, usize) {
     call_pm_service(PM_WAITPID, pid as i32);
     let pid = PM_SERVICE.exclusive_access().result1;
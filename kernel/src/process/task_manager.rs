@@ -2,12 +2,19 @@ use super::context::TaskContext;
 use super::kernel_stack_alloc::KernelStack;
 use super::loader::open_app_file;
 use crate::config::TRAP_CONTEXT_START_VA;
-use crate::mem::address_space::{copy_address_space, user_space_from_elf, AddressSpace, KERNEL_SPACE};
+use crate::fs::FdTable;
+use crate::mem::address_space::{
+    copy_address_space, user_space_from_elf, user_space_from_elf_with_args, AddressSpace, ElfError, MemoryUsage,
+    KERNEL_SPACE,
+};
+use crate::mem::frame_allocator::OutOfMemory;
 use crate::mem::page_table::{PhyAddr, VirtAddr, PPN};
 use crate::trap::{trap_handler, TrapContext};
 use crate::config::{GREEN, RESET};
 use alloc::collections::BTreeMap;
+use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use sync::UPSafeCell;
 
 const INIT_PROC_PID: usize = 1; // pre-allocated pid for init process, to avoid call pm service in kernel thread
@@ -29,6 +36,11 @@ pub fn remove_task(pid: usize) {
     TASK_MANAGER.exclusive_access().remove_task(pid);
 }
 
+// the pids of all tasks, in order
+pub fn task_pids() -> Vec<usize> {
+    return TASK_MANAGER.exclusive_access().ready_tasks.keys().copied().collect();
+}
+
 lazy_static! {
     pub static ref TASK_MANAGER: UPSafeCell<TaskManager> =
         unsafe { UPSafeCell::new(TaskManager::new()) };
@@ -74,19 +86,22 @@ pub struct TaskControlBlockInner {
     pub heap_bottom: usize,
     pub program_brk: usize, // heap top
     pub user_stack_start: usize,
+    pub fd_table: FdTable, // the files are shared with the parent after fork, and kept by exec
+    pub exit_usage: Option<MemoryUsage>, // the memory usage taken when the process exits, for waitpid
 }
 
 impl TaskControlBlock {
+    // only used for the processes built at boot time, which can not fail
     pub fn new(elf_data: &[u8], pid: usize) -> Self {
 
         // build user space, and get trap context ppn, sp of user stack
-        let (user_space, user_sp, elf_entry_point) = user_space_from_elf(elf_data);
+        let (user_space, user_sp, heap_bottom, elf_entry_point) = user_space_from_elf(elf_data).unwrap();
         let trap_ctx_ppn = user_space
             .translate(VirtAddr::from(TRAP_CONTEXT_START_VA).to_down_vpn())
             .unwrap();
 
         // allocate kernel stack
-        let kernel_stack = KernelStack::new(pid);
+        let kernel_stack = KernelStack::new(pid).unwrap();
         let kernel_stack_top = kernel_stack.get_top();
 
         let task_context: TaskContext = TaskContext::new(kernel_stack_top);
@@ -98,10 +113,12 @@ impl TaskControlBlock {
                 UPSafeCell::new(TaskControlBlockInner {
                     trap_ctx_ppn,
                     user_stack_start: user_sp.into(),
-                    heap_bottom: user_sp.into(),
-                    program_brk: user_sp.into(),
+                    heap_bottom: heap_bottom.into(),
+                    program_brk: heap_bottom.into(),
                     task_ctx: task_context,
                     address_space: user_space,
+                    fd_table: FdTable::new(),
+                    exit_usage: None,
                 })
             },
         };
@@ -125,17 +142,18 @@ impl TaskControlBlock {
         task_control_block
     }
 
-    pub fn fork(self: &Arc<TaskControlBlock>, child_pid: usize) -> Arc<TaskControlBlock> {
-        let parent_inner = self.inner.exclusive_access();
+    // return OutOfMemory if frames run out, and nothing of the child is left
+    pub fn fork(self: &Arc<TaskControlBlock>, child_pid: usize) -> Result<Arc<TaskControlBlock>, OutOfMemory> {
+        let mut parent_inner = self.inner.exclusive_access();
 
         // copy address space and get new trap context ppn
-        let child_address_space = copy_address_space(&parent_inner.address_space);
+        let child_address_space = copy_address_space(&mut parent_inner.address_space)?;
         let child_trap_ctx_ppn = child_address_space
             .translate(VirtAddr::from(TRAP_CONTEXT_START_VA).to_down_vpn())
             .unwrap();
 
         // allocate pid and kernel stack
-        let child_kernel_stack = KernelStack::new(child_pid);
+        let child_kernel_stack = KernelStack::new(child_pid)?;
         let child_kernel_stack_top = child_kernel_stack.get_top();
 
         let child_task_context = TaskContext::new(child_kernel_stack_top);
@@ -151,6 +169,8 @@ impl TaskControlBlock {
                     program_brk: parent_inner.program_brk,
                     task_ctx: child_task_context,
                     address_space: child_address_space,
+                    fd_table: parent_inner.fd_table.clone(),
+                    exit_usage: None,
                 })
             },
         });
@@ -163,18 +183,30 @@ impl TaskControlBlock {
             .get_trap_ctx()
             .kernel_sp = child_kernel_stack_top;
 
-        return child_task_control_block;
+        return Ok(child_task_control_block);
     }
 
-    pub fn exec(&self, elf_data: &[u8]) {
-        let (user_space, user_sp, elf_entry_point) = user_space_from_elf(elf_data);
+    // if the elf is rejected or frames run out, the old address space is kept, and the process goes on running the old program
+    // replace the program with elf_data, which starts with argv and envp on its stack
+    pub fn exec(&self, elf_data: &[u8], argv: &[String], envp: &[String]) -> Result<(), ElfError> {
+        let (mut user_space, user_sp, heap_bottom, elf_entry_point) = user_space_from_elf_with_args(elf_data, argv, envp)?;
         let trap_ctx_ppn = user_space
             .translate(VirtAddr::from(TRAP_CONTEXT_START_VA).to_down_vpn())
             .unwrap();
 
         let mut inner = self.inner.exclusive_access();
+        // the memory limits are kept by the new program
+        if !user_space.set_limits(inner.address_space.get_limits())
+            || !user_space.set_stack_limit(inner.address_space.get_stack_limit())
+        {
+            return Err(ElfError::OutOfMemory);
+        }
         inner.address_space = user_space;
         inner.trap_ctx_ppn = trap_ctx_ppn;
+        // the heap of the new program starts above its user stack
+        inner.user_stack_start = user_sp.into();
+        inner.heap_bottom = heap_bottom.into();
+        inner.program_brk = heap_bottom.into();
 
         inner.set_trap_ctx(
             elf_entry_point,
@@ -184,6 +216,7 @@ impl TaskControlBlock {
             trap_handler as usize,
         );
         drop(inner);
+        return Ok(());
     }
 
     pub fn get_pid(&self) -> usize {
@@ -217,6 +250,16 @@ impl TaskControlBlockInner {
         return unsafe { (ctx_addr.0 as *mut TrapContext).as_mut().unwrap() };
     }
 
+    // the memory usage of a process that has exited is the one it had when it exited
+    pub fn memory_usage(&self) -> MemoryUsage {
+        if let Some(usage) = self.exit_usage {
+            return usage;
+        }
+        let mut usage = self.address_space.memory_usage();
+        usage.heap = self.program_brk - self.heap_bottom;
+        return usage;
+    }
+
     /// change the location of the program break. return None if failed.
     pub fn change_program_brk(&mut self, size: i32) -> Option<usize> {
         let old_break = self.program_brk;
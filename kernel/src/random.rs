@@ -0,0 +1,24 @@
+// a pseudo random number generator for the kernel, used to randomize the layout of user address spaces.
+// it is not cryptographically secure, but the timer is mixed in on every call, so the numbers depend on
+// when they are taken, which is hard to guess from user space.
+
+use riscv::register::time;
+use sync::UPSafeCell;
+
+lazy_static! {
+    static ref STATE: UPSafeCell<u64> = unsafe { UPSafeCell::new(0x853c_49e6_748f_ea9b) };
+}
+
+pub fn random() -> usize {
+    let mut state = STATE.exclusive_access();
+    let mut x = *state ^ (time::read() as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    if x == 0 {
+        x = 0x853c_49e6_748f_ea9b; // xorshift never leaves 0
+    }
+    // xorshift64*
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    *state = x;
+    return x.wrapping_mul(0x2545_f491_4f6c_dd1d) as usize;
+}
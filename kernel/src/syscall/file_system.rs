@@ -1,38 +1,79 @@
-use crate::mem::page_table::physical_bytes_of_user_ptr;
-use crate::sbi;
-use crate::process::scheduler::get_current_satp;
-use crate::config::{FD_STDOUT, FD_STDIN};
+use crate::process::scheduler::get_current_task;
+use crate::mem::address_space::AccessType;
+use crate::config::PAGE_SIZE;
+use crate::mem::user_ptr::UserSlice;
+use alloc::vec;
+use core::cmp::min;
+use errno::SyscallResult;
 
-// return the number of bytes written successfully
-pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
-    match fd {
-        FD_STDOUT => {
-            let buffers = physical_bytes_of_user_ptr(get_current_satp(), buf, len);
-            for buffer in buffers {
-                print!("{}", core::str::from_utf8(buffer).unwrap());
-            }
-            len as isize
-        },
-        _ => {
-            panic!("Unsupported fd in sys_write!");
+// write buf to the file of fd. Return the number of bytes written, EBADF if fd is not open for writing,
+// or EFAULT if buf can not be read by user (ENOMEM if its pages can not be mapped).
+pub fn sys_write(fd: usize, buf: usize, len: usize) -> SyscallResult {
+    let task = get_current_task();
+    let mut inner = task.inner.exclusive_access();
+    let file = inner.fd_table.get(fd)?;
+    UserSlice::new(buf, len).check(&mut inner.address_space, AccessType::Load)?;
+    drop(inner);
+    // the file may block, so the data goes through a kernel buffer a page at a time, and the task is not borrowed
+    // while it is written
+    let mut bounce = vec![0; min(len, PAGE_SIZE)];
+    let mut written = 0;
+    while written < len {
+        let chunk = &mut bounce[..min(len - written, PAGE_SIZE)];
+        UserSlice::new(buf + written, chunk.len()).read(&mut task.inner.exclusive_access().address_space, chunk)?;
+        let n = file.write(chunk)?;
+        written += n;
+        if n < chunk.len() {
+            break;
         }
     }
+    Ok(written)
 }
 
-// only support read from stdin, len = 1
-pub fn sys_read(fd: usize, buf: *mut u8, len: usize) -> isize {
-    match fd {
-        FD_STDIN => {
-            assert_eq!(len, 1, "Only support len = 1 in sys_read!");
-            let c = sbi::console_getchar();
-            let mut bytes = physical_bytes_of_user_ptr(get_current_satp(), buf, len);
-            unsafe {
-                bytes[0].as_mut_ptr().write_volatile(c); // write to the physical memory of user space
-            }
-            return 1
-        }
-        _ => {
-            panic!("Unsupported fd in sys_read!");
+// read from the file of fd into buf, as much as the file has ready (the console gives a byte at a time).
+// Return the number of bytes read, 0 at the end of the file or if len is 0, EBADF if fd is not open for reading,
+// or EFAULT if buf can not be written by user, before anything is read.
+pub fn sys_read(fd: usize, buf: usize, len: usize) -> SyscallResult {
+    let task = get_current_task();
+    let mut inner = task.inner.exclusive_access();
+    let file = inner.fd_table.get(fd)?;
+    UserSlice::new(buf, len).check(&mut inner.address_space, AccessType::Store)?;
+    drop(inner);
+    // read into a kernel buffer, see sys_write
+    let mut bounce = vec![0; min(len, PAGE_SIZE)];
+    let mut read = 0;
+    while read < len {
+        let chunk = &mut bounce[..min(len - read, PAGE_SIZE)];
+        let n = file.read(chunk)?;
+        UserSlice::new(buf + read, n).write(&mut task.inner.exclusive_access().address_space, &chunk[..n])?;
+        read += n;
+        if n < chunk.len() {
+            break;
         }
     }
-}
\ No newline at end of file
+    Ok(read)
+}
+
+// return 0, or EBADF if fd is not open
+pub fn sys_close(fd: usize) -> SyscallResult {
+    let task = get_current_task();
+    let mut inner = task.inner.exclusive_access();
+    inner.fd_table.close(fd)?;
+    Ok(0)
+}
+
+// open the file of fd at the lowest free fd. Return the new fd, EBADF if fd is not open, or EMFILE if the
+// fd table is full
+pub fn sys_dup(fd: usize) -> SyscallResult {
+    let task = get_current_task();
+    let mut inner = task.inner.exclusive_access();
+    inner.fd_table.dup(fd)
+}
+
+// open the file of old_fd at new_fd, closing the file that was there. Return new_fd, or EBADF if old_fd is not
+// open or new_fd is beyond the fd table
+pub fn sys_dup2(old_fd: usize, new_fd: usize) -> SyscallResult {
+    let task = get_current_task();
+    let mut inner = task.inner.exclusive_access();
+    inner.fd_table.dup2(old_fd, new_fd)
+}
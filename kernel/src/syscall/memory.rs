@@ -0,0 +1,231 @@
+// syscalls about memory mapping
+
+use crate::config::{PAGE_SIZE, USER_SPACE_END, USER_STACK_SIZE};
+use crate::mem::address_space::{MemoryLimits, MemoryUsage, SectionPermisson};
+use crate::mem::page_cache::open_cached_file;
+use crate::mem::shm::{shm_create, shm_destroy, shm_get};
+use crate::mem::user_ptr::UserPtr;
+use crate::process::scheduler::get_current_task;
+use bitflags::bitflags;
+use errno::{Errno, SyscallResult};
+
+bitflags! {
+    // flags of mmap and mmap_file, PROT_* are also used by mprotect.
+    pub struct MmapFlags: usize {
+        const PROT_READ = 1 << 0;
+        const PROT_WRITE = 1 << 1;
+        const PROT_EXEC = 1 << 2;
+        const MAP_SHARED = 1 << 3; // shared with child processes after fork, private (copy on write) if not set
+        const MAP_FIXED = 1 << 4; // map at exactly the given address, replacing old mappings
+    }
+}
+
+impl MmapFlags {
+    fn parse(flags: usize) -> Result<Self, Errno> {
+        return MmapFlags::from_bits(flags).ok_or(Errno::EINVAL);
+    }
+
+    fn permisson(&self) -> SectionPermisson {
+        let mut permisson = SectionPermisson::U;
+        if self.contains(MmapFlags::PROT_READ) {
+            permisson |= SectionPermisson::R;
+        }
+        if self.contains(MmapFlags::PROT_WRITE) {
+            permisson |= SectionPermisson::W;
+        }
+        if self.contains(MmapFlags::PROT_EXEC) {
+            permisson |= SectionPermisson::X;
+        }
+        return permisson;
+    }
+}
+
+// the range [start, start + len) must be in the user half
+fn check_range(start: usize, len: usize) -> Result<(), Errno> {
+    if start >= USER_SPACE_END || len >= USER_SPACE_END {
+        return Err(Errno::EINVAL);
+    }
+    Ok(())
+}
+
+// return the start address of the mapped area, EINVAL if the flags or the range are invalid,
+// or ENOMEM if there is no room or the memory limits are exceeded.
+// if start is 0, or the area is occupied without MAP_FIXED, the kernel chooses the address.
+pub fn sys_mmap(start: usize, len: usize, flags: usize) -> SyscallResult {
+    let flags = MmapFlags::parse(flags)?;
+    check_range(start, len)?;
+    if len == 0 || start % PAGE_SIZE != 0 || (flags.contains(MmapFlags::MAP_FIXED) && start == 0) {
+        return Err(Errno::EINVAL);
+    }
+    let task = get_current_task();
+    let mut inner = task.inner.exclusive_access();
+    inner
+        .address_space
+        .mmap(
+            start.into(),
+            len,
+            flags.permisson(),
+            flags.contains(MmapFlags::MAP_SHARED),
+            flags.contains(MmapFlags::MAP_FIXED),
+        )
+        .map(|start| start.0)
+        .ok_or(Errno::ENOMEM)
+}
+
+// map len bytes of the file at path from its start, at an address chosen by the kernel. flags are those of mmap,
+// without MAP_FIXED. A MAP_SHARED mapping writes to the file on msync and munmap, a private one is copied on write.
+// return the start address of the mapped area, EFAULT if path can not be read, ENOENT if there is no such file,
+// EINVAL if the flags are invalid or len is 0 or beyond the file, or ENOMEM as sys_mmap.
+pub fn sys_mmap_file(path: UserPtr<u8>, len: usize, flags: usize) -> SyscallResult {
+    let flags = match MmapFlags::parse(flags)? {
+        flags if flags.contains(MmapFlags::MAP_FIXED) => return Err(Errno::EINVAL),
+        flags => flags,
+    };
+    let task = get_current_task();
+    let mut inner = task.inner.exclusive_access();
+    let path = path.read_string(&mut inner.address_space)?;
+    let file = open_cached_file(&path).ok_or(Errno::ENOENT)?;
+    if len == 0 || (len + PAGE_SIZE - 1) / PAGE_SIZE > file.page_cnt() {
+        return Err(Errno::EINVAL);
+    }
+    inner
+        .address_space
+        .mmap_file(file, len, flags.permisson(), flags.contains(MmapFlags::MAP_SHARED))
+        .map(|start| start.0)
+        .ok_or(Errno::ENOMEM)
+}
+
+// write the changed pages of shared file mappings in the range to their files.
+// return 0 if success, EINVAL if the range can not be changed by user.
+pub fn sys_msync(start: usize, len: usize) -> SyscallResult {
+    check_range(start, len)?;
+    let task = get_current_task();
+    let mut inner = task.inner.exclusive_access();
+    if inner.address_space.msync(start.into(), len) {
+        Ok(0)
+    } else {
+        Err(Errno::EINVAL)
+    }
+}
+
+// return 0 if success, EINVAL if the range can not be unmapped.
+pub fn sys_munmap(start: usize, len: usize) -> SyscallResult {
+    check_range(start, len)?;
+    let task = get_current_task();
+    let mut inner = task.inner.exclusive_access();
+    if inner.address_space.munmap(start.into(), len) {
+        Ok(0)
+    } else {
+        Err(Errno::EINVAL)
+    }
+}
+
+// return 0 if success, EINVAL if the prot is invalid, or ENOMEM if some pages in the range are not mapped.
+pub fn sys_mprotect(start: usize, len: usize, prot: usize) -> SyscallResult {
+    let prot = MmapFlags::parse(prot)?;
+    if (MmapFlags::MAP_SHARED | MmapFlags::MAP_FIXED).intersects(prot) {
+        return Err(Errno::EINVAL);
+    }
+    check_range(start, len)?;
+    let task = get_current_task();
+    let mut inner = task.inner.exclusive_access();
+    if inner.address_space.mprotect(start.into(), len, prot.permisson()) {
+        Ok(0)
+    } else {
+        Err(Errno::ENOMEM)
+    }
+}
+
+// create a shared memory object of size bytes, or open the object of name if there is one. name is null for an
+// object without a name. Return the id of the object, EFAULT if name can not be read, EINVAL if size is 0 or
+// the existing object is smaller, or ENOMEM if frames run out.
+pub fn sys_shm_create(name: UserPtr<u8>, size: usize) -> SyscallResult {
+    if size >= USER_SPACE_END {
+        return Err(Errno::EINVAL);
+    }
+    let name = if name.is_null() {
+        None
+    } else {
+        let task = get_current_task();
+        let mut inner = task.inner.exclusive_access();
+        Some(name.read_string(&mut inner.address_space)?)
+    };
+    shm_create(name.as_deref(), size)
+}
+
+// return 0 if success, EINVAL if there is no such object. The object keeps living in the address spaces it is
+// mapped into.
+pub fn sys_shm_destroy(id: usize) -> SyscallResult {
+    if shm_destroy(id) {
+        Ok(0)
+    } else {
+        Err(Errno::EINVAL)
+    }
+}
+
+// map the shared memory object with PROT_* in flags, MAP_FIXED is allowed as for mmap, and MAP_SHARED is implied.
+// return the start address of the mapped area, EINVAL if there is no such object, or the errors of sys_mmap.
+pub fn sys_shm_map(id: usize, start: usize, flags: usize) -> SyscallResult {
+    let flags = MmapFlags::parse(flags)?;
+    let object = shm_get(id).ok_or(Errno::EINVAL)?;
+    check_range(start, 0)?;
+    if start % PAGE_SIZE != 0 || (flags.contains(MmapFlags::MAP_FIXED) && start == 0) {
+        return Err(Errno::EINVAL);
+    }
+    let task = get_current_task();
+    let mut inner = task.inner.exclusive_access();
+    inner
+        .address_space
+        .map_shared(&object, start.into(), flags.permisson(), flags.contains(MmapFlags::MAP_FIXED))
+        .map(|start| start.0)
+        .ok_or(Errno::ENOMEM)
+}
+
+// unmap the shared memory mapped at start by shm_map. Return 0 if success, EINVAL if there is no such mapping.
+pub fn sys_shm_unmap(start: usize) -> SyscallResult {
+    check_range(start, 0)?;
+    let task = get_current_task();
+    let mut inner = task.inner.exclusive_access();
+    if inner.address_space.unmap_shared(start.into()) {
+        Ok(0)
+    } else {
+        Err(Errno::EINVAL)
+    }
+}
+
+// write the memory usage of the current process to usage_ptr. Return 0, or EFAULT if usage_ptr can not be written.
+pub fn sys_memory_usage(usage_ptr: UserPtr<MemoryUsage>) -> SyscallResult {
+    let task = get_current_task();
+    let mut inner = task.inner.exclusive_access();
+    let usage = inner.memory_usage();
+    usage_ptr.write(&mut inner.address_space, &usage)?;
+    Ok(0)
+}
+
+// limit the resident memory and the size of all mappings of the current process, in bytes. usize::MAX is unlimited.
+// the limits are kept after fork and exec. Return 0, or EINVAL if the process already uses more.
+pub fn sys_set_memory_limits(resident: usize, mapped: usize) -> SyscallResult {
+    let task = get_current_task();
+    let mut inner = task.inner.exclusive_access();
+    if inner.address_space.set_limits(MemoryLimits { resident, mapped }) {
+        Ok(0)
+    } else {
+        Err(Errno::EINVAL)
+    }
+}
+
+// limit the size the user stack of the current process can grow to, in bytes, no more than the default limit.
+// the limit is kept after fork and exec. Return 0, or EINVAL if it is below the initial stack or the stack is
+// already larger.
+pub fn sys_set_stack_limit(limit: usize) -> SyscallResult {
+    if limit < USER_STACK_SIZE {
+        return Err(Errno::EINVAL);
+    }
+    let task = get_current_task();
+    let mut inner = task.inner.exclusive_access();
+    if inner.address_space.set_stack_limit(limit) {
+        Ok(0)
+    } else {
+        Err(Errno::EINVAL)
+    }
+}
@@ -1,40 +1,79 @@
+const SYSCALL_DUP: usize = 23;
+const SYSCALL_DUP2: usize = 24; // dup3 in linux, without its flags
+const SYSCALL_CLOSE: usize = 57;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
 const SYSCALL_EXIT: usize = 93;
 const SYSCALL_YIELD: usize = 124;
+const SYSCALL_SET_MEMORY_LIMITS: usize = 164;
+const SYSCALL_MEMORY_USAGE: usize = 165;
+const SYSCALL_SET_STACK_LIMIT: usize = 166; // not in linux, which has it in setrlimit
 const SYSCALL_GET_TIME: usize = 169;
 const SYSCALL_GETPID: usize = 172;
+const SYSCALL_SHM_CREATE: usize = 194;
+const SYSCALL_SHM_DESTROY: usize = 195;
+const SYSCALL_SHM_MAP: usize = 196;
+const SYSCALL_SHM_UNMAP: usize = 197;
 const SYSCALL_SBRK: usize = 214;
+const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_FORK: usize = 220;
 const SYSCALL_EXEC: usize = 221;
+const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MMAP_FILE: usize = 223; // not in linux, files are named by path until there are file descriptors
+const SYSCALL_MPROTECT: usize = 226;
+const SYSCALL_MSYNC: usize = 227;
 const SYSCALL_WAITPID: usize = 260;
 const SERVICE_PROCESS_MANAGER: usize = 511;
 
 
 mod file_system;
+mod memory;
 mod process;
 pub mod process_manager;
 
 use file_system::*;
+use memory::*;
 use process::*;
 use process_manager::*;
+use crate::mem::user_ptr::UserPtr;
+use errno::{Errno, SyscallResult};
 
-// call the corresponding syscall function according to the syscall_id
-pub fn syscall(syscall_id: usize, args: [usize; 3]) -> isize { 
-    match syscall_id {
+// call the corresponding syscall function according to the syscall_id, with the arguments in a0-a5.
+// return the values for a0 and a1, a1 is None for syscalls with one return value and is kept as it was
+pub fn syscall(syscall_id: usize, args: [usize; 6]) -> (isize, Option<usize>) {
+    let result: SyscallResult = match syscall_id {
         SYSCALL_YIELD => sys_yield(),
         SYSCALL_EXIT => sys_exit(args[0] as i32),
         SYSCALL_GET_TIME => sys_get_time(),
-        SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
-        SYSCALL_READ => sys_read(args[0], args[1] as *mut u8, args[2]),
+        SYSCALL_WRITE => sys_write(args[0], args[1], args[2]),
+        SYSCALL_READ => sys_read(args[0], args[1], args[2]),
+        SYSCALL_CLOSE => sys_close(args[0]),
+        SYSCALL_DUP => sys_dup(args[0]),
+        SYSCALL_DUP2 => sys_dup2(args[0], args[1]),
         SYSCALL_GETPID => sys_get_pid(),
         SYSCALL_SBRK => sys_sbrk(args[0] as i32),
+        SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2]),
+        SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
+        SYSCALL_MPROTECT => sys_mprotect(args[0], args[1], args[2]),
+        SYSCALL_MMAP_FILE => sys_mmap_file(UserPtr::new(args[0]), args[1], args[2]),
+        SYSCALL_MSYNC => sys_msync(args[0], args[1]),
+        SYSCALL_SET_MEMORY_LIMITS => sys_set_memory_limits(args[0], args[1]),
+        SYSCALL_MEMORY_USAGE => sys_memory_usage(UserPtr::new(args[0])),
+        SYSCALL_SET_STACK_LIMIT => sys_set_stack_limit(args[0]),
+        SYSCALL_SHM_CREATE => sys_shm_create(UserPtr::new(args[0]), args[1]),
+        SYSCALL_SHM_DESTROY => sys_shm_destroy(args[0]),
+        SYSCALL_SHM_MAP => sys_shm_map(args[0], args[1], args[2]),
+        SYSCALL_SHM_UNMAP => sys_shm_unmap(args[0]),
         SYSCALL_FORK => sys_fork(),
-        SYSCALL_EXEC => sys_exec(args[0] as *const u8),
-        SYSCALL_WAITPID => sys_waitpid(args[0] as isize, args[1] as *mut i32),
-        SERVICE_PROCESS_MANAGER => process_manager_syscall(args[0] as isize, args[1], args[2] as *mut i32),
-        _ => panic!("Unsupported syscall_id: {}", syscall_id),
-    }
+        SYSCALL_EXEC => sys_exec(UserPtr::new(args[0]), UserPtr::new(args[1]), UserPtr::new(args[2])),
+        SYSCALL_WAITPID => sys_waitpid(args[0] as isize, UserPtr::new(args[1]), UserPtr::new(args[2])),
+        SERVICE_PROCESS_MANAGER => {
+            let (service_id, arg) = process_manager_syscall(args[0] as isize, args[1]);
+            return (service_id as isize, Some(arg as usize));
+        }
+        _ => Err(Errno::ENOSYS),
+    };
+    (Errno::encode(result), None)
 }
 
 
@@ -1,17 +1,31 @@
 // syscalss about process management
 
-use crate::process::scheduler::{change_program_brk, exit_current_and_run_next, fork_process, waitpid_process, get_current_satp, get_current_task, get_pid, suspend_current_and_run_next};
-use crate::process::task_manager::{add_task, remove_task};
+use crate::process::scheduler::{cancel_fork_process, change_program_brk, exit_current_and_run_next, fork_process, waitpid_process, get_current_task, get_pid, suspend_current_and_run_next};
+use crate::process::task_manager::{add_task, get_task, remove_task};
 use crate::time::get_time_ms;
-use crate::mem::page_table;
+use crate::mem::address_space::{AddressSpace, MemoryUsage};
+use crate::mem::user_ptr::UserPtr;
 use crate::process::loader::open_app_file;
-use crate::config::{GREEN, RESET};
+use crate::config::{GREEN, RED, RESET, USER_ARG_MAX};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use errno::{Errno, SyscallResult};
 
-pub fn sys_fork() -> isize {
+// return the pid of child process to parent, 0 to child, or ENOMEM if frames run out.
+pub fn sys_fork() -> SyscallResult {
     println!("{}[kernel] fork a new process{}", GREEN, RESET);
     let current_task = get_current_task();
     let child_pid = fork_process();
-    let child_task = current_task.fork(child_pid);
+    let child_task = match current_task.fork(child_pid) {
+        Ok(child_task) => child_task,
+        Err(_) => {
+            println!("{}[kernel] fork failed: out of memory{}", RED, RESET);
+            // the child has been recorded by PM, remove it
+            cancel_fork_process(child_pid);
+            return Err(Errno::ENOMEM);
+        }
+    };
 
     // set the return value of child process to 0
     let child_inner = child_task.inner.exclusive_access();
@@ -22,37 +36,74 @@ pub fn sys_fork() -> isize {
     // add new task to scheduler
     add_task(child_pid, child_task);
 
-    return child_pid as isize;
+    return Ok(child_pid);
 }
 
-// return 0 if success, -1 if no such file.
-pub fn sys_exec(path: *const u8) -> isize {
-    let satp = get_current_satp();
-    let app_name = page_table::get_string(satp, path);
-    if let Some(data) = open_app_file(app_name.as_str()) {
-        let task = get_current_task();
-        println!("{}[kernel] exec app: {}, pid = {}{}", GREEN, app_name, task.pid, RESET);
-        task.exec(data);
-        0
-    } else {
-        -1
+// read the NULL-terminated array of strings at ptr, a null ptr is an empty array. size adds up the bytes the
+// strings and their pointers take on the new stack, E2BIG if it is beyond USER_ARG_MAX
+fn read_user_strings(space: &mut AddressSpace, ptr: UserPtr<usize>, size: &mut usize) -> Result<Vec<String>, Errno> {
+    let mut strings = Vec::new();
+    if ptr.is_null() {
+        return Ok(strings);
+    }
+    loop {
+        let addr = ptr.add(strings.len()).read(space)?;
+        if addr == 0 {
+            return Ok(strings);
+        }
+        let string = UserPtr::<u8>::new(addr).read_string(space)?;
+        *size += string.len() + 1 + size_of::<usize>();
+        if *size > USER_ARG_MAX {
+            return Err(Errno::E2BIG);
+        }
+        strings.push(string);
     }
 }
 
-// no such child process -> -1.
-// child process is still running -> -2.
-// else -> pid, and exit code of child process is kept in exit_code_ptr.
-pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
+// run the program at path, with argv and envp, both NULL-terminated arrays of strings or null for none.
+// return 0 if success. If it fails, the old program goes on running, and the error is EFAULT if the path or the
+// arrays are not readable, E2BIG if the arrays are too long, ENOENT if there is no such file, ENOEXEC if the elf
+// is rejected, or ENOMEM if frames run out.
+pub fn sys_exec(path: UserPtr<u8>, argv: UserPtr<usize>, envp: UserPtr<usize>) -> SyscallResult {
+    let task = get_current_task();
+    let mut inner = task.inner.exclusive_access();
+    let app_name = path.read_string(&mut inner.address_space)?;
+    let mut size = 0;
+    let argv = read_user_strings(&mut inner.address_space, argv, &mut size)?;
+    let envp = read_user_strings(&mut inner.address_space, envp, &mut size)?;
+    drop(inner);
+    let data = open_app_file(app_name.as_str()).ok_or(Errno::ENOENT)?;
+    println!("{}[kernel] exec app: {}, pid = {}{}", GREEN, app_name, task.pid, RESET);
+    if let Err(err) = task.exec(data, &argv, &envp) {
+        println!("{}[kernel] exec failed: {:?}{}", RED, err, RESET);
+        return Err(err.into());
+    }
+    Ok(0)
+}
+
+// no such child process -> ECHILD.
+// child process is still running -> EAGAIN.
+// else -> pid, and exit code of child process is kept in exit_code_ptr. If usage_ptr is not null,
+// the memory usage of the child is kept in it, its peak is the largest resident memory of the child's last program.
+pub fn sys_waitpid(pid: isize, exit_code_ptr: UserPtr<i32>, usage_ptr: UserPtr<MemoryUsage>) -> SyscallResult {
     let current_task = get_current_task();
-    let (pid, exit_code) = waitpid_process(pid);
-    let cur_task_inner = current_task.inner.exclusive_access();
-    if pid != -1 && pid != -2{
-        page_table::write_into(cur_task_inner.address_space.get_satp(), exit_code_ptr, exit_code as i32);
-        // remove task to release resources
-        remove_task(pid as usize);
+    // the process manager answers -1 for no such child, and -2 for a running one
+    let (pid, exit_code) = match waitpid_process(pid) {
+        (-1, _) => return Err(Errno::ECHILD),
+        (-2, _) => return Err(Errno::EAGAIN),
+        (pid, exit_code) => (pid as usize, exit_code),
+    };
+    let mut cur_task_inner = current_task.inner.exclusive_access();
+    // the exit code and usage are dropped if they can not be written
+    let _ = exit_code_ptr.write(&mut cur_task_inner.address_space, &(exit_code as i32));
+    if !usage_ptr.is_null() {
+        let usage = get_task(pid).unwrap().inner.exclusive_access().memory_usage();
+        let _ = usage_ptr.write(&mut cur_task_inner.address_space, &usage);
     }
+    // remove task to release resources
+    remove_task(pid);
     drop(cur_task_inner);
-    return pid;
+    return Ok(pid);
 }
 
 pub fn sys_exit(exit_code: i32) -> ! {
@@ -61,24 +112,21 @@ pub fn sys_exit(exit_code: i32) -> ! {
 }
 
 
-pub fn sys_yield() -> isize {
+pub fn sys_yield() -> SyscallResult {
     suspend_current_and_run_next();
-    0
+    Ok(0)
 }
 
-pub fn sys_get_time() -> isize {
-    get_time_ms() as isize
+pub fn sys_get_time() -> SyscallResult {
+    Ok(get_time_ms())
 }
 
-pub fn sys_sbrk(size: i32) -> isize {
-    if let Some(old_brk) = change_program_brk(size) {
-        old_brk as isize
-    } else {
-        -1
-    }
+// return the old program break, or ENOMEM if the heap can not be changed to the size
+pub fn sys_sbrk(size: i32) -> SyscallResult {
+    change_program_brk(size).ok_or(Errno::ENOMEM)
 }
 
-pub fn sys_get_pid() -> isize {
-    get_pid() as isize
+pub fn sys_get_pid() -> SyscallResult {
+    Ok(get_pid())
 }
 
@@ -1,10 +1,8 @@
-use super::sys_yield;
 use crate::process::{
     context::TaskContext,
-    scheduler::switch_in,
+    scheduler::{suspend_current_and_run_next, switch_in},
     task_manager::TaskControlBlock,
 };
-use crate::{mem::page_table, process::scheduler::get_current_task};
 use alloc::sync::Arc;
 use lazy_static::lazy_static;
 use sync::UPSafeCell;
@@ -15,6 +13,7 @@ const PM_WAITPID: usize = 3;
 const PM_SUSPEND_AND_RUN_NEXT: usize = 4;
 const PM_EXIT_AND_RUN_NEXT: usize = 5;
 const PM_FETCH: usize = 6;
+const PM_CANCEL_FORK: usize = 7;
 const BUSY: usize = 1;
 const IDLE: usize = 0;
 
@@ -48,7 +47,9 @@ impl PmService {
     }
 }
 
-pub fn process_manager_syscall(result1: isize, result2: usize, arg: *mut i32) -> isize {
+// give the results of the last service to the task that asked for it, and wait for the next service.
+// return the id of the service and its argument, for a0 and a1 of the process manager
+pub fn process_manager_syscall(result1: isize, result2: usize) -> (usize, i32) {
     loop {
         let mut pm_service = PM_SERVICE.exclusive_access();
         if pm_service.service_status == BUSY {
@@ -67,25 +68,13 @@ pub fn process_manager_syscall(result1: isize, result2: usize, arg: *mut i32) ->
             match serice_id {
                 PM_NONE => {
                     drop(pm_service);
-                    sys_yield();
+                    suspend_current_and_run_next();
                 }
-                PM_FORK | PM_SUSPEND_AND_RUN_NEXT | PM_FETCH => {
+                PM_FORK | PM_SUSPEND_AND_RUN_NEXT | PM_FETCH | PM_WAITPID | PM_EXIT_AND_RUN_NEXT | PM_CANCEL_FORK => {
                     pm_service.service_status = BUSY;
+                    let arg = pm_service.arg;
                     drop(pm_service);
-                    return serice_id as isize;
-                }
-                PM_WAITPID | PM_EXIT_AND_RUN_NEXT => {
-                    pm_service.service_status = BUSY;
-                    let current_task = get_current_task();
-                    let cur_task_inner = current_task.inner.exclusive_access();
-                    page_table::write_into(
-                        cur_task_inner.address_space.get_satp(),
-                        arg,
-                        pm_service.arg,
-                    );
-                    drop(pm_service);
-                    drop(cur_task_inner);
-                    return serice_id as isize;
+                    return (serice_id, arg);
                 }
                 _ => {
                     panic!("Unknown service id: {}", serice_id);
@@ -1,3 +1,4 @@
+use crate::mem::asid::asid_supported;
 use riscv::register::sstatus;
 
 #[repr(C)]
@@ -8,6 +9,7 @@ pub struct TrapContext {
     pub kernel_satp: usize,
     pub kernel_sp: usize,    // the va of the current app's kernel stack sp in kernel space
     pub trap_handler: usize, // the va of the trap handler's entry in kernel space
+    pub flush_tlb: usize,    // 1 if the hardware has no ASID, then TLB is flushed when trapping into kernel
 }
 
 impl TrapContext {
@@ -28,6 +30,7 @@ impl TrapContext {
             kernel_satp,
             kernel_sp,
             trap_handler,
+            flush_tlb: !asid_supported() as usize,
         };
         trap_context.x[2] = sp;
         return trap_context;
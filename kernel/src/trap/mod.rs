@@ -3,7 +3,8 @@ mod context;
 use crate::{
     config::{RED, RESET, TRAMPOLINE_START_VA, TRAP_CONTEXT_START_VA},
     syscall::syscall,
-    process::scheduler::{exit_current_and_run_next, get_current_satp, get_current_trap_ctx, suspend_current_and_run_next},
+    process::scheduler::{exit_current_and_run_next, get_current_satp, get_current_trap_ctx, get_pid, handle_page_fault, suspend_current_and_run_next},
+    mem::{address_space::{AccessType, PageFaultError}, asid::asid_supported},
 };
 pub use context::TrapContext;
 use core::arch::{asm, global_asm};
@@ -40,22 +41,51 @@ pub fn trap_handler() -> ! {
         Trap::Exception(Exception::UserEnvCall) => {
             // set the pc to the next instruction of ecall
             trap_ctx.sepc += 4; 
-            // set a0 = result from syscall
-            let result = syscall(
+            // set a0 (and a1) = results from syscall, the arguments are in a0-a5
+            let (a0, a1) = syscall(
                 trap_ctx.x[17],
-                [trap_ctx.x[10], trap_ctx.x[11], trap_ctx.x[12]],
-            ) as usize; 
+                [trap_ctx.x[10], trap_ctx.x[11], trap_ctx.x[12], trap_ctx.x[13], trap_ctx.x[14], trap_ctx.x[15]],
+            );
 
             // trap context will change after exec 
             let current_trap_ctx = get_current_trap_ctx();
-            current_trap_ctx.x[10] = result;
+            current_trap_ctx.x[10] = a0 as usize;
+            if let Some(a1) = a1 {
+                current_trap_ctx.x[11] = a1;
+            }
         }
-        Trap::Exception(Exception::StoreFault)
+        Trap::Exception(Exception::LoadPageFault)
         | Trap::Exception(Exception::StorePageFault)
-        | Trap::Exception(Exception::LoadFault)
-        | Trap::Exception(Exception::LoadPageFault)
-        | Trap::Exception(Exception::InstructionFault)
         | Trap::Exception(Exception::InstructionPageFault) => {
+            let access = match scause.cause() {
+                Trap::Exception(Exception::LoadPageFault) => AccessType::Load,
+                Trap::Exception(Exception::StorePageFault) => AccessType::Store,
+                _ => AccessType::Execute,
+            };
+            // if the fault is resolved, return and execute the instruction again
+            match handle_page_fault(stval, access) {
+                Ok(()) => {}
+                Err(PageFaultError::StackOverflow) => {
+                    println!("{}[kernel] Stack overflow in application, pid = {}, bad addr = {:#x}, bad instruction = {:#x}, kernel killed it.{}", RED, get_pid(), stval, trap_ctx.sepc, RESET);
+                    exit_current_and_run_next(-2);
+                }
+                Err(PageFaultError::OutOfMemory) => {
+                    println!("{}[kernel] Out of memory in application, pid = {}, bad addr = {:#x}, bad instruction = {:#x}, kernel killed it.{}", RED, get_pid(), stval, trap_ctx.sepc, RESET);
+                    exit_current_and_run_next(-2);
+                }
+                Err(PageFaultError::MemoryLimit) => {
+                    println!("{}[kernel] Memory limit exceeded in application, pid = {}, bad addr = {:#x}, bad instruction = {:#x}, kernel killed it.{}", RED, get_pid(), stval, trap_ctx.sepc, RESET);
+                    exit_current_and_run_next(-2);
+                }
+                Err(PageFaultError::SegmentationFault) => {
+                    println!("{}[kernel] PageFault in application, bad addr = {:#x}, bad instruction = {:#x}, kernel killed it.{}", RED, stval, trap_ctx.sepc, RESET);
+                    exit_current_and_run_next(-2);
+                }
+            }
+        }
+        Trap::Exception(Exception::StoreFault)
+        | Trap::Exception(Exception::LoadFault)
+        | Trap::Exception(Exception::InstructionFault) => {
             println!("{}[kernel] PageFault in application, bad addr = {:#x}, bad instruction = {:#x}, kernel killed it.{}", RED, stval, trap_ctx.sepc, RESET);
             exit_current_and_run_next(-2);
         }
@@ -108,6 +138,7 @@ pub fn trap_return() -> ! {
             return_va = in(reg) return_va,
             in("a0") trap_ctx_va,      // a0 = virt addr of Trap Context
             in("a1") user_satp,        // a1 = phy addr of usr page table
+            in("a2") !asid_supported() as usize, // a2 = whether to flush TLB
             options(noreturn)
         );
     }
@@ -39,21 +39,28 @@ __user_trap:
     ld t0, 34*8(sp)
     # load trap_handler into t1
     ld t1, 36*8(sp)
+    # load flush_tlb into t2
+    ld t2, 37*8(sp)
     # sp -> kernel_sp in kernel space
     ld sp, 35*8(sp)
-    # switch to kernel space
+    # switch to kernel space. TLB entries are tagged with ASID, only flush them if the hardware has no ASID
     csrw satp, t0
+    beqz t2, 1f
     sfence.vma
+1:
     # jump to trap_handler
     jr t1
 
     # When back from S mode, we have args:
     # a0 = trap context address of app
     # a1 = user space satp
+    # a2 = whether to flush TLB
 __user_return:
     # switch to user space
     csrw satp, a1
+    beqz a2, 2f
     sfence.vma
+2:
     # save trap context in user space in sscratch
     csrw sscratch, a0
     # sp -> trap context in user space(after allocated), sscratch -> user stack sp
@@ -27,10 +27,15 @@ pub fn console_putchar(c: usize) {
     write_reg!(THR, c as u8);
 }
 
+// whether a byte has been received and not read yet
+pub fn console_has_char() -> bool {
+    let tmp = 0x01;
+    return read_reg!(LSR) & tmp != 0;
+}
+
 // return zero if no more bytes are present.
 pub fn console_getchar() -> u8 {
-    let tmp = 0x01;
-    if read_reg!(LSR) & tmp == 0{
+    if !console_has_char() {
         return 0;
     }
     return read_reg!(RBR);
@@ -0,0 +1,222 @@
+// a polling driver of the virtio block device of qemu, on the mmio transport. Only one request is in flight,
+// which is enough for swapping pages. Both the legacy (version 1) and the modern (version 2) interface are
+// supported. The kernel space maps physical memory identically, so buffers are given to the device by their
+// virtual addresses.
+
+use crate::config::PAGE_SIZE;
+use crate::mem::frame_allocator::{alloc_contiguous_frames, ContiguousFrameTracker};
+use crate::mem::page_table::PhyAddr;
+use core::mem::size_of;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+
+pub const SECTOR_SIZE: usize = 512;
+
+// mmio registers
+const MAGIC_VALUE: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const DEVICE_FEATURES: usize = 0x010;
+const DEVICE_FEATURES_SEL: usize = 0x014;
+const DRIVER_FEATURES: usize = 0x020;
+const DRIVER_FEATURES_SEL: usize = 0x024;
+const GUEST_PAGE_SIZE: usize = 0x028; // legacy
+const QUEUE_SEL: usize = 0x030;
+const QUEUE_NUM_MAX: usize = 0x034;
+const QUEUE_NUM: usize = 0x038;
+const QUEUE_ALIGN: usize = 0x03c; // legacy
+const QUEUE_PFN: usize = 0x040; // legacy
+const QUEUE_READY: usize = 0x044;
+const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
+const STATUS: usize = 0x070;
+const QUEUE_DESC_LOW: usize = 0x080;
+const QUEUE_DESC_HIGH: usize = 0x084;
+const QUEUE_DRIVER_LOW: usize = 0x090;
+const QUEUE_DRIVER_HIGH: usize = 0x094;
+const QUEUE_DEVICE_LOW: usize = 0x0a0;
+const QUEUE_DEVICE_HIGH: usize = 0x0a4;
+const CONFIG_CAPACITY: usize = 0x100; // the size of the disk in sectors, a 64-bit value
+
+const MAGIC: u32 = 0x7472_6976; // "virt"
+const BLOCK_DEVICE: u32 = 2;
+
+// device status bits
+const ACKNOWLEDGE: u32 = 1;
+const DRIVER: u32 = 2;
+const DRIVER_OK: u32 = 4;
+const FEATURES_OK: u32 = 8;
+
+const VIRTIO_F_VERSION_1: u32 = 1 << 0; // bit 32 of the features, in their second word
+
+const QUEUE_SIZE: usize = 4; // a request takes 3 descriptors
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2; // the device writes the buffer
+
+const REQUEST_IN: u32 = 0; // read from the disk
+const REQUEST_OUT: u32 = 1; // write to the disk
+const STATUS_OK: u8 = 0;
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct AvailRing {
+    flags: u16,
+    idx: u16,
+    ring: [u16; QUEUE_SIZE],
+}
+
+#[repr(C)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+#[repr(C)]
+struct UsedRing {
+    flags: u16,
+    idx: u16,
+    ring: [UsedElem; QUEUE_SIZE],
+}
+
+#[repr(C)]
+struct RequestHeader {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+// the queue takes two pages, as the legacy interface wants: the descriptors and the available ring in the first
+// page, and the used ring in the second. The request header and its status follow the available ring.
+const AVAIL_OFFSET: usize = QUEUE_SIZE * size_of::<Descriptor>();
+const HEADER_OFFSET: usize = 0x800;
+const STATUS_OFFSET: usize = HEADER_OFFSET + size_of::<RequestHeader>();
+const USED_OFFSET: usize = PAGE_SIZE;
+
+pub struct VirtioBlock {
+    base: usize,
+    queue: ContiguousFrameTracker,
+    used_idx: u16,
+    capacity: usize, // in sectors
+}
+
+impl VirtioBlock {
+    // probe the mmio device at base, return None if it is not a block device, or it can not be set up
+    pub fn new(base: usize) -> Option<Self> {
+        let read = |offset: usize| unsafe { read_volatile((base + offset) as *const u32) };
+        let write = |offset: usize, value: u32| unsafe { write_volatile((base + offset) as *mut u32, value) };
+        let version = read(VERSION);
+        if read(MAGIC_VALUE) != MAGIC || !(version == 1 || version == 2) || read(DEVICE_ID) != BLOCK_DEVICE {
+            return None;
+        }
+        write(STATUS, 0); // reset
+        write(STATUS, ACKNOWLEDGE);
+        write(STATUS, ACKNOWLEDGE | DRIVER);
+        // no optional feature is used, the modern interface needs VIRTIO_F_VERSION_1
+        write(DRIVER_FEATURES_SEL, 0);
+        write(DRIVER_FEATURES, 0);
+        if version == 2 {
+            write(DEVICE_FEATURES_SEL, 1);
+            if read(DEVICE_FEATURES) & VIRTIO_F_VERSION_1 == 0 {
+                return None;
+            }
+            write(DRIVER_FEATURES_SEL, 1);
+            write(DRIVER_FEATURES, VIRTIO_F_VERSION_1);
+            write(STATUS, ACKNOWLEDGE | DRIVER | FEATURES_OK);
+            if read(STATUS) & FEATURES_OK == 0 {
+                return None;
+            }
+        } else {
+            write(GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        }
+
+        write(QUEUE_SEL, 0);
+        if (read(QUEUE_NUM_MAX) as usize) < QUEUE_SIZE {
+            return None;
+        }
+        let queue = alloc_contiguous_frames(1)?;
+        let queue_pa = PhyAddr::from(queue.ppn).0;
+        write(QUEUE_NUM, QUEUE_SIZE as u32);
+        if version == 2 {
+            for (low, high, pa) in [
+                (QUEUE_DESC_LOW, QUEUE_DESC_HIGH, queue_pa),
+                (QUEUE_DRIVER_LOW, QUEUE_DRIVER_HIGH, queue_pa + AVAIL_OFFSET),
+                (QUEUE_DEVICE_LOW, QUEUE_DEVICE_HIGH, queue_pa + USED_OFFSET),
+            ] {
+                write(low, pa as u32);
+                write(high, (pa >> 32) as u32);
+            }
+            write(QUEUE_READY, 1);
+        } else {
+            write(QUEUE_ALIGN, PAGE_SIZE as u32);
+            write(QUEUE_PFN, queue.ppn.0 as u32);
+        }
+        let status = if version == 2 { FEATURES_OK } else { 0 };
+        write(STATUS, ACKNOWLEDGE | DRIVER | status | DRIVER_OK);
+
+        let capacity = read(CONFIG_CAPACITY) as usize | (read(CONFIG_CAPACITY + 4) as usize) << 32;
+        return Some(Self { base, queue, used_idx: 0, capacity });
+    }
+
+    // the size of the disk in bytes
+    pub fn size(&self) -> usize {
+        return self.capacity * SECTOR_SIZE;
+    }
+
+    pub fn read(&mut self, sector: usize, buf: &mut [u8]) -> bool {
+        return self.request(REQUEST_IN, sector, buf.as_mut_ptr() as usize, buf.len());
+    }
+
+    pub fn write(&mut self, sector: usize, buf: &[u8]) -> bool {
+        return self.request(REQUEST_OUT, sector, buf.as_ptr() as usize, buf.len());
+    }
+
+    // send a request for len bytes at sector, and wait for the device to finish it. Return false if it fails.
+    fn request(&mut self, kind: u32, sector: usize, buf: usize, len: usize) -> bool {
+        if len % SECTOR_SIZE != 0 || sector + len / SECTOR_SIZE > self.capacity {
+            return false;
+        }
+        let queue = PhyAddr::from(self.queue.ppn).0;
+        let descriptors = queue as *mut Descriptor;
+        let avail = (queue + AVAIL_OFFSET) as *mut AvailRing;
+        let used = (queue + USED_OFFSET) as *const UsedRing;
+        let header = (queue + HEADER_OFFSET) as *mut RequestHeader;
+        let status = (queue + STATUS_OFFSET) as *mut u8;
+        unsafe {
+            write_volatile(header, RequestHeader { kind, reserved: 0, sector: sector as u64 });
+            write_volatile(status, 0xff);
+            let data_flags = if kind == REQUEST_IN { DESC_F_WRITE } else { 0 };
+            write_volatile(
+                descriptors,
+                Descriptor { addr: header as u64, len: size_of::<RequestHeader>() as u32, flags: DESC_F_NEXT, next: 1 },
+            );
+            write_volatile(
+                descriptors.add(1),
+                Descriptor { addr: buf as u64, len: len as u32, flags: data_flags | DESC_F_NEXT, next: 2 },
+            );
+            write_volatile(descriptors.add(2), Descriptor { addr: status as u64, len: 1, flags: DESC_F_WRITE, next: 0 });
+
+            // publish the chain, then tell the device
+            let idx = read_volatile(&(*avail).idx);
+            write_volatile(&mut (*avail).ring[idx as usize % QUEUE_SIZE], 0);
+            fence(Ordering::SeqCst);
+            write_volatile(&mut (*avail).idx, idx.wrapping_add(1));
+            fence(Ordering::SeqCst);
+            write_volatile((self.base + QUEUE_NOTIFY) as *mut u32, 0);
+
+            while read_volatile(&(*used).idx) == self.used_idx {}
+            fence(Ordering::SeqCst);
+            self.used_idx = self.used_idx.wrapping_add(1);
+            let interrupt = read_volatile((self.base + INTERRUPT_STATUS) as *const u32);
+            write_volatile((self.base + INTERRUPT_ACK) as *mut u32, interrupt);
+            return read_volatile(status) == STATUS_OK;
+        }
+    }
+}
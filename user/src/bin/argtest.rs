@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{args, envs, execve, fork, syscall6, waitpid, Errno};
+
+const SYSCALL_EXEC: usize = 221;
+
+const ARGV: [&str; 4] = ["argtest\0", "a\0", "hello world\0", "\0"];
+const ENVP: [&str; 2] = ["KEY=value\0", "EMPTY=\0"];
+
+fn without_nul(strings: &[&'static str]) -> Vec<&'static str> {
+    strings.iter().map(|&string| &string[..string.len() - 1]).collect()
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let argv: Vec<&str> = args().collect();
+    assert_eq!(argv[0], "argtest");
+    if argv.len() > 1 {
+        // run by the test below
+        assert_eq!(argv, without_nul(&ARGV));
+        assert_eq!(envs().collect::<Vec<_>>(), without_nul(&ENVP));
+        return 0;
+    }
+
+    // the program gets the arguments and the environment
+    let pid = fork().unwrap();
+    if pid == 0 {
+        execve(ARGV[0], &ARGV, &ENVP);
+        panic!("unreachable!");
+    }
+    let mut exit_code = -1;
+    assert_eq!(waitpid(pid, &mut exit_code), Ok(pid));
+    assert_eq!(exit_code, 0);
+
+    // too long, or not readable
+    let long = "a".repeat(1023) + "\0";
+    let many = [long.as_str(); 20];
+    assert_eq!(execve(ARGV[0], &many, &[]), Errno::E2BIG);
+    assert_eq!(execve(ARGV[0], &[ARGV[0]], &many), Errno::E2BIG);
+    let exec = |argv: usize| Errno::decode(syscall6(SYSCALL_EXEC, [ARGV[0].as_ptr() as usize, argv, 0, 0, 0, 0]).0);
+    assert_eq!(exec(8), Err(Errno::EFAULT));
+    assert_eq!(exec([8usize, 0].as_ptr() as usize), Err(Errno::EFAULT));
+    println!("argtest passed!");
+    0
+}
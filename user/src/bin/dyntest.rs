@@ -0,0 +1,105 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::alloc::{GlobalAlloc, Layout};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::fmt::{self, Write};
+use core::hint::black_box;
+
+// built as a position independent executable that needs libuser, see the makefile.
+// the kernel starts the dynamic loader in its PT_INTERP (ld), which maps libuser and links the calls below to it.
+// the program does not link user_lib itself, so it has its own entry, heap and panic handler on top of libuser.
+
+const PIE_BASE: usize = 0x1_0000_0000;
+const MMAP_BASE: usize = 0x20_0000_0000; // the loader maps libuser with mmap
+
+extern "C" {
+    fn user_write(fd: usize, buf: *const u8, len: usize) -> isize;
+    fn user_exit(exit_code: i32) -> !;
+    fn user_getpid() -> isize;
+    fn user_alloc(size: usize, align: usize) -> *mut u8;
+    fn user_dealloc(ptr: *mut u8, size: usize, align: usize);
+}
+
+// the loader jumps here with the stack the kernel set up. user_start(sp, main) of libuser reads the arguments
+// from it, sets up the heap and exits with what main returns
+global_asm!(
+    ".section .text.entry, \"ax\"",
+    ".globl _start",
+    "_start:",
+    "    mv a0, sp",
+    "    lla a1, main",
+    "    tail user_start",
+);
+
+// the heap of libuser
+struct SharedHeap;
+
+unsafe impl GlobalAlloc for SharedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        user_alloc(layout.size(), layout.align())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        user_dealloc(ptr, layout.size(), layout.align())
+    }
+}
+
+#[global_allocator]
+static HEAP: SharedHeap = SharedHeap;
+
+struct Stdout;
+
+impl Write for Stdout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match unsafe { user_write(1, s.as_ptr(), s.len()) } {
+            len if len == s.len() as isize => Ok(()),
+            _ => Err(fmt::Error),
+        }
+    }
+}
+
+#[panic_handler]
+fn panic_handler(panic_info: &core::panic::PanicInfo) -> ! {
+    let _ = writeln!(Stdout, "Panicked: {}", panic_info);
+    unsafe { user_exit(-1) }
+}
+
+// pointers in the program are relocated by the loader too, the kernel leaves a program with an interpreter to it
+static NAMES: [&str; 3] = ["zero", "one", "two"];
+static FUNCS: [fn(usize) -> usize; 2] = [double, square];
+
+fn double(x: usize) -> usize {
+    x * 2
+}
+
+fn square(x: usize) -> usize {
+    x * x
+}
+
+#[no_mangle]
+extern "C" fn main() -> i32 {
+    // the program is at its own base, and the functions of libuser are in the library
+    let main_addr = black_box(main as usize);
+    assert!((PIE_BASE..MMAP_BASE).contains(&main_addr));
+    for addr in [user_write as usize, user_getpid as usize, user_alloc as usize] {
+        assert!(black_box(addr) >= MMAP_BASE);
+    }
+    assert_eq!(*black_box(&NAMES), ["zero", "one", "two"]);
+    assert_eq!(black_box(&FUNCS).map(|f| f(5)), [10, 25]);
+
+    // calls go to libuser through the PLT
+    assert!(unsafe { user_getpid() } > 0);
+    let squares: Vec<usize> = (0..1000).map(square).collect();
+    assert_eq!(squares.iter().sum::<usize>(), 332_833_500);
+    let boxed = Box::new([7u8; 0x2000]);
+    assert!(boxed.iter().all(|&byte| byte == 7));
+    drop((squares, boxed));
+
+    writeln!(Stdout, "dyntest passed!").unwrap();
+    0
+}
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exec, getpid, mmap, read, sbrk, syscall6, wait, write, Errno, PROT_READ};
+
+// a syscall the user library does not wrap, or with arguments it would not pass
+fn raw_syscall(id: usize, args: [usize; 3]) -> Result<usize, Errno> {
+    Errno::decode(syscall6(id, [args[0], args[1], args[2], 0, 0, 0]).0)
+}
+
+const SYSCALL_READ: usize = 63;
+const SYSCALL_WRITE: usize = 64;
+const SYSCALL_GETPID: usize = 172;
+
+static READ_ONLY: [u8; 4] = *b"ro\n\0";
+static LONG_PATH: [u8; 5000] = [b'a'; 5000]; // no NUL within the bound of the kernel
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // a bad syscall fails, and the kernel keeps running
+    assert_eq!(raw_syscall(1000, [0; 3]), Err(Errno::ENOSYS));
+    assert_eq!(write(5, b"lost\n"), Err(Errno::EBADF));
+    assert_eq!(read(5, &mut [0u8; 1]), Err(Errno::EBADF));
+    assert_eq!(raw_syscall(SYSCALL_WRITE, [1, 0, 8]), Err(Errno::EFAULT));
+    assert_eq!(raw_syscall(SYSCALL_READ, [0, 0, 1]), Err(Errno::EFAULT));
+    assert_eq!(raw_syscall(SYSCALL_READ, [0, READ_ONLY.as_ptr() as usize, 1]), Err(Errno::EFAULT));
+    assert_eq!(raw_syscall(SYSCALL_WRITE, [1, usize::MAX - 4, 8]), Err(Errno::EFAULT));
+    assert_eq!(read(0, &mut []), Ok(0));
+    assert_eq!(write(1, b"write passed\n"), Ok(13));
+
+    // six arguments, a1 is kept by syscalls with one return value
+    assert_eq!(Errno::decode(syscall6(1000, [1, 2, 3, 4, 5, 6]).0), Err(Errno::ENOSYS));
+    let (pid, a1) = syscall6(SYSCALL_GETPID, [0, 42, 0, 0, 0, 0]);
+    assert_eq!((pid as usize, a1), (getpid() as usize, 42));
+
+    assert_eq!(exec("no_such_program\0"), Errno::ENOENT);
+    assert_eq!(exec(core::str::from_utf8(&LONG_PATH).unwrap()), Errno::EFAULT);
+    assert_eq!(wait(&mut 0), Err(Errno::ECHILD));
+    assert_eq!(mmap(0, 4096, PROT_READ, 1 << 10), Err(Errno::EINVAL));
+    assert_eq!(sbrk(i32::MIN), Err(Errno::ENOMEM));
+    println!("errnotest passed!");
+    0
+}
@@ -3,14 +3,14 @@
 
 #[macro_use]
 extern crate user_lib;
-use user_lib::{exit, fork, wait, waitpid, yield_};
+use user_lib::{exit, fork, wait, waitpid, yield_, Errno};
 
 const MAGIC: i32 = -0x10384;
 
 #[no_mangle]
 pub fn main() -> i32 {
     println!("I am the parent. Forking the child...");
-    let pid = fork();
+    let pid = fork().unwrap();
     if pid == 0 {
         println!("I am the child.");
         for _ in 0..7 {
@@ -22,8 +22,8 @@ pub fn main() -> i32 {
     }
     println!("I am the parent, waiting now..");
     let mut xstate: i32 = 0;
-    assert!(waitpid(pid as usize, &mut xstate) == pid && xstate == MAGIC);
-    assert!(waitpid(pid as usize, &mut xstate) < 0 && wait(&mut xstate) <= 0);
+    assert!(waitpid(pid, &mut xstate) == Ok(pid) && xstate == MAGIC);
+    assert!(waitpid(pid, &mut xstate) == Err(Errno::ECHILD) && wait(&mut xstate) == Err(Errno::ECHILD));
     println!("waitpid {} ok.", pid);
     println!("exit pass.");
     0
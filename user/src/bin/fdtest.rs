@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{close, dup, dup2, exit, fork, waitpid, write, Errno};
+
+const STDOUT: usize = 1;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // dup takes the lowest free fd, which writes to the same console
+    let fd = dup(STDOUT).unwrap();
+    assert_eq!(fd, 3);
+    assert_eq!(write(fd, b"dup passed\n"), Ok(11));
+    assert_eq!(close(fd), Ok(()));
+    assert_eq!(write(fd, b"closed\n"), Err(Errno::EBADF));
+    assert_eq!(close(fd), Err(Errno::EBADF));
+
+    assert_eq!(dup2(STDOUT, 10), Ok(10));
+    assert_eq!(dup2(10, 10), Ok(10));
+    assert_eq!(write(10, b"dup2 passed\n"), Ok(12));
+    assert_eq!(dup2(5, 6), Err(Errno::EBADF));
+    assert_eq!(dup2(STDOUT, usize::MAX), Err(Errno::EBADF));
+
+    // the child inherits the files, its fds are its own
+    let pid = fork().unwrap();
+    if pid == 0 {
+        assert_eq!(write(10, b"inherited\n"), Ok(10));
+        assert_eq!(close(10), Ok(()));
+        assert_eq!(close(STDOUT), Ok(()));
+        exit(if write(STDOUT, b"closed\n") == Err(Errno::EBADF) { 0 } else { 1 });
+    }
+    let mut exit_code = -1;
+    assert_eq!(waitpid(pid, &mut exit_code), Ok(pid));
+    assert_eq!(exit_code, 0);
+    assert_eq!(write(10, b"still open\n"), Ok(11));
+    assert_eq!(close(10), Ok(()));
+
+    // the table is bounded
+    let mut fds = Vec::new();
+    let err = loop {
+        match dup(STDOUT) {
+            Ok(fd) => fds.push(fd),
+            Err(err) => break err,
+        }
+    };
+    assert_eq!(err, Errno::EMFILE);
+    assert!(fds.len() > 10);
+    for fd in fds {
+        assert_eq!(close(fd), Ok(()));
+    }
+    println!("fdtest passed!");
+    0
+}
@@ -9,7 +9,7 @@ use user_lib::{exec, fork, getpid, wait};
 #[no_mangle]
 pub fn main() -> i32 {
     println!("pid {}: parent start forking ...", getpid());
-    let pid = fork();
+    let pid = fork().unwrap();
     if pid == 0 {
         // child process
         println!(
@@ -22,7 +22,7 @@ pub fn main() -> i32 {
         // parent process
         let mut exit_code: i32 = 0;
         println!("pid {}: ready waiting child ...", getpid());
-        assert_eq!(pid, wait(&mut exit_code));
+        assert_eq!(Ok(pid), wait(&mut exit_code));
         assert_eq!(exit_code, 0);
         println!(
             "pid {}: got child info:: pid {}, exit code: {}",
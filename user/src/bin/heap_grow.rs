@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use user_lib::{heap_stats, sbrk};
+
+const LEN: usize = 256 * 1024; // 2 MiB of u64, far more than the heap starts with
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let brk = sbrk(0).unwrap();
+
+    let mut v: Vec<u64> = Vec::new();
+    for i in 0..LEN {
+        v.push(i as u64);
+    }
+    assert!(v.iter().enumerate().all(|(i, &x)| x == i as u64));
+    let mut s = String::new();
+    for _ in 0..1024 {
+        s.push_str("0123456789abcdef");
+    }
+    assert_eq!(s.len(), 16 * 1024);
+    let grown_brk = sbrk(0).unwrap();
+    assert!(grown_brk >= brk + LEN * 8);
+    assert!(heap_stats().total >= LEN * 8);
+    println!("heap grows to {:#x} bytes", heap_stats().total);
+
+    // the large block at the break is given back
+    drop(v);
+    drop(s);
+    assert!(sbrk(0).unwrap() < grown_brk);
+    println!("heap shrinks to {:#x} bytes", heap_stats().total);
+
+    // an allocation the kernel refuses fails without aborting
+    let mut huge: Vec<u8> = Vec::new();
+    assert!(huge.try_reserve(1 << 36).is_err());
+    assert!(heap_stats().failed > 0);
+    println!("heap_grow passed!");
+    0
+}
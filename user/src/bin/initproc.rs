@@ -10,7 +10,7 @@ const RED: &str = "\x1b[31m";
 const PURPLE: &str = "\x1b[35m";
 const RESET: &str = "\x1b[0m";
 
-use user_lib::{exec, fork, wait, yield_};
+use user_lib::{exec, fork, wait, yield_, Errno};
 
 #[no_mangle]
 fn main() -> i32 {
@@ -19,13 +19,13 @@ fn main() -> i32 {
     println!("{}{}{}", PURPLE, str1, RESET);
     println!("str1:{:#x}",str1.as_ptr() as usize);
     println!("path:{:#x}",path.as_ptr() as usize);
-    if fork() == 0 {
+    if fork() == Ok(0) {
         exec(&path);
     } else {
         loop {
             let mut exit_code: i32 = 0;
             let pid = wait(&mut exit_code);
-            if pid == -1 {
+            if pid == Err(Errno::ECHILD) {
                 yield_();
                 continue;
             }
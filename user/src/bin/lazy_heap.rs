@@ -0,0 +1,33 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::sbrk;
+
+const RESERVE_SIZE: usize = 64 * 1024 * 1024; // more than we could afford to map eagerly
+const STRIDE: usize = 1024 * 1024;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let heap_start = sbrk(RESERVE_SIZE as i32).expect("sbrk failed to reserve heap");
+    println!("reserved {:#x} bytes of heap at {:#x}", RESERVE_SIZE, heap_start);
+
+    // touch one page in every STRIDE bytes, each of them is mapped on the first access
+    for offset in (0..RESERVE_SIZE).step_by(STRIDE) {
+        let ptr = (heap_start + offset) as *mut usize;
+        unsafe {
+            assert_eq!(ptr.read_volatile(), 0); // lazy pages are zeroed
+            ptr.write_volatile(offset);
+        }
+    }
+    for offset in (0..RESERVE_SIZE).step_by(STRIDE) {
+        let ptr = (heap_start + offset) as *const usize;
+        assert_eq!(unsafe { ptr.read_volatile() }, offset);
+    }
+
+    assert_eq!(sbrk(-(RESERVE_SIZE as i32)), Ok(heap_start + RESERVE_SIZE));
+    println!("lazy_heap passed!");
+    0
+}
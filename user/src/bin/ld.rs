@@ -0,0 +1,470 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::asm;
+use core::fmt;
+use core::mem::size_of;
+use core::ptr::copy_nonoverlapping;
+use user_lib::{getauxval, initial_sp, mmap, mmap_file, mprotect, munmap};
+use user_lib::{AT_ENTRY, AT_PHDR, AT_PHNUM, PROT_EXEC, PROT_READ, PROT_WRITE};
+
+// the dynamic loader, the interpreter (/lib/ld) of dynamically linked programs. Built as a position independent
+// executable, see the makefile, so the kernel loads it at its own base, relocates it and starts it instead of the
+// program, which it maps but leaves unrelocated.
+// the loader maps the shared libraries the program needs (DT_NEEDED, then the ones they need) from the files of
+// their names, applies the relocations of the program and the libraries, and jumps to the entry of the program
+// with the stack the kernel set up. Symbols are looked up in the program first, then in the libraries in the
+// order they are loaded, and all of them are bound before the program starts.
+// the loader does not use the heap, which is left to the program.
+
+const PAGE_SIZE: usize = 0x1000;
+const MAX_OBJECTS: usize = 8; // the program and its libraries
+
+const EM_RISCV: u16 = 243;
+const ET_DYN: u16 = 3;
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_PHDR: u32 = 6;
+const PT_GNU_RELRO: u32 = 0x6474_e552;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const DT_NULL: usize = 0;
+const DT_NEEDED: usize = 1;
+const DT_PLTRELSZ: usize = 2;
+const DT_HASH: usize = 4;
+const DT_STRTAB: usize = 5;
+const DT_SYMTAB: usize = 6;
+const DT_RELA: usize = 7;
+const DT_RELASZ: usize = 8;
+const DT_RELAENT: usize = 9;
+const DT_SYMENT: usize = 11;
+const DT_REL: usize = 17;
+const DT_PLTREL: usize = 20;
+const DT_JMPREL: usize = 23;
+
+const R_RISCV_NONE: u32 = 0;
+const R_RISCV_64: u32 = 2;
+const R_RISCV_RELATIVE: u32 = 3;
+const R_RISCV_JUMP_SLOT: u32 = 5;
+
+const SHN_UNDEF: u16 = 0;
+const SHN_ABS: u16 = 0xfff1;
+const STB_LOCAL: u8 = 0;
+const STB_WEAK: u8 = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ElfHeader {
+    ident: [u8; 16],
+    type_: u16,
+    machine: u16,
+    _version: u32,
+    _entry: u64,
+    ph_offset: u64,
+    _sh_offset: u64,
+    _flags: u32,
+    _header_size: u16,
+    ph_entry_size: u16,
+    ph_count: u16,
+    _section_headers: [u16; 3],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ProgramHeader {
+    type_: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    _paddr: u64,
+    file_size: u64,
+    mem_size: u64,
+    _align: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Symbol {
+    name: u32,
+    info: u8,
+    _other: u8,
+    section: u16,
+    value: u64,
+    _size: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Rela {
+    offset: u64,
+    info: u64,
+    addend: i64,
+}
+
+// the names are of libraries, an empty one is the program
+enum LoadError {
+    NoProgram, // started without a program, the auxiliary vector has none
+    NoLibrary(&'static str),
+    Malformed(&'static str), // not an elf file the loader can link
+    UndefinedSymbol(&'static str),
+    BadRelocation(u32),
+    TooManyLibraries,
+    OutOfMemory,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::NoProgram => write!(f, "no program to load"),
+            LoadError::NoLibrary(name) => write!(f, "library {} not found", name),
+            LoadError::Malformed("") => write!(f, "the program can not be linked"),
+            LoadError::Malformed(name) => write!(f, "library {} can not be linked", name),
+            LoadError::UndefinedSymbol(name) => write!(f, "undefined symbol {}", name),
+            LoadError::BadRelocation(type_) => write!(f, "unsupported relocation type {}", type_),
+            LoadError::TooManyLibraries => write!(f, "more than {} libraries", MAX_OBJECTS - 1),
+            LoadError::OutOfMemory => write!(f, "out of memory"),
+        }
+    }
+}
+
+// the loader reads the objects it loaded, and trusts the addresses in them
+fn read<T: Copy>(va: usize) -> T {
+    unsafe { (va as *const T).read_unaligned() }
+}
+
+// the NUL-terminated string at va, without the NUL
+fn c_str(va: usize) -> &'static str {
+    let len = (0..).take_while(|&i| read::<u8>(va + i) != 0).count();
+    let bytes = unsafe { core::slice::from_raw_parts(va as *const u8, len) };
+    core::str::from_utf8(bytes).unwrap_or("")
+}
+
+fn page_down(va: usize) -> usize {
+    va & !(PAGE_SIZE - 1)
+}
+
+fn page_up(va: usize) -> usize {
+    page_down(va + PAGE_SIZE - 1)
+}
+
+// the hash of a symbol name in DT_HASH
+fn elf_hash(name: &str) -> u32 {
+    let mut hash: u32 = 0;
+    for &c in name.as_bytes() {
+        hash = (hash << 4).wrapping_add(c as u32);
+        let high = hash & 0xf000_0000;
+        hash ^= high >> 24;
+        hash &= !high;
+    }
+    hash
+}
+
+// the (tag, value) pairs of the dynamic section at va, before DT_NULL
+fn dynamic_entries(va: usize) -> impl Iterator<Item = (usize, usize)> {
+    (0..)
+        .map(move |i| (read::<usize>(va + i * 16), read::<usize>(va + i * 16 + 8)))
+        .take_while(|&(tag, _)| tag != DT_NULL)
+}
+
+// a loaded elf object, the program or a shared library. The addresses are where it is loaded
+#[derive(Clone, Copy)]
+struct Object {
+    name: &'static str, // the name it is needed by, empty for the program
+    base: usize,
+    phdr: usize,
+    ph_count: usize,
+    dynamic: usize,
+    strtab: usize,
+    symtab: usize,
+    hash: usize,
+    rela: (usize, usize),   // the start and the size of the relocations
+    jmprel: (usize, usize), // the same for the relocations of the PLT
+}
+
+impl Object {
+    // find the tables in the dynamic section of an object loaded at base
+    fn new(name: &'static str, base: usize, phdr: usize, ph_count: usize) -> Result<Self, LoadError> {
+        let mut object = Object {
+            name,
+            base,
+            phdr,
+            ph_count,
+            dynamic: 0,
+            strtab: 0,
+            symtab: 0,
+            hash: 0,
+            rela: (0, 0),
+            jmprel: (0, 0),
+        };
+        let dynamic = object
+            .program_headers()
+            .find(|ph| ph.type_ == PT_DYNAMIC)
+            .ok_or(LoadError::Malformed(name))?;
+        object.dynamic = base + dynamic.vaddr as usize;
+        for (tag, value) in dynamic_entries(object.dynamic) {
+            match tag {
+                DT_STRTAB => object.strtab = base + value,
+                DT_SYMTAB => object.symtab = base + value,
+                DT_HASH => object.hash = base + value,
+                DT_RELA => object.rela.0 = base + value,
+                DT_RELASZ => object.rela.1 = value,
+                DT_JMPREL => object.jmprel.0 = base + value,
+                DT_PLTRELSZ => object.jmprel.1 = value,
+                DT_RELAENT if value != size_of::<Rela>() => return Err(LoadError::Malformed(name)),
+                DT_SYMENT if value != size_of::<Symbol>() => return Err(LoadError::Malformed(name)),
+                DT_PLTREL if value != DT_RELA => return Err(LoadError::Malformed(name)),
+                DT_REL => return Err(LoadError::Malformed(name)), // RISC-V only uses rela
+                _ => {}
+            }
+        }
+        Ok(object)
+    }
+
+    fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> {
+        let phdr = self.phdr;
+        (0..self.ph_count).map(move |i| read::<ProgramHeader>(phdr + i * size_of::<ProgramHeader>()))
+    }
+
+    // the names of the libraries the object needs
+    fn needed(&self) -> impl Iterator<Item = &'static str> {
+        let strtab = self.strtab;
+        dynamic_entries(self.dynamic)
+            .filter(|&(tag, _)| tag == DT_NEEDED)
+            .map(move |(_, offset)| c_str(strtab + offset))
+    }
+
+    fn symbol(&self, index: usize) -> Symbol {
+        read(self.symtab + index * size_of::<Symbol>())
+    }
+
+    fn symbol_address(&self, symbol: &Symbol) -> usize {
+        match symbol.section {
+            SHN_ABS => symbol.value as usize,
+            _ => self.base + symbol.value as usize,
+        }
+    }
+
+    // the address of the symbol called name, if the object defines it
+    fn lookup(&self, name: &str, hash: u32) -> Option<usize> {
+        if self.hash == 0 {
+            return None;
+        }
+        // nbucket, nchain, the buckets, then the chains
+        let bucket_count = read::<u32>(self.hash) as usize;
+        let (buckets, chains) = (self.hash + 8, self.hash + 8 + bucket_count * 4);
+        if bucket_count == 0 {
+            return None;
+        }
+        let mut index = read::<u32>(buckets + hash as usize % bucket_count * 4) as usize;
+        while index != 0 {
+            let symbol = self.symbol(index);
+            if symbol.section != SHN_UNDEF && c_str(self.strtab + symbol.name as usize) == name {
+                return Some(self.symbol_address(&symbol));
+            }
+            index = read::<u32>(chains + index * 4) as usize;
+        }
+        None
+    }
+
+    // make the segments writable for the relocations, or give them the permissions in their program headers,
+    // with the part that is only written by relocations (PT_GNU_RELRO) read only
+    fn protect(&self, writable: bool) -> Result<(), LoadError> {
+        for ph in self.program_headers() {
+            let (start, end) = (self.base + ph.vaddr as usize, self.base + (ph.vaddr + ph.mem_size) as usize);
+            let (start, end, prot) = match ph.type_ {
+                PT_LOAD if writable => (page_down(start), page_up(end), PROT_READ | PROT_WRITE),
+                PT_LOAD => (page_down(start), page_up(end), prot(ph.flags)),
+                PT_GNU_RELRO if !writable => (page_down(start), page_down(end), PROT_READ),
+                _ => continue,
+            };
+            if start < end {
+                mprotect(start, end - start, prot).map_err(|_| LoadError::Malformed(self.name))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn prot(flags: u32) -> usize {
+    let mut prot = 0;
+    if flags & PF_R != 0 {
+        prot |= PROT_READ;
+    }
+    if flags & PF_W != 0 {
+        prot |= PROT_WRITE;
+    }
+    if flags & PF_X != 0 {
+        prot |= PROT_EXEC;
+    }
+    prot
+}
+
+// map the library called name from the file of that name, at a base mmap chooses
+fn load_library(name: &'static str) -> Result<Object, LoadError> {
+    // the string table keeps the NUL after the name, which the path needs
+    let path = unsafe { core::str::from_utf8_unchecked(core::slice::from_raw_parts(name.as_ptr(), name.len() + 1)) };
+    let header_page = mmap_file(path, PAGE_SIZE, PROT_READ, 0).map_err(|_| LoadError::NoLibrary(name))?;
+    let header: ElfHeader = read(header_page);
+    let ph_size = size_of::<ProgramHeader>();
+    let table_end = header.ph_offset as usize + header.ph_count as usize * ph_size;
+    if header.ident[..4] != *b"\x7fELF"
+        || (header.ident[4], header.ident[5]) != (2, 1) // 64 bit, little endian
+        || (header.type_, header.machine) != (ET_DYN, EM_RISCV)
+        || header.ph_entry_size as usize != ph_size
+        || table_end > PAGE_SIZE
+    {
+        munmap(header_page, PAGE_SIZE).unwrap();
+        return Err(LoadError::Malformed(name));
+    }
+    let header_ph = |i: usize| read::<ProgramHeader>(header_page + header.ph_offset as usize + i * ph_size);
+    let loads = || (0..header.ph_count as usize).map(header_ph).filter(|ph| ph.type_ == PT_LOAD);
+
+    // the segments are in order of address, and do not share pages
+    let (mut low, mut high, mut file_end) = (usize::MAX, 0, 0);
+    let mut phdr = None;
+    for ph in loads() {
+        let (offset, vaddr) = (ph.offset as usize, ph.vaddr as usize);
+        let (file_size, mem_size) = (ph.file_size as usize, ph.mem_size as usize);
+        if file_size > mem_size || page_down(vaddr) < high || vaddr.checked_add(mem_size).is_none() {
+            munmap(header_page, PAGE_SIZE).unwrap();
+            return Err(LoadError::Malformed(name));
+        }
+        if offset <= header.ph_offset as usize && table_end <= offset + file_size {
+            phdr = Some(vaddr + header.ph_offset as usize - offset);
+        }
+        low = low.min(page_down(vaddr));
+        high = page_up(vaddr + mem_size);
+        file_end = file_end.max(offset + file_size);
+    }
+    munmap(header_page, PAGE_SIZE).unwrap();
+    // the loader finds the segments of a library by its program headers, so they have to be loaded
+    let phdr = phdr.ok_or(LoadError::Malformed(name))?;
+
+    let file = mmap_file(path, file_end, PROT_READ, 0).map_err(|_| LoadError::Malformed(name))?;
+    let image = match mmap(0, high - low, PROT_READ | PROT_WRITE, 0) {
+        Ok(image) => image,
+        Err(_) => {
+            munmap(file, file_end).unwrap();
+            return Err(LoadError::OutOfMemory);
+        }
+    };
+    let base = image - low;
+    for ph in (0..header.ph_count as usize).map(|i| read::<ProgramHeader>(file + header.ph_offset as usize + i * ph_size)) {
+        if ph.type_ == PT_LOAD {
+            let src = (file + ph.offset as usize) as *const u8;
+            unsafe { copy_nonoverlapping(src, (base + ph.vaddr as usize) as *mut u8, ph.file_size as usize) };
+        }
+    }
+    munmap(file, file_end).unwrap();
+    Object::new(name, base, base + phdr, header.ph_count as usize)
+}
+
+// the value a symbol of object is bound to, from the first object that defines it
+fn resolve(object: &Object, index: usize, objects: &[Object]) -> Result<usize, LoadError> {
+    if index == 0 {
+        return Ok(0);
+    }
+    let symbol = object.symbol(index);
+    let bind = symbol.info >> 4;
+    if bind == STB_LOCAL && symbol.section != SHN_UNDEF {
+        return Ok(object.symbol_address(&symbol));
+    }
+    let name = c_str(object.strtab + symbol.name as usize);
+    let hash = elf_hash(name);
+    match objects.iter().find_map(|object| object.lookup(name, hash)) {
+        Some(address) => Ok(address),
+        None if bind == STB_WEAK => Ok(0),
+        None => Err(LoadError::UndefinedSymbol(name)),
+    }
+}
+
+fn relocate(object: &Object, objects: &[Object]) -> Result<(), LoadError> {
+    for (start, size) in [object.rela, object.jmprel] {
+        for i in 0..size / size_of::<Rela>() {
+            let rela: Rela = read(start + i * size_of::<Rela>());
+            let addend = rela.addend as usize;
+            let value = match rela.info as u32 {
+                R_RISCV_NONE => continue,
+                R_RISCV_RELATIVE => object.base.wrapping_add(addend),
+                R_RISCV_64 | R_RISCV_JUMP_SLOT => {
+                    resolve(object, (rela.info >> 32) as usize, objects)?.wrapping_add(addend)
+                }
+                other => return Err(LoadError::BadRelocation(other)),
+            };
+            unsafe { ((object.base + rela.offset as usize) as *mut usize).write_unaligned(value) };
+        }
+    }
+    Ok(())
+}
+
+// load the libraries of the program and link them all, return the entry of the program
+fn load() -> Result<usize, LoadError> {
+    let (phdr, ph_count) = match (getauxval(AT_PHDR), getauxval(AT_PHNUM)) {
+        (Some(phdr), Some(ph_count)) => (phdr, ph_count),
+        _ => return Err(LoadError::NoProgram),
+    };
+    let entry = getauxval(AT_ENTRY).ok_or(LoadError::NoProgram)?;
+    // the base of the program is where its program headers are, less their address in the file
+    let program_phdr = (0..ph_count)
+        .map(|i| read::<ProgramHeader>(phdr + i * size_of::<ProgramHeader>()))
+        .find(|ph| ph.type_ == PT_PHDR)
+        .ok_or(LoadError::Malformed(""))?;
+    let program = Object::new("", phdr - program_phdr.vaddr as usize, phdr, ph_count)?;
+
+    // the libraries are loaded breadth first, each once
+    let mut objects = [program; MAX_OBJECTS];
+    let (mut count, mut next) = (1, 0);
+    while next < count {
+        let object = objects[next];
+        for name in object.needed() {
+            if objects[..count].iter().any(|loaded| loaded.name == name) {
+                continue;
+            }
+            if count == MAX_OBJECTS {
+                return Err(LoadError::TooManyLibraries);
+            }
+            objects[count] = load_library(name)?;
+            count += 1;
+        }
+        next += 1;
+    }
+
+    let objects = &objects[..count];
+    for object in objects {
+        object.protect(true)?;
+    }
+    for object in objects {
+        relocate(object, objects)?;
+    }
+    for object in objects {
+        object.protect(false)?;
+    }
+    Ok(entry)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let entry = match load() {
+        Ok(entry) => entry,
+        Err(err) => {
+            println!("ld: {}", err);
+            return -1;
+        }
+    };
+    // the code that was relocated is run from here, and the program starts with the stack of the kernel
+    unsafe {
+        asm!(
+            "fence.i",
+            "mv sp, {sp}",
+            "jr {entry}",
+            sp = in(reg) initial_sp(),
+            entry = in(reg) entry,
+            options(noreturn)
+        );
+    }
+}
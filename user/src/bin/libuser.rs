@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+extern crate user_lib;
+
+use alloc::alloc::{alloc, dealloc, Layout};
+use core::slice;
+use user_lib::Errno;
+
+// user_lib as a shared library, built with -shared and src/libuser.map, see the makefile.
+// a dynamically linked program gets these functions from it through the dynamic loader (ld),
+// so the heap and the arguments belong to the library, and the program only keeps its own code.
+
+// the entry of the program jumps here with the initial stack pointer
+#[no_mangle]
+pub extern "C" fn user_start(sp: *const usize, main: extern "C" fn() -> i32) -> ! {
+    user_lib::start(sp, || main())
+}
+
+// the number of bytes written, or the negative error number
+#[no_mangle]
+pub extern "C" fn user_write(fd: usize, buf: *const u8, len: usize) -> isize {
+    let buf = unsafe { slice::from_raw_parts(buf, len) };
+    Errno::encode(user_lib::write(fd, buf))
+}
+
+#[no_mangle]
+pub extern "C" fn user_exit(exit_code: i32) -> ! {
+    user_lib::exit(exit_code);
+    unreachable!("exit returned");
+}
+
+#[no_mangle]
+pub extern "C" fn user_getpid() -> isize {
+    user_lib::getpid()
+}
+
+// memory from the heap of user_lib, null if it can not be allocated
+#[no_mangle]
+pub extern "C" fn user_alloc(size: usize, align: usize) -> *mut u8 {
+    match Layout::from_size_align(size, align) {
+        Ok(layout) if size > 0 => unsafe { alloc(layout) },
+        _ => core::ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn user_dealloc(ptr: *mut u8, size: usize, align: usize) {
+    unsafe { dealloc(ptr, Layout::from_size_align_unchecked(size, align)) }
+}
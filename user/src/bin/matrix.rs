@@ -5,7 +5,7 @@
 #[macro_use]
 extern crate user_lib;
 
-use user_lib::{exit, fork, get_time, getpid, wait, yield_};
+use user_lib::{exit, fork, get_time, getpid, wait, yield_, Errno};
 
 static NUM: usize = 30;
 const N: usize = 10;
@@ -48,7 +48,7 @@ fn work(times: isize) {
 #[no_mangle]
 pub fn main() -> i32 {
     for _ in 0..NUM {
-        let pid = fork();
+        let pid = fork().unwrap();
         if pid == 0 {
             let current_time = get_time();
             let times = (current_time as i32 as isize) * (current_time as i32 as isize) % 1000;
@@ -60,11 +60,11 @@ pub fn main() -> i32 {
 
     let mut exit_code: i32 = 0;
     for _ in 0..NUM {
-        if wait(&mut exit_code) < 0 {
+        if wait(&mut exit_code).is_err() {
             panic!("wait failed.");
         }
     }
-    assert!(wait(&mut exit_code) < 0);
+    assert_eq!(wait(&mut exit_code), Err(Errno::ECHILD));
     println!("matrix passed.");
     0
 }
@@ -0,0 +1,133 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{
+    exit, fork, memory_usage, mmap, munmap, sbrk, set_memory_limits, waitpid, waitpid_with_usage, Errno,
+    MemoryUsage, MAP_SHARED, PROT_READ, PROT_WRITE,
+};
+
+const PAGE_SIZE: usize = 4096;
+const MIB: usize = 1024 * 1024;
+
+fn touch(start: usize, len: usize) {
+    for addr in (start..start + len).step_by(PAGE_SIZE) {
+        unsafe { (addr as *mut u8).write_volatile(1) };
+    }
+}
+
+// run f in a child process and return its exit code
+fn in_child(f: fn() -> i32) -> i32 {
+    let pid = fork().unwrap();
+    if pid == 0 {
+        exit(f());
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), Ok(pid));
+    exit_code
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let usage = memory_usage();
+    assert!(usage.resident > 0 && usage.page_tables > 0 && usage.stack > 0);
+    assert!(usage.peak >= usage.resident + usage.page_tables);
+
+    // touched pages are resident, reserved ones are only mapped
+    let start = mmap(0, 2 * MIB, PROT_READ | PROT_WRITE, 0).unwrap();
+    assert_eq!(memory_usage().mapped, usage.mapped + 2 * MIB);
+    assert!(memory_usage().resident < usage.resident + MIB);
+    touch(start, MIB);
+    let touched = memory_usage();
+    assert!(touched.resident >= usage.resident + MIB);
+    assert!(touched.peak >= touched.resident + touched.page_tables);
+    // the heap grows with sbrk
+    let v: Vec<u8> = Vec::with_capacity(MIB);
+    assert!(memory_usage().heap >= MIB);
+    drop(v);
+    assert_eq!(munmap(start, 2 * MIB), Ok(()));
+    assert!(memory_usage().resident < touched.resident);
+    println!("usage passed");
+
+    // a limit below the current usage is refused
+    assert_eq!(set_memory_limits(PAGE_SIZE, usize::MAX), Err(Errno::EINVAL));
+    assert_eq!(set_memory_limits(usize::MAX, PAGE_SIZE), Err(Errno::EINVAL));
+
+    // touching pages beyond the resident limit kills the process
+    fn exceed_resident() -> i32 {
+        let usage = memory_usage();
+        assert_eq!(set_memory_limits(usage.resident + usage.page_tables + 256 * 1024, usize::MAX), Ok(()));
+        let start = mmap(0, MIB, PROT_READ | PROT_WRITE, 0).expect("lazy mmap is not charged");
+        touch(start, MIB);
+        0
+    }
+    assert_eq!(in_child(exceed_resident), -2);
+
+    // mappings beyond the mapped limit are refused, by sbrk, mmap and shared mmap
+    fn exceed_mapped() -> i32 {
+        assert_eq!(set_memory_limits(usize::MAX, memory_usage().mapped + 64 * 1024), Ok(()));
+        assert_eq!(sbrk(MIB as i32), Err(Errno::ENOMEM));
+        assert_eq!(mmap(0, MIB, PROT_READ | PROT_WRITE, 0), Err(Errno::ENOMEM));
+        assert_eq!(mmap(0, MIB, PROT_READ | PROT_WRITE, MAP_SHARED), Err(Errno::ENOMEM));
+        assert!(mmap(0, 16 * 1024, PROT_READ | PROT_WRITE, 0).is_ok());
+        // the limits are kept by a child
+        fn child() -> i32 {
+            assert_eq!(mmap(0, MIB, PROT_READ | PROT_WRITE, 0), Err(Errno::ENOMEM));
+            0
+        }
+        assert_eq!(in_child(child), 0);
+        0
+    }
+    assert_eq!(in_child(exceed_mapped), 0);
+
+    // the copies a child makes of the pages it shares with its parent are new frames, which a child at its
+    // resident limit can not take
+    let private = mmap(0, MIB, PROT_READ | PROT_WRITE, 0).unwrap();
+    touch(private, MIB);
+    let pid = fork().unwrap();
+    if pid == 0 {
+        let usage = memory_usage();
+        assert_eq!(set_memory_limits(usage.resident + usage.page_tables, usize::MAX), Ok(()));
+        touch(private, MIB);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), Ok(pid));
+    assert_eq!(exit_code, -2);
+    assert_eq!(munmap(private, MIB), Ok(()));
+
+    println!("limits passed");
+
+    // the parent gets the peak usage of a child
+    let pid = fork().unwrap();
+    if pid == 0 {
+        let start = mmap(0, 2 * MIB, PROT_READ | PROT_WRITE, 0).unwrap();
+        touch(start, 2 * MIB);
+        munmap(start, 2 * MIB).unwrap();
+        exit(0);
+    }
+    let mut usage = MemoryUsage::default();
+    assert_eq!(waitpid_with_usage(pid, &mut exit_code, &mut usage), Ok(pid));
+    assert_eq!(exit_code, 0);
+    assert!(usage.peak >= 2 * MIB);
+    // and the usage it had when it exited
+    let pid = fork().unwrap();
+    if pid == 0 {
+        let start = mmap(0, 2 * MIB, PROT_READ | PROT_WRITE, 0).unwrap();
+        touch(start, MIB);
+        exit(0);
+    }
+    assert_eq!(waitpid_with_usage(pid, &mut exit_code, &mut usage), Ok(pid));
+    assert!(usage.resident >= MIB && usage.page_tables > 0 && usage.stack > 0);
+    assert!(usage.mapped >= 2 * MIB);
+    assert!(usage.peak >= usage.resident + usage.page_tables);
+    println!("child usage passed");
+
+    println!("memlimit passed!");
+    0
+}
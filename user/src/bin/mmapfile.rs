@@ -0,0 +1,74 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, mmap_file, msync, munmap, waitpid, Errno, MAP_FIXED, MAP_SHARED, PROT_READ, PROT_WRITE};
+
+const PAGE_SIZE: usize = 4096;
+const FILE: &str = "mmapdata.txt\0"; // user/data/mmapdata.txt
+const BYTE: usize = 9;
+
+fn bytes(start: usize) -> &'static mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(start as *mut u8, PAGE_SIZE) }
+}
+
+// the byte of the file, read through a new private mapping
+fn file_byte(offset: usize) -> u8 {
+    let start = mmap_file(FILE, PAGE_SIZE, PROT_READ, 0).unwrap();
+    let byte = bytes(start)[offset];
+    assert_eq!(munmap(start, PAGE_SIZE), Ok(()));
+    byte
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let private = mmap_file(FILE, PAGE_SIZE, PROT_READ | PROT_WRITE, 0).unwrap();
+    assert_eq!(&bytes(private)[..8], b"mmapfile");
+    let original = bytes(private)[BYTE];
+
+    // a private mapping is copied on write
+    bytes(private)[BYTE] = original ^ 0xff;
+    let shared = mmap_file(FILE, PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_SHARED).unwrap();
+    assert_eq!(bytes(shared)[BYTE], original);
+    println!("private mapping passed");
+
+    // shared mappings see the writes of each other at once, so does a new private mapping
+    let shared2 = mmap_file(FILE, PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_SHARED).unwrap();
+    bytes(shared)[BYTE] = original ^ 1;
+    assert_eq!(bytes(shared2)[BYTE], original ^ 1);
+    let private2 = mmap_file(FILE, PAGE_SIZE, PROT_READ, 0).unwrap();
+    assert_eq!(bytes(private2)[BYTE], original ^ 1);
+    // and a child writes to the same pages
+    let pid = fork().unwrap();
+    if pid == 0 {
+        bytes(shared)[BYTE] = original ^ 2;
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), Ok(pid));
+    assert_eq!(bytes(shared2)[BYTE], original ^ 2);
+    println!("shared mapping passed");
+
+    // msync writes to the file, which keeps the data after the file leaves the page cache
+    assert_eq!(msync(shared, PAGE_SIZE), Ok(()));
+    for start in [private, shared, shared2, private2] {
+        assert_eq!(munmap(start, PAGE_SIZE), Ok(()));
+    }
+    assert_eq!(file_byte(BYTE), original ^ 2);
+    // so does munmap
+    let shared = mmap_file(FILE, PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_SHARED).unwrap();
+    bytes(shared)[BYTE] = original;
+    assert_eq!(munmap(shared, PAGE_SIZE), Ok(()));
+    assert_eq!(file_byte(BYTE), original);
+    println!("write back passed");
+
+    assert_eq!(mmap_file("no_such_file\0", PAGE_SIZE, PROT_READ, 0), Err(Errno::ENOENT));
+    assert_eq!(mmap_file(FILE, 0, PROT_READ, 0), Err(Errno::EINVAL));
+    assert_eq!(mmap_file(FILE, 1 << 30, PROT_READ, 0), Err(Errno::EINVAL)); // beyond the file
+    assert_eq!(mmap_file(FILE, PAGE_SIZE, PROT_READ, MAP_FIXED), Err(Errno::EINVAL));
+
+    println!("mmapfile passed!");
+    0
+}
@@ -0,0 +1,128 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::asm;
+use user_lib::{
+    exit, fork, mmap, mprotect, munmap, waitpid, Errno, MAP_FIXED, MAP_SHARED, PROT_EXEC, PROT_READ, PROT_WRITE,
+};
+
+const PAGE_SIZE: usize = 4096;
+const PAGES: usize = 4;
+const USER_SPACE_END: usize = 0x40_0000_0000;
+
+fn write_pages(start: usize, pages: usize, value: usize) {
+    for i in 0..pages {
+        unsafe { ((start + i * PAGE_SIZE) as *mut usize).write_volatile(value + i) };
+    }
+}
+
+fn check_pages(start: usize, pages: usize, value: usize) {
+    for i in 0..pages {
+        assert_eq!(unsafe { ((start + i * PAGE_SIZE) as *const usize).read_volatile() }, value + i);
+    }
+}
+
+// run f in a child process and return its exit code
+fn in_child(f: fn() -> i32) -> i32 {
+    let pid = fork().unwrap();
+    if pid == 0 {
+        exit(f());
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), Ok(pid));
+    exit_code
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // private mapping: zeroed, and copied on write after fork
+    let private = mmap(0, PAGES * PAGE_SIZE, PROT_READ | PROT_WRITE, 0).expect("mmap failed");
+    check_pages(private, PAGES, 0);
+    write_pages(private, PAGES, 100);
+    let pid = fork().unwrap();
+    if pid == 0 {
+        check_pages(private, PAGES, 100);
+        write_pages(private, PAGES, 200);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), Ok(pid));
+    assert_eq!(exit_code, 0);
+    check_pages(private, PAGES, 100);
+    println!("private mapping passed");
+
+    // shared mapping: writes of the child are seen by the parent
+    let shared = mmap(0, PAGES * PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_SHARED).expect("mmap failed");
+    let pid = fork().unwrap();
+    if pid == 0 {
+        write_pages(shared, PAGES, 300);
+        exit(0);
+    }
+    assert_eq!(waitpid(pid, &mut exit_code), Ok(pid));
+    assert_eq!(exit_code, 0);
+    check_pages(shared, PAGES, 300);
+    println!("shared mapping passed");
+
+    // partial munmap leaves the rest of the mapping usable
+    assert_eq!(munmap(private + PAGE_SIZE, PAGE_SIZE), Ok(()));
+    check_pages(private, 1, 100);
+    check_pages(private + 2 * PAGE_SIZE, PAGES - 2, 102);
+    // the hole can be mapped again at a fixed address, and it is zeroed
+    assert_eq!(
+        mmap(private + PAGE_SIZE, PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_FIXED),
+        Ok(private + PAGE_SIZE)
+    );
+    check_pages(private + PAGE_SIZE, 1, 0);
+    // a fixed area that is rejected leaves the old mappings in place
+    assert_eq!(
+        mmap(private, USER_SPACE_END - PAGE_SIZE, PROT_READ, MAP_FIXED),
+        Err(Errno::ENOMEM)
+    );
+    check_pages(private, 1, 100);
+    check_pages(private + 2 * PAGE_SIZE, PAGES - 2, 102);
+    assert_eq!(munmap(private, PAGES * PAGE_SIZE), Ok(()));
+    assert_eq!(munmap(shared, PAGES * PAGE_SIZE), Ok(()));
+    // unaligned requests are rejected
+    assert_eq!(mmap(shared + 1, PAGE_SIZE, PROT_READ, MAP_FIXED), Err(Errno::EINVAL));
+    assert_eq!(munmap(shared + 1, PAGE_SIZE), Err(Errno::EINVAL));
+    println!("munmap passed");
+
+    // touching unmapped memory kills the process
+    fn touch_unmapped() -> i32 {
+        let start = mmap(0, PAGE_SIZE, PROT_READ | PROT_WRITE, 0).unwrap();
+        munmap(start, PAGE_SIZE).unwrap();
+        unsafe { (start as *mut usize).write_volatile(1) };
+        0
+    }
+    assert_eq!(in_child(touch_unmapped), -2);
+
+    // writing read-only memory kills the process
+    fn write_read_only() -> i32 {
+        let start = mmap(0, PAGE_SIZE, PROT_READ | PROT_WRITE, 0).unwrap();
+        unsafe { (start as *mut usize).write_volatile(1) };
+        assert_eq!(mprotect(start, PAGE_SIZE, PROT_READ), Ok(()));
+        assert_eq!(unsafe { (start as *const usize).read_volatile() }, 1);
+        unsafe { (start as *mut usize).write_volatile(2) };
+        0
+    }
+    assert_eq!(in_child(write_read_only), -2);
+    println!("mprotect passed");
+
+    // write an instruction and execute it after making the page executable
+    let code = mmap(0, PAGE_SIZE, PROT_READ | PROT_WRITE, 0).expect("mmap failed");
+    unsafe { (code as *mut u32).write_volatile(0x00008067) }; // ret
+    assert_eq!(mprotect(code, PAGE_SIZE, PROT_READ | PROT_EXEC), Ok(()));
+    unsafe {
+        asm!("fence.i");
+        let f: extern "C" fn() = core::mem::transmute(code);
+        f();
+    }
+    assert_eq!(munmap(code, PAGE_SIZE), Ok(()));
+    println!("mprotect exec passed");
+
+    println!("mmaptest passed!");
+    0
+}
@@ -0,0 +1,91 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, get_time, mmap, wait, yield_, Errno, MAP_SHARED, PROT_READ, PROT_WRITE};
+
+const MAX_CHILD: usize = 64;
+const CHUNK_SIZE: usize = 4 * 1024 * 1024; // each child holds this much memory, MAX_CHILD of them exceed the physical memory
+
+const TIMEOUT_MS: isize = 1000;
+
+const RUNNING: usize = 0;
+const HOLDING: usize = 1;
+const FAILED: usize = 2;
+
+// word 0 is the release flag, word i + 1 is the status of child i. It is shared by all the children.
+static mut STATE: *mut usize = core::ptr::null_mut();
+
+fn state(idx: usize) -> *mut usize {
+    unsafe { STATE.add(idx) }
+}
+
+fn child(i: usize) -> i32 {
+    // a shared mapping is allocated eagerly, so the memory is taken by mmap itself
+    let status = if mmap(0, CHUNK_SIZE, PROT_READ | PROT_WRITE, MAP_SHARED).is_ok() {
+        HOLDING
+    } else {
+        FAILED
+    };
+    unsafe { state(i + 1).write_volatile(status) };
+    while unsafe { state(0).read_volatile() } == 0 {
+        yield_();
+    }
+    0
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let shared = mmap(0, 4096, PROT_READ | PROT_WRITE, MAP_SHARED).expect("mmap failed");
+    unsafe { STATE = shared as *mut usize };
+
+    // fork until memory runs out, either in fork or in the mmap of a child
+    let mut child_cnt = 0;
+    let mut exhausted = false;
+    while child_cnt < MAX_CHILD && !exhausted {
+        match fork() {
+            Ok(0) => {
+                exit(child(child_cnt));
+            }
+            Ok(_) => {}
+            Err(errno) => {
+                assert_eq!(errno, Errno::ENOMEM);
+                println!("fork failed after {} children", child_cnt);
+                exhausted = true;
+                break;
+            }
+        };
+        // a child killed by a page fault out of memory never reports
+        let start = get_time();
+        while unsafe { state(child_cnt + 1).read_volatile() } == RUNNING && get_time() - start < TIMEOUT_MS {
+            yield_();
+        }
+        if unsafe { state(child_cnt + 1).read_volatile() } != HOLDING {
+            println!("memory ran out in child {}", child_cnt);
+            exhausted = true;
+        }
+        child_cnt += 1;
+    }
+    assert!(exhausted, "memory did not run out");
+
+    // release the children, and their memory
+    unsafe { state(0).write_volatile(1) };
+    let mut exit_code: i32 = 0;
+    for _ in 0..child_cnt {
+        assert!(wait(&mut exit_code).is_ok());
+        assert!(exit_code == 0 || exit_code == -2);
+    }
+    assert_eq!(wait(&mut exit_code), Err(Errno::ECHILD));
+
+    // the system is still usable
+    let pid = fork().expect("fork failed after memory was released");
+    if pid == 0 {
+        exit(7);
+    }
+    assert_eq!(wait(&mut exit_code), Ok(pid));
+    assert_eq!(exit_code, 7);
+    println!("oom_fork passed!");
+    0
+}
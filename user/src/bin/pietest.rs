@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::hint::black_box;
+
+// built as a position independent executable, see the makefile.
+// the pointers in these statics are R_RISCV_RELATIVE relocations the kernel applies at load time.
+
+const PIE_BASE: usize = 0x1_0000_0000;
+
+static VALUE: usize = 42;
+static VALUE_REF: &usize = &VALUE;
+static NAMES: [&str; 3] = ["zero", "one", "two"];
+static FUNCS: [fn(usize) -> usize; 2] = [double, square];
+
+fn double(x: usize) -> usize {
+    x * 2
+}
+
+fn square(x: usize) -> usize {
+    x * x
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // the program is loaded above PIE_BASE, not at the address it is linked at
+    assert!(main as usize >= PIE_BASE);
+    // black_box makes the pointers be read from memory, instead of being folded by the compiler
+    let value_ref = *black_box(&VALUE_REF);
+    assert!(core::ptr::eq(value_ref, &VALUE));
+    assert_eq!(*value_ref, 42);
+    assert_eq!(*black_box(&NAMES), ["zero", "one", "two"]);
+    assert_eq!(black_box(&FUNCS).map(|f| f(5)), [10, 25]);
+    println!("pietest passed!");
+    0
+}
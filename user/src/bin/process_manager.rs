@@ -16,6 +16,7 @@ const PM_WAITPID: usize = 3;
 const PM_SUSPEND: usize = 4;
 const PM_EXIT: usize = 5;
 const PM_FETCH: usize = 6;
+const PM_CANCEL_FORK: usize = 7;
 
 use alloc::collections::VecDeque;
 use alloc::sync::{Arc, Weak};
@@ -69,6 +70,19 @@ fn fork() -> isize {
     return child_pid as isize;
 }
 
+// the kernel failed to build the child process, forget it as if it was never forked
+fn cancel_fork(child_pid: usize) {
+    let parent_pcb = PROCESS_MANAGER.exclusive_access().get_current().unwrap();
+    let mut parent_inner = parent_pcb.inner.exclusive_access();
+    parent_inner.children.retain(|p| p.get_pid() != child_pid);
+    drop(parent_inner);
+    PROCESS_MANAGER
+        .exclusive_access()
+        .ready_process_queue
+        .retain(|p| p.get_pid() != child_pid);
+    // ---- the pid is released when the last reference is dropped
+}
+
 // return: (wait result, exit code)
 // no such child process: wait result = -1
 // child process has not exited: wait result = -2
@@ -334,6 +348,11 @@ fn main() -> i32 {
                 result1 = fetch_ready_process();
                 result2 = 0;
             }
+            PM_CANCEL_FORK => {
+                cancel_fork(arg as usize);
+                result1 = 0;
+                result2 = 0;
+            }
             _ => {
                 panic!("Unknown service id: {}", service_id);
             }
@@ -0,0 +1,141 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, mmap, munmap, shm_create, shm_destroy, shm_map, shm_unmap, waitpid, Errno, MAP_FIXED, MAP_SHARED,
+    PROT_READ, PROT_WRITE,
+};
+
+const PAGE_SIZE: usize = 4096;
+const PAGES: usize = 2;
+
+fn write_pages(start: usize, pages: usize, value: usize) {
+    for i in 0..pages {
+        unsafe { ((start + i * PAGE_SIZE) as *mut usize).write_volatile(value + i) };
+    }
+}
+
+fn check_pages(start: usize, pages: usize, value: usize) {
+    for i in 0..pages {
+        assert_eq!(unsafe { ((start + i * PAGE_SIZE) as *const usize).read_volatile() }, value + i);
+    }
+}
+
+fn wait_child(pid: usize) -> i32 {
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), Ok(pid));
+    exit_code
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // an object without a name, zeroed
+    let id = shm_create(None, PAGES * PAGE_SIZE).expect("shm_create failed");
+    let first = shm_map(id, 0, PROT_READ | PROT_WRITE, 0).expect("shm_map failed");
+    check_pages(first, PAGES, 0);
+
+    // two mappings in one process see the same memory
+    let second = shm_map(id, 0, PROT_READ | PROT_WRITE, 0).unwrap();
+    assert_ne!(first, second);
+    write_pages(second, PAGES, 100);
+    check_pages(first, PAGES, 100);
+    println!("two mappings passed");
+
+    // the mappings stay shared after fork, and a child can map the object again
+    let pid = fork().unwrap();
+    if pid == 0 {
+        check_pages(first, PAGES, 100);
+        write_pages(first, PAGES, 200);
+        let third = shm_map(id, 0, PROT_READ | PROT_WRITE, 0).unwrap();
+        write_pages(third + PAGE_SIZE, 1, 301);
+        exit(0);
+    }
+    assert_eq!(wait_child(pid), 0);
+    check_pages(first, 1, 200);
+    check_pages(second + PAGE_SIZE, 1, 301);
+    println!("fork passed");
+
+    // writing a read-only mapping kills the process
+    let read_only = shm_map(id, 0, PROT_READ, 0).unwrap();
+    check_pages(read_only, 1, 200);
+    let pid = fork().unwrap();
+    if pid == 0 {
+        write_pages(read_only, 1, 0);
+        exit(0);
+    }
+    assert_eq!(wait_child(pid), -2);
+    assert_eq!(shm_unmap(read_only), Ok(()));
+    println!("read-only mapping passed");
+
+    // a mapping at a fixed address replaces the old mapping
+    let fixed = shm_map(id, first, PROT_READ | PROT_WRITE, MAP_FIXED);
+    assert_eq!(fixed, Ok(first));
+    check_pages(first, 1, 200);
+
+    // an object with a name is opened by other processes
+    let named = shm_create(Some("shmtest\0"), PAGE_SIZE).expect("shm_create failed");
+    let pid = fork().unwrap();
+    if pid == 0 {
+        let id = shm_create(Some("shmtest\0"), PAGE_SIZE).unwrap();
+        write_pages(shm_map(id, 0, PROT_READ | PROT_WRITE, 0).unwrap(), 1, 400);
+        exit(id as i32);
+    }
+    assert_eq!(wait_child(pid), named as i32);
+    let named_start = shm_map(named, 0, PROT_READ, 0).unwrap();
+    check_pages(named_start, 1, 400);
+    // it is not larger than it was created
+    assert_eq!(shm_create(Some("shmtest\0"), 2 * PAGE_SIZE), Err(Errno::EINVAL));
+    println!("named object passed");
+
+    // a destroyed object lives until its last mapping is unmapped
+    assert_eq!(shm_destroy(id), Ok(()));
+    assert_eq!(shm_destroy(id), Err(Errno::EINVAL));
+    assert_eq!(shm_map(id, 0, PROT_READ, 0), Err(Errno::EINVAL));
+    check_pages(first, 1, 200);
+    write_pages(second, PAGES, 500);
+    check_pages(first, PAGES, 500);
+    assert_eq!(shm_unmap(first), Ok(()));
+    assert_eq!(shm_unmap(first), Err(Errno::EINVAL));
+    assert_eq!(munmap(second, PAGES * PAGE_SIZE), Ok(()));
+    // the name can be used again for a new object
+    assert_eq!(shm_destroy(named), Ok(()));
+    let again = shm_create(Some("shmtest\0"), PAGE_SIZE).unwrap();
+    assert_ne!(again, named);
+    check_pages(shm_map(again, 0, PROT_READ, 0).unwrap(), 1, 0);
+    check_pages(named_start, 1, 400);
+    assert_eq!(shm_destroy(again), Ok(()));
+    println!("destroy passed");
+
+    // an object without a name goes with its last mapping, also when the process that maps it exits
+    let anonymous = shm_create(None, PAGE_SIZE).unwrap();
+    let start = shm_map(anonymous, 0, PROT_READ | PROT_WRITE, 0).unwrap();
+    assert_eq!(shm_unmap(start), Ok(()));
+    assert_eq!(shm_map(anonymous, 0, PROT_READ, 0), Err(Errno::EINVAL));
+    assert_eq!(shm_destroy(anonymous), Err(Errno::EINVAL));
+    let anonymous = shm_create(None, PAGE_SIZE).unwrap();
+    let pid = fork().unwrap();
+    if pid == 0 {
+        shm_map(anonymous, 0, PROT_READ, 0).unwrap();
+        exit(0);
+    }
+    assert_eq!(wait_child(pid), 0);
+    assert_eq!(shm_map(anonymous, 0, PROT_READ, 0), Err(Errno::EINVAL));
+    // a named object is kept for the processes that open it later
+    let named = shm_create(Some("shmtest\0"), PAGE_SIZE).unwrap();
+    let start = shm_map(named, 0, PROT_READ | PROT_WRITE, 0).unwrap();
+    write_pages(start, 1, 600);
+    assert_eq!(shm_unmap(start), Ok(()));
+    check_pages(shm_map(named, 0, PROT_READ, 0).unwrap(), 1, 600);
+    assert_eq!(shm_destroy(named), Ok(()));
+    // shm_unmap only unmaps shared memory objects
+    let shared = mmap(0, PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_SHARED).unwrap();
+    assert_eq!(shm_unmap(shared), Err(Errno::EINVAL));
+    assert_eq!(munmap(shared, PAGE_SIZE), Ok(()));
+    println!("last mapping passed");
+
+    println!("shmtest passed!");
+    0
+}
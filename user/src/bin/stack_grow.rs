@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{fork, set_stack_limit, waitpid, Errno};
+
+const DEPTH: usize = 1024;
+const FRAME_SIZE: usize = 1024; // about 1 MiB of stack in total, far more than the initial user stack
+
+#[inline(never)]
+fn recurse(depth: usize) -> usize {
+    let mut buffer = [0u8; FRAME_SIZE];
+    buffer[depth % FRAME_SIZE] = depth as u8;
+    let below = if depth == 0 { 0 } else { recurse(depth - 1) };
+    // read the buffer through a volatile pointer so that it is kept on the stack
+    below + unsafe { (buffer.as_ptr().add(depth % FRAME_SIZE)).read_volatile() } as usize
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // a smaller limit of a child process, its stack can not grow as far.
+    // it is forked before this stack grows
+    assert_eq!(set_stack_limit(4096), Err(Errno::EINVAL));
+    let pid = fork().unwrap();
+    if pid == 0 {
+        assert_eq!(set_stack_limit(64 * 1024), Ok(()));
+        recurse(DEPTH);
+        panic!("the stack grows beyond its limit");
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), Ok(pid));
+    assert_eq!(exit_code, -2); // killed for stack overflow
+
+    println!("recurse {} times with {} bytes stack frame", DEPTH, FRAME_SIZE);
+    let sum = recurse(DEPTH);
+    let expected: usize = (0..=DEPTH).map(|depth| depth as u8 as usize).sum();
+    assert_eq!(sum, expected);
+
+    assert_eq!(set_stack_limit(64 * 1024), Err(Errno::EINVAL)); // this stack is already larger
+    println!("stack_grow passed!");
+    0
+}
@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, getpid, memory_usage, mmap, waitpid, yield_, PROT_READ, PROT_WRITE};
+
+const PAGE_SIZE: usize = 4096;
+const MIB: usize = 1024 * 1024;
+// together the children touch more memory than the free frames when RAM stands in for the swap disk
+// (FEATURES=swap-ram-disk), but fit in RAM without swap. Each of them stays under the memory limit of usertests
+const CHILD_NUM: usize = 4;
+const CHILD_MEMORY: usize = 20 * MIB;
+
+fn page_value(pid: usize, page: usize) -> usize {
+    return pid << 32 | page;
+}
+
+fn touch_and_check() -> i32 {
+    let pid = getpid() as usize;
+    let start = mmap(0, CHILD_MEMORY, PROT_READ | PROT_WRITE, 0).unwrap();
+    let pages = unsafe { core::slice::from_raw_parts_mut(start as *mut [usize; PAGE_SIZE / 8], CHILD_MEMORY / PAGE_SIZE) };
+    for (i, page) in pages.iter_mut().enumerate() {
+        page[0] = page_value(pid, i);
+        page[PAGE_SIZE / 8 - 1] = !page_value(pid, i);
+    }
+    // let the others run, they may push our pages out
+    for _ in 0..CHILD_NUM {
+        yield_();
+    }
+    for (i, page) in pages.iter().enumerate() {
+        if page[0] != page_value(pid, i) || page[PAGE_SIZE / 8 - 1] != !page_value(pid, i) {
+            println!("pid {}: page {} is broken", pid, i);
+            return -1;
+        }
+    }
+    println!("pid {}: {} KiB swapped out at the end", pid, memory_usage().swapped / 1024);
+    return 0;
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut pids = [0; CHILD_NUM];
+    for pid in pids.iter_mut() {
+        *pid = fork().unwrap();
+        if *pid == 0 {
+            exit(touch_and_check());
+        }
+    }
+    for pid in pids {
+        let mut exit_code: i32 = 0;
+        assert_eq!(waitpid(pid, &mut exit_code), Ok(pid));
+        assert_eq!(exit_code, 0);
+    }
+    println!("swaptest passed!");
+    0
+}
@@ -18,8 +18,9 @@ const RESET: &str = "\x1b[0m";
 extern crate alloc;
 
 use user_lib::console::getchar;
-use user_lib::{exec, fork, waitpid};
+use user_lib::{execve, fork, waitpid, Errno};
 use alloc::string::String;
+use alloc::vec::Vec;
 
 
 #[no_mangle]
@@ -32,27 +33,29 @@ pub fn main() -> i32 {
         match c {
             LF | CR => { // Enter
                 println!("");
-                if !line.is_empty() {
-                    line.push('\0');
-                    let pid = fork();
+                // the program and its arguments are separated by spaces
+                let words: Vec<String> = line.split_whitespace().map(|word| String::from(word) + "\0").collect();
+                if !words.is_empty() {
+                    let argv: Vec<&str> = words.iter().map(String::as_str).collect();
+                    let pid = fork().unwrap();
                     if pid == 0 {
                         // child process
-                        if exec(line.as_str()) == -1 {
-                            println!("{}no such program: {}{}", RED, RESET, line.as_str());
-                            return -4;
+                        match execve(argv[0], &argv, &[]) {
+                            Errno::ENOENT => println!("{}no such program: {}{}", RED, RESET, line.as_str()),
+                            errno => println!("{}can not run {}: {:?}{}", RED, line.as_str(), errno, RESET),
                         }
-                        unreachable!();
+                        return -4;
                     } else {
                         let mut exit_code: i32 = 0;
-                        let exit_pid = waitpid(pid as usize, &mut exit_code);
-                        assert_eq!(pid, exit_pid);
+                        let exit_pid = waitpid(pid, &mut exit_code);
+                        assert_eq!(Ok(pid), exit_pid);
                         println!(
                             "{}Process {} exited with code {}{}",
                             GREEN, pid, exit_code, RESET
                         );
                     }
-                    line.clear();
                 }
+                line.clear();
                 print!("$ ");
             }
             BS | DL => { // Backspace or Delete
@@ -5,15 +5,29 @@
 extern crate user_lib;
 
 static TESTS: &[&str] = &[
+    "argtest\0",
+    "dyntest\0",
+    "errnotest\0",
     "exit\0",
     "fantastic_text\0",
+    "fdtest\0",
     "forktest\0",
     "forktest2\0",
     "forktest_simple\0",
+    "heap_grow\0",
     "hello_world\0",
+    "lazy_heap\0",
     "matrix\0",
+    "memlimit\0",
+    "mmapfile\0",
+    "mmaptest\0",
+    "oom_fork\0",
+    "pietest\0",
+    "shmtest\0",
     "sleep\0",
     "sleep_simple\0",
+    "stack_grow\0",
+    "swaptest\0",
     "stack_overflow\0",
     "yield\0",
 ];
@@ -24,14 +38,14 @@ use user_lib::{exec, fork, waitpid};
 pub fn main() -> i32 {
     for test in TESTS {
         println!("Usertests: Running {}", test);
-        let pid = fork();
+        let pid = fork().unwrap();
         if pid == 0 {
             exec(*test);
             panic!("unreachable!");
         } else {
             let mut exit_code: i32 = Default::default();
-            let wait_pid = waitpid(pid as usize, &mut exit_code);
-            assert_eq!(pid, wait_pid);
+            let wait_pid = waitpid(pid, &mut exit_code);
+            assert_eq!(Ok(pid), wait_pid);
             println!(
                 "\x1b[32mUsertests: Test {} in Process {} exited with code {}\x1b[0m",
                 test, pid, exit_code
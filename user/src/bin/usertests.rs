@@ -5,76 +5,69 @@
 extern crate user_lib;
 
 // not in SUCC_TESTS & FAIL_TESTS
-// count_lines, infloop, user_shell, usertests
+// count_lines, infloop, ld, libuser, user_shell, usertests
 
 // item of TESTS : app_name(argv_0), argv_1, argv_2, argv_3, exit_code
 static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
+    ("argtest\0", "\0", "\0", "\0", 0),
+    ("dyntest\0", "\0", "\0", "\0", 0),
+    ("errnotest\0", "\0", "\0", "\0", 0),
     ("exit\0", "\0", "\0", "\0", 0),
     ("fantastic_text\0", "\0", "\0", "\0", 0),
+    ("fdtest\0", "\0", "\0", "\0", 0),
     ("forktest_simple\0", "\0", "\0", "\0", 0),
     ("forktest\0", "\0", "\0", "\0", 0),
     ("forktest2\0", "\0", "\0", "\0", 0),
     ("forktree\0", "\0", "\0", "\0", 0),
+    ("heap_grow\0", "\0", "\0", "\0", 0),
     ("hello_world\0", "\0", "\0", "\0", 0),
+    ("lazy_heap\0", "\0", "\0", "\0", 0),
     ("matrix\0", "\0", "\0", "\0", 0),
+    ("memlimit\0", "\0", "\0", "\0", 0),
+    ("mmapfile\0", "\0", "\0", "\0", 0),
+    ("mmaptest\0", "\0", "\0", "\0", 0),
+    ("oom_fork\0", "\0", "\0", "\0", 0),
+    ("pietest\0", "\0", "\0", "\0", 0),
+    ("shmtest\0", "\0", "\0", "\0", 0),
     ("sleep_simple\0", "\0", "\0", "\0", 0),
     ("sleep\0", "\0", "\0", "\0", 0),
+    ("stack_grow\0", "\0", "\0", "\0", 0),
+    ("swaptest\0", "\0", "\0", "\0", 0),
     ("yield\0", "\0", "\0", "\0", 0),
 ];
 
 static FAIL_TESTS: &[(&str, &str, &str, &str, i32)] = &[("stack_overflow\0", "\0", "\0", "\0", -2)];
 
-use user_lib::{exec, fork, waitpid};
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{execve, fork, set_memory_limits, waitpid_with_usage, MemoryUsage};
+
+// each test runs with this much resident memory at most, so that a regression is caught
+const TEST_MEMORY_LIMIT: usize = 32 * 1024 * 1024;
 
 fn run_tests(tests: &[(&str, &str, &str, &str, i32)]) -> i32 {
     let mut pass_num = 0;
-    let mut arr: [*const u8; 4] = [
-        core::ptr::null::<u8>(),
-        core::ptr::null::<u8>(),
-        core::ptr::null::<u8>(),
-        core::ptr::null::<u8>(),
-    ];
-
     for test in tests {
         println!("Usertests: Running {}", test.0);
-        arr[0] = test.0.as_ptr();
-        if test.1 != "\0" {
-            arr[1] = test.1.as_ptr();
-            arr[2] = core::ptr::null::<u8>();
-            arr[3] = core::ptr::null::<u8>();
-            if test.2 != "\0" {
-                arr[2] = test.2.as_ptr();
-                arr[3] = core::ptr::null::<u8>();
-                if test.3 != "\0" {
-                    arr[3] = test.3.as_ptr();
-                } else {
-                    arr[3] = core::ptr::null::<u8>();
-                }
-            } else {
-                arr[2] = core::ptr::null::<u8>();
-                arr[3] = core::ptr::null::<u8>();
-            }
-        } else {
-            arr[1] = core::ptr::null::<u8>();
-            arr[2] = core::ptr::null::<u8>();
-            arr[3] = core::ptr::null::<u8>();
-        }
-
-        let pid = fork();
+        let argv: Vec<&str> = [test.0, test.1, test.2, test.3].into_iter().filter(|arg| *arg != "\0").collect();
+        let pid = fork().unwrap();
         if pid == 0 {
-            exec(test.0);
+            assert_eq!(set_memory_limits(TEST_MEMORY_LIMIT, usize::MAX), Ok(()));
+            execve(test.0, &argv, &[]);
             panic!("unreachable!");
         } else {
             let mut exit_code: i32 = Default::default();
-            let wait_pid = waitpid(pid as usize, &mut exit_code);
-            assert_eq!(pid, wait_pid);
+            let mut usage = MemoryUsage::default();
+            let wait_pid = waitpid_with_usage(pid, &mut exit_code, &mut usage);
+            assert_eq!(Ok(pid), wait_pid);
             if exit_code == test.4 {
                 // summary apps with  exit_code
                 pass_num = pass_num + 1;
             }
             println!(
-                "\x1b[32mUsertests: Test {} in Process {} exited with code {}\x1b[0m",
-                test.0, pid, exit_code
+                "\x1b[32mUsertests: Test {} in Process {} exited with code {}, peak memory {} KiB\x1b[0m",
+                test.0, pid, exit_code, usage.peak / 1024
             );
         }
     }
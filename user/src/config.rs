@@ -1,4 +1,5 @@
-pub const USER_HEAP_SIZE: usize = 0x4000;
+pub const USER_HEAP_GROW_SIZE: usize = 0x4000; // the smallest size the heap grows by with sbrk
+pub const USER_HEAP_SHRINK_SIZE: usize = 0x1_0000; // free blocks of this size at the break are given back
 pub const USER_HEAP_GRANULARITY: usize = 8;
 
 
@@ -8,7 +8,7 @@ const STDOUT: usize = 1;
 
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        write(STDOUT, s.as_bytes());
+        write(STDOUT, s.as_bytes()).map_err(|_| fmt::Error)?;
         Ok(())
     }
 }
@@ -19,7 +19,7 @@ pub fn print(args: fmt::Arguments) {
 
 pub fn getchar() -> u8 {
     let mut buf = [0u8; 1];
-    read(STDIN, &mut buf);
+    read(STDIN, &mut buf).unwrap();
     return buf[0]
 }
 
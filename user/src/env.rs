@@ -0,0 +1,94 @@
+// the arguments and the environment of the program, which the kernel puts on the initial stack as
+// argc, argv pointers ending with NULL, envp pointers ending with NULL, and the auxiliary vector of
+// (key, value) pairs ending with AT_NULL. The strings end with NUL.
+
+use core::ptr::null;
+
+// keys of the auxiliary vector
+pub const AT_NULL: usize = 0;
+pub const AT_PHDR: usize = 3; // the program headers of the program
+pub const AT_PHENT: usize = 4;
+pub const AT_PHNUM: usize = 5;
+pub const AT_PAGESZ: usize = 6;
+pub const AT_BASE: usize = 7; // where the interpreter is loaded
+pub const AT_ENTRY: usize = 9; // the entry of the program
+pub const AT_RANDOM: usize = 25; // 16 random bytes
+
+static mut SP: *const usize = null();
+static mut ARGV: *const usize = null();
+static mut ENVP: *const usize = null();
+
+// called by _start with the initial stack pointer, before main
+pub(crate) fn init(sp: *const usize) {
+    unsafe {
+        SP = sp;
+        let argc = *sp;
+        ARGV = sp.add(1);
+        ENVP = sp.add(argc + 2);
+    }
+}
+
+// the envp the program got, for exec to pass on
+pub(crate) fn envp() -> *const usize {
+    unsafe { ENVP }
+}
+
+// the stack pointer the program started with, it points to argc
+pub fn initial_sp() -> *const usize {
+    unsafe { SP }
+}
+
+// the value of key in the auxiliary vector, which follows envp
+pub fn getauxval(key: usize) -> Option<usize> {
+    let mut entry = envp();
+    if entry.is_null() {
+        return None;
+    }
+    unsafe {
+        while *entry != 0 {
+            entry = entry.add(1);
+        }
+        entry = entry.add(1);
+        while *entry != AT_NULL {
+            if *entry == key {
+                return Some(*entry.add(1));
+            }
+            entry = entry.add(2);
+        }
+    }
+    None
+}
+
+// an iterator over argv or envp, the strings are without their NUL
+#[derive(Clone)]
+pub struct Args {
+    next: *const usize,
+}
+
+impl Iterator for Args {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<&'static str> {
+        if self.next.is_null() {
+            return None;
+        }
+        let ptr = unsafe { *self.next } as *const u8;
+        if ptr.is_null() {
+            return None;
+        }
+        self.next = unsafe { self.next.add(1) };
+        let len = (0..).take_while(|&i| unsafe { *ptr.add(i) } != 0).count();
+        let bytes = unsafe { core::slice::from_raw_parts(ptr, len) };
+        Some(core::str::from_utf8(bytes).unwrap_or(""))
+    }
+}
+
+// the arguments of the program, the first is the name it is run with
+pub fn args() -> Args {
+    Args { next: unsafe { ARGV } }
+}
+
+// the environment of the program, as "KEY=value" strings
+pub fn envs() -> Args {
+    Args { next: envp() }
+}
@@ -1,8 +1,25 @@
-use crate::syscall::{sys_read, sys_write};
+use crate::syscall::{sys_close, sys_dup, sys_dup2, sys_read, sys_write};
+use crate::{result, unit_result, Errno};
 
-pub fn read(fd: usize, buf: &mut [u8]) -> isize {
-    sys_read(fd, buf)
+// return the number of bytes read or written
+pub fn read(fd: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+    result(sys_read(fd, buf))
 }
-pub fn write(fd: usize, buf: &[u8]) -> isize {
-    sys_write(fd, buf)
+pub fn write(fd: usize, buf: &[u8]) -> Result<usize, Errno> {
+    result(sys_write(fd, buf))
+}
+
+// 0, 1 and 2 are stdin, stdout and stderr on the console. Open files are inherited by fork and kept by exec
+pub fn close(fd: usize) -> Result<(), Errno> {
+    unit_result(sys_close(fd))
+}
+
+// return the lowest free fd, which shares the file of fd. EMFILE if too many files are open
+pub fn dup(fd: usize) -> Result<usize, Errno> {
+    result(sys_dup(fd))
+}
+
+// make new_fd share the file of old_fd, closing the file of new_fd first. Return new_fd
+pub fn dup2(old_fd: usize, new_fd: usize) -> Result<usize, Errno> {
+    result(sys_dup2(old_fd, new_fd))
 }
@@ -9,19 +9,28 @@ mod lang_item;
 mod syscall;
 mod file;
 mod config;
+mod env;
 
 use syscall::*;
 use config::*;
-use allocator::GlobalBuddyAllocator;
+use allocator::{GlobalBuddyAllocator, HeapStats};
+use core::arch::global_asm;
+use core::cmp::max;
+use core::iter::once;
+use alloc::vec::Vec;
+pub use errno::Errno;
+pub use file::{close, dup, dup2, read, write};
+pub use syscall::syscall6;
+pub use env::{args, envs, getauxval, initial_sp, Args};
+pub use env::{AT_BASE, AT_ENTRY, AT_NULL, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM, AT_RANDOM};
 
 extern crate alloc;
 
-static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
-
-// implement global allocator traits to enable use of alloc crate
+// implement global allocator traits to enable use of alloc crate.
+// the heap starts empty and grows with sbrk, it has no upper bound other than what the kernel allows.
 #[global_allocator]
 static USER_HEAP_ALLOCATOR: GlobalBuddyAllocator = unsafe {
-    GlobalBuddyAllocator::new(USER_HEAP_SIZE, USER_HEAP_GRANULARITY)
+    GlobalBuddyAllocator::new(usize::MAX, USER_HEAP_GRANULARITY)
 };
 
 #[alloc_error_handler]
@@ -30,9 +39,24 @@ pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
     panic!("Heap allocation error, layout = {:?}", layout);
 }
 
+// the entry of the program, sp points to argc, argv and envp that the kernel pushed
+global_asm!(
+    ".section .text.entry, \"ax\"",
+    ".globl _start",
+    "_start:",
+    "    mv a0, sp",
+    "    tail start_main",
+);
+
 #[no_mangle]
-#[link_section = ".text.entry"]
-pub extern "C" fn _start() -> ! {
+extern "C" fn start_main(sp: *const usize) -> ! {
+    start(sp, main)
+}
+
+// set up the arguments and the heap from the initial stack pointer sp, then run main and exit with what it returns.
+// a program linked with the shared user_lib starts here through user_start, see libuser
+pub fn start(sp: *const usize, main: impl FnOnce() -> i32) -> ! {
+    env::init(sp);
     init_heap_allocator();
     exit(main());
     panic!("unreachable after sys_exit!");
@@ -46,11 +70,32 @@ fn main() -> i32 {
 }
 
 fn init_heap_allocator() {
-    println!("start init user heap!");
-    unsafe {
-        println!("heap start: {:x}, size: {:x}", HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
-        USER_HEAP_ALLOCATOR.init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
+    USER_HEAP_ALLOCATOR.set_grow_handler(grow_heap);
+    USER_HEAP_ALLOCATOR.set_shrink_handler(shrink_heap, USER_HEAP_SHRINK_SIZE);
+}
+
+// move the program break up for a new region of the heap.
+// the region starts at the old break, so that it holds a block of size aligned to its size.
+fn grow_heap(min_size: usize, _max_size: usize) -> Option<(usize, usize)> {
+    let size = max(min_size, USER_HEAP_GROW_SIZE);
+    let start = sbrk(0).ok()?;
+    let end = ((start + size - 1) & !(size - 1)) + size;
+    if end - start > i32::MAX as usize || sbrk((end - start) as i32).is_err() {
+        return None;
     }
+    Some((start, end - start))
+}
+
+// give a free block back if it is at the program break
+fn shrink_heap(start: usize, size: usize) -> bool {
+    if sbrk(0) != Ok(start + size) || size > i32::MAX as usize {
+        return false;
+    }
+    sbrk(-(size as i32)).is_ok()
+}
+
+pub fn heap_stats() -> HeapStats {
+    USER_HEAP_ALLOCATOR.stats()
 }
 
 pub fn exit(exit_code: i32) -> isize {
@@ -70,37 +115,98 @@ pub fn sleep(time: usize) {
     }
 }
 
-pub fn sbrk(size: i32) -> isize {
-    sys_sbrk(size)
+// a syscall returns a value >= 0, or the negative error number
+fn result(ret: isize) -> Result<usize, Errno> {
+    Errno::decode(ret)
+}
+fn unit_result(ret: isize) -> Result<(), Errno> {
+    Errno::decode(ret).map(|_| ())
+}
+
+// move the program break by size bytes, return the old break
+pub fn sbrk(size: i32) -> Result<usize, Errno> {
+    result(sys_sbrk(size))
+}
+
+// flags of mmap, PROT_* are also used by mprotect
+pub const PROT_READ: usize = 1 << 0;
+pub const PROT_WRITE: usize = 1 << 1;
+pub const PROT_EXEC: usize = 1 << 2;
+pub const MAP_SHARED: usize = 1 << 3;
+pub const MAP_FIXED: usize = 1 << 4;
+
+// map len bytes of zeroed anonymous memory, return the start address
+pub fn mmap(start: usize, len: usize, prot: usize, flags: usize) -> Result<usize, Errno> {
+    result(sys_mmap(start, len, prot | flags))
+}
+pub fn munmap(start: usize, len: usize) -> Result<(), Errno> {
+    unit_result(sys_munmap(start, len))
+}
+pub fn mprotect(start: usize, len: usize, prot: usize) -> Result<(), Errno> {
+    unit_result(sys_mprotect(start, len, prot))
+}
+// map len bytes of the file at path (ending with '\0') from its start, with the prot and flags of mmap but MAP_FIXED.
+// a MAP_SHARED mapping writes to the file on msync and munmap. Return the start address
+pub fn mmap_file(path: &str, len: usize, prot: usize, flags: usize) -> Result<usize, Errno> {
+    result(sys_mmap_file(path.as_ptr(), len, prot | flags))
+}
+pub fn msync(start: usize, len: usize) -> Result<(), Errno> {
+    unit_result(sys_msync(start, len))
+}
+
+// create a shared memory object of size bytes, or open the object of name if there is one. name ends with '\0',
+// and an object without a name can only be found by its id. Return the id
+pub fn shm_create(name: Option<&str>, size: usize) -> Result<usize, Errno> {
+    result(sys_shm_create(name.map_or(core::ptr::null(), |name| name.as_ptr()), size))
+}
+// the object can not be opened or mapped any more, its memory lives until the last mapping is unmapped
+pub fn shm_destroy(id: usize) -> Result<(), Errno> {
+    unit_result(sys_shm_destroy(id))
+}
+// map a shared memory object, with the prot and flags of mmap. Return the start address
+pub fn shm_map(id: usize, start: usize, prot: usize, flags: usize) -> Result<usize, Errno> {
+    result(sys_shm_map(id, start, prot | flags))
+}
+pub fn shm_unmap(start: usize) -> Result<(), Errno> {
+    unit_result(sys_shm_unmap(start))
 }
 
 pub fn getpid() -> isize {
     sys_getpid()
 }
-pub fn fork() -> isize {
-    sys_fork()
+// return the pid of the child to the parent, and 0 to the child
+pub fn fork() -> Result<usize, Errno> {
+    result(sys_fork())
 }
-pub fn exec(path: &str) -> isize {
-    sys_exec(path)
+// path ends with '\0'. The program gets path as its only argument, and the environment of this one.
+// Return only if the program can not be run
+pub fn exec(path: &str) -> Errno {
+    let argv = [path.as_ptr() as usize, 0];
+    match result(sys_exec(path, argv.as_ptr(), env::envp())) {
+        Ok(_) => unreachable!("exec returned after success"),
+        Err(errno) => errno,
+    }
 }
 
-// wait for any child process to exit
-pub fn wait(exit_code: &mut i32) -> isize {
-    loop { // busy waiting
-        match sys_waitpid(-1, exit_code as *mut _) {
-            -2 => { // child process is still running
-                yield_();
-            }
-            exit_pid => return exit_pid,
-        }
+// run path with the arguments argv, the first is the name of the program, and the environment envp of
+// "KEY=value" strings. All the strings end with '\0' as path. Return only if the program can not be run,
+// E2BIG if argv and envp are too long
+pub fn execve(path: &str, argv: &[&str], envp: &[&str]) -> Errno {
+    let pointers = |strings: &[&str]| -> Vec<usize> {
+        strings.iter().map(|string| string.as_ptr() as usize).chain(once(0)).collect()
+    };
+    let (argv, envp) = (pointers(argv), pointers(envp));
+    match result(sys_exec(path, argv.as_ptr(), envp.as_ptr())) {
+        Ok(_) => unreachable!("exec returned after success"),
+        Err(errno) => errno,
     }
 }
 
-// wait for a specific child process to exit
-pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
+// wait for a child process (any of them if pid is -1) to exit, return its pid, or ECHILD if there is no such child
+fn wait_child(pid: isize, exit_code: *mut i32, usage: *mut MemoryUsage) -> Result<usize, Errno> {
     loop { // busy waiting
-        match sys_waitpid(pid as isize, exit_code as *mut _) {
-            -2 => { // child process is still running
+        match result(sys_waitpid(pid, exit_code, usage)) {
+            Err(Errno::EAGAIN) => { // child process is still running
                 yield_();
             }
             exit_pid => return exit_pid,
@@ -108,8 +214,56 @@ pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
     }
 }
 
+// wait for any child process to exit
+pub fn wait(exit_code: &mut i32) -> Result<usize, Errno> {
+    wait_child(-1, exit_code as *mut _, core::ptr::null_mut())
+}
+
+// wait for a specific child process to exit
+pub fn waitpid(pid: usize, exit_code: &mut i32) -> Result<usize, Errno> {
+    wait_child(pid as isize, exit_code as *mut _, core::ptr::null_mut())
+}
+
+// wait for a specific child process to exit, and get the memory usage of its last program
+pub fn waitpid_with_usage(pid: usize, exit_code: &mut i32, usage: &mut MemoryUsage) -> Result<usize, Errno> {
+    wait_child(pid as isize, exit_code as *mut _, usage as *mut _)
+}
+
+// memory used by a process, in bytes. A frame shared with other processes is counted by each of them.
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct MemoryUsage {
+    pub resident: usize,    // frames mapped in user space
+    pub page_tables: usize, // frames of the page table
+    pub heap: usize,        // the size of the heap
+    pub stack: usize,       // the size of the user stack
+    pub mapped: usize,      // the size of all mappings
+    pub peak: usize,        // the largest resident + page_tables of the program so far
+    pub swapped: usize,     // pages on the swap disk
+}
+
+pub fn memory_usage() -> MemoryUsage {
+    let mut usage = MemoryUsage::default();
+    sys_memory_usage(&mut usage as *mut _);
+    usage
+}
+
+// limit resident memory (with page tables) and the size of all mappings, in bytes. usize::MAX is unlimited.
+// the limits are kept after fork and exec. Return EINVAL if the process already uses more
+pub fn set_memory_limits(resident: usize, mapped: usize) -> Result<(), Errno> {
+    unit_result(sys_set_memory_limits(resident, mapped))
+}
+
+// limit the size the user stack can grow to, in bytes. The limit is kept after fork and exec.
+// Return EINVAL if it is below the initial stack or the stack is already larger
+pub fn set_stack_limit(limit: usize) -> Result<(), Errno> {
+    unit_result(sys_set_stack_limit(limit))
+}
+
 pub fn pm_service(result1: isize, result2: usize, arg: &mut i32) -> isize {
-    return sys_pm_service(result1, result2, arg as *mut _);
+    let (service_id, service_arg) = sys_pm_service(result1, result2);
+    *arg = service_arg as i32;
+    return service_id;
 }
 
 
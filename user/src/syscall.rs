@@ -1,31 +1,57 @@
+const SYSCALL_DUP: usize = 23;
+const SYSCALL_DUP2: usize = 24;
+const SYSCALL_CLOSE: usize = 57;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
 const SYSCALL_EXIT: usize = 93;
 const SYSCALL_YIELD: usize = 124;
+const SYSCALL_SET_MEMORY_LIMITS: usize = 164;
+const SYSCALL_MEMORY_USAGE: usize = 165;
+const SYSCALL_SET_STACK_LIMIT: usize = 166;
 const SYSCALL_GET_TIME: usize = 169;
 const SYSCALL_GETPID: usize = 172;
+const SYSCALL_SHM_CREATE: usize = 194;
+const SYSCALL_SHM_DESTROY: usize = 195;
+const SYSCALL_SHM_MAP: usize = 196;
+const SYSCALL_SHM_UNMAP: usize = 197;
 const SYSCALL_SBRK: usize = 214;
+const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_FORK: usize = 220;
 const SYSCALL_EXEC: usize = 221;
+const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MMAP_FILE: usize = 223;
+const SYSCALL_MPROTECT: usize = 226;
+const SYSCALL_MSYNC: usize = 227;
 const SYSCALL_WAITPID: usize = 260;
 
 const SERVICE_PROCESS_MANAGER: usize = 511;
 
 
+use crate::MemoryUsage;
 use core::arch::asm;
 
+// a syscall with up to three arguments and one return value
 fn syscall(id: usize, args: [usize; 3]) -> isize {
+    syscall6(id, [args[0], args[1], args[2], 0, 0, 0]).0
+}
+
+// a syscall with arguments in a0-a5, return a0 and a1. a1 is the second return value of the syscalls that have one
+pub fn syscall6(id: usize, args: [usize; 6]) -> (isize, usize) {
     let mut ret: isize;
+    let mut ret1: usize;
     unsafe {
         asm!(
             "ecall",
             inlateout("x10") args[0] => ret, // a0 = args[0], ... (wait for execution), ret = a0
-            in("x11") args[1],
+            inlateout("x11") args[1] => ret1, // the kernel may change a1 as a second return value
             in("x12") args[2],
+            in("x13") args[3],
+            in("x14") args[4],
+            in("x15") args[5],
             in("x17") id // a7 = id, pass the syscall id
         );
     }
-    ret
+    (ret, ret1)
 }
 
 pub fn sys_write(fd: usize, buffer: &[u8]) -> isize {
@@ -36,6 +62,18 @@ pub fn sys_read(fd: usize, buffer: &mut [u8]) -> isize {
     syscall(SYSCALL_READ, [fd, buffer.as_mut_ptr() as usize, buffer.len()])
 }
 
+pub fn sys_close(fd: usize) -> isize {
+    syscall(SYSCALL_CLOSE, [fd, 0, 0])
+}
+
+pub fn sys_dup(fd: usize) -> isize {
+    syscall(SYSCALL_DUP, [fd, 0, 0])
+}
+
+pub fn sys_dup2(old_fd: usize, new_fd: usize) -> isize {
+    syscall(SYSCALL_DUP2, [old_fd, new_fd, 0])
+}
+
 pub fn sys_exit(xstate: i32) -> isize {
     syscall(SYSCALL_EXIT, [xstate as usize, 0, 0])
 }
@@ -52,6 +90,42 @@ pub fn sys_sbrk(size: i32) -> isize {
     syscall(SYSCALL_SBRK, [size as usize, 0, 0])
 }
 
+pub fn sys_mmap(start: usize, len: usize, flags: usize) -> isize {
+    syscall(SYSCALL_MMAP, [start, len, flags])
+}
+
+pub fn sys_munmap(start: usize, len: usize) -> isize {
+    syscall(SYSCALL_MUNMAP, [start, len, 0])
+}
+
+pub fn sys_mprotect(start: usize, len: usize, prot: usize) -> isize {
+    syscall(SYSCALL_MPROTECT, [start, len, prot])
+}
+
+pub fn sys_mmap_file(path: *const u8, len: usize, flags: usize) -> isize {
+    syscall(SYSCALL_MMAP_FILE, [path as usize, len, flags])
+}
+
+pub fn sys_msync(start: usize, len: usize) -> isize {
+    syscall(SYSCALL_MSYNC, [start, len, 0])
+}
+
+pub fn sys_shm_create(name: *const u8, size: usize) -> isize {
+    syscall(SYSCALL_SHM_CREATE, [name as usize, size, 0])
+}
+
+pub fn sys_shm_destroy(id: usize) -> isize {
+    syscall(SYSCALL_SHM_DESTROY, [id, 0, 0])
+}
+
+pub fn sys_shm_map(id: usize, start: usize, flags: usize) -> isize {
+    syscall(SYSCALL_SHM_MAP, [id, start, flags])
+}
+
+pub fn sys_shm_unmap(start: usize) -> isize {
+    syscall(SYSCALL_SHM_UNMAP, [start, 0, 0])
+}
+
 pub fn sys_getpid() -> isize {
     syscall(SYSCALL_GETPID, [0, 0, 0])
 }
@@ -60,15 +134,28 @@ pub fn sys_fork() -> isize {
     syscall(SYSCALL_FORK, [0, 0, 0])
 }
 
-pub fn sys_exec(path: &str) -> isize {
-    syscall(SYSCALL_EXEC, [path.as_ptr() as usize, 0, 0])
+pub fn sys_exec(path: &str, argv: *const usize, envp: *const usize) -> isize {
+    syscall(SYSCALL_EXEC, [path.as_ptr() as usize, argv as usize, envp as usize])
+}
+
+pub fn sys_waitpid(pid: isize, exit_code: *mut i32, usage: *mut MemoryUsage) -> isize {
+    syscall(SYSCALL_WAITPID, [pid as usize, exit_code as usize, usage as usize])
+}
+
+pub fn sys_memory_usage(usage: *mut MemoryUsage) -> isize {
+    syscall(SYSCALL_MEMORY_USAGE, [usage as usize, 0, 0])
+}
+
+pub fn sys_set_stack_limit(limit: usize) -> isize {
+    syscall(SYSCALL_SET_STACK_LIMIT, [limit, 0, 0])
 }
 
-pub fn sys_waitpid(pid: isize, exit_code: *mut i32) -> isize {
-    syscall(SYSCALL_WAITPID, [pid as usize, exit_code as usize, 0])
+pub fn sys_set_memory_limits(resident: usize, mapped: usize) -> isize {
+    syscall(SYSCALL_SET_MEMORY_LIMITS, [resident, mapped, 0])
 }
 
-pub fn sys_pm_service(result1: isize, result2: usize, arg: *mut i32) -> isize {
-    syscall(SERVICE_PROCESS_MANAGER, [result1 as usize, result2, arg as usize])
+// return the id of the next service in a0 and its argument in a1
+pub fn sys_pm_service(result1: isize, result2: usize) -> (isize, usize) {
+    syscall6(SERVICE_PROCESS_MANAGER, [result1 as usize, result2, 0, 0, 0, 0])
 }
 